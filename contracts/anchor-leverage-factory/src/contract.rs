@@ -0,0 +1,360 @@
+use cosmwasm_std::{
+    log, to_binary, Api, Binary, CosmosMsg, Env, Extern, HandleResponse, HumanAddr, InitResponse,
+    Querier, StdError, StdResult, Storage, WasmMsg,
+};
+use terraswap::hook::InitHook;
+
+use crate::msg::{
+    ConfigResponse, HandleMsg, InitMsg, LeverageResponse, LeveragesResponse, QueryMsg,
+};
+use crate::state::{
+    get_config, get_leverage, get_tmp_owner, read_leverages, remove_tmp_owner, set_config,
+    set_leverage, set_tmp_owner, Config,
+};
+use anchor_leverage::state::ConfigMsg;
+
+/// Contract instantiation tx
+/// tx inputs are specified in InitMsg in msg.rs file
+pub fn init<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: InitMsg,
+) -> StdResult<InitResponse> {
+    set_config(
+        &mut deps.storage,
+        &Config {
+            owner: deps.api.canonical_address(&env.message.sender)?,
+            leverage_code_id: msg.leverage_code_id,
+            leverage_config: msg.leverage_config,
+        },
+    )?;
+
+    Ok(InitResponse::default())
+}
+
+/// General handler for contract tx input
+/// tx inputs are defined HandleMsg enum in msg.rs file
+pub fn handle<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: HandleMsg,
+) -> StdResult<HandleResponse> {
+    match msg {
+        HandleMsg::UpdateConfig {
+            owner,
+            leverage_code_id,
+            leverage_config,
+        } => update_config(deps, env, owner, leverage_code_id, leverage_config),
+        HandleMsg::CreateLeverage {} => create_leverage(deps, env),
+        HandleMsg::Register { owner } => register(deps, env, owner),
+    }
+}
+
+pub fn query<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    msg: QueryMsg,
+) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::Leverage { owner } => to_binary(&query_leverage(deps, owner)?),
+        QueryMsg::Leverages { start_after, limit } => {
+            to_binary(&query_leverages(deps, start_after, limit)?)
+        }
+    }
+}
+
+fn update_config<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    owner: Option<HumanAddr>,
+    leverage_code_id: Option<u64>,
+    leverage_config: Option<Box<ConfigMsg>>,
+) -> StdResult<HandleResponse> {
+    let mut config = get_config(&deps.storage)?;
+    if deps.api.canonical_address(&env.message.sender)? != config.owner {
+        return Err(StdError::unauthorized());
+    }
+
+    if let Some(owner) = owner {
+        config.owner = deps.api.canonical_address(&owner)?;
+    }
+    if let Some(leverage_code_id) = leverage_code_id {
+        config.leverage_code_id = leverage_code_id;
+    }
+    if let Some(leverage_config) = leverage_config {
+        config.leverage_config = *leverage_config;
+    }
+    set_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "update_config")],
+        data: None,
+    })
+}
+
+/// Instantiates an isolated leverage contract owned by the sender,
+/// the new contract calls back `Register` from its init hook
+fn create_leverage<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let config = get_config(&deps.storage)?;
+    let owner = deps.api.canonical_address(&env.message.sender)?;
+    if get_leverage(&deps.storage, &owner)?.is_some() {
+        return Err(StdError::generic_err(format!(
+            "Leverage contract already exists for '{}'",
+            env.message.sender
+        )));
+    }
+    set_tmp_owner(&mut deps.storage, &owner)?;
+
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Wasm(WasmMsg::Instantiate {
+            code_id: config.leverage_code_id,
+            send: vec![],
+            label: None,
+            msg: to_binary(&anchor_leverage::msg::InitMsg {
+                config: config.leverage_config,
+                owner: Some(env.message.sender.clone()),
+                // fees stay with the protocol operator running the factory
+                fee_admin: Some(deps.api.human_address(&config.owner)?),
+                init_hook: Some(InitHook {
+                    contract_addr: env.contract.address,
+                    msg: to_binary(&HandleMsg::Register {
+                        owner: env.message.sender.clone(),
+                    })?,
+                }),
+            })?,
+        })],
+        log: vec![
+            log("action", "create_leverage"),
+            log("owner", env.message.sender),
+        ],
+        data: None,
+    })
+}
+
+fn register<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    owner: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let tmp_owner = get_tmp_owner(&deps.storage)?;
+    let owner_raw = deps.api.canonical_address(&owner)?;
+    if tmp_owner != owner_raw || get_leverage(&deps.storage, &owner_raw)?.is_some() {
+        return Err(StdError::unauthorized());
+    }
+
+    set_leverage(
+        &mut deps.storage,
+        &owner_raw,
+        &deps.api.canonical_address(&env.message.sender)?,
+    )?;
+    remove_tmp_owner(&mut deps.storage);
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "register"),
+            log("owner", owner),
+            log("contract_addr", env.message.sender),
+        ],
+        data: None,
+    })
+}
+
+pub fn query_config<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<ConfigResponse> {
+    let config = get_config(&deps.storage)?;
+    Ok(ConfigResponse {
+        owner: deps.api.human_address(&config.owner)?,
+        leverage_code_id: config.leverage_code_id,
+        leverage_config: config.leverage_config,
+    })
+}
+
+pub fn query_leverage<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    owner: HumanAddr,
+) -> StdResult<LeverageResponse> {
+    match get_leverage(&deps.storage, &deps.api.canonical_address(&owner)?)? {
+        Some(contract_addr) => Ok(LeverageResponse {
+            owner,
+            contract_addr: deps.api.human_address(&contract_addr)?,
+        }),
+        None => Err(StdError::not_found("leverage contract")),
+    }
+}
+
+pub fn query_leverages<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    start_after: Option<HumanAddr>,
+    limit: Option<u32>,
+) -> StdResult<LeveragesResponse> {
+    let start_after = match start_after {
+        Some(owner) => Some(deps.api.canonical_address(&owner)?),
+        None => None,
+    };
+
+    let leverages = read_leverages(&deps.storage, start_after, limit)?
+        .iter()
+        .map(|(owner, contract_addr)| {
+            Ok(LeverageResponse {
+                owner: deps.api.human_address(owner)?,
+                contract_addr: deps.api.human_address(contract_addr)?,
+            })
+        })
+        .collect::<StdResult<Vec<LeverageResponse>>>()?;
+
+    Ok(LeveragesResponse { leverages })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_bignumber::{Decimal256, Uint256};
+    use cosmwasm_std::from_binary;
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR,
+    };
+
+    fn leverage_config() -> ConfigMsg {
+        ConfigMsg {
+            anchor_overseer_contract: HumanAddr::from("overseer"),
+            anchor_market_contract: HumanAddr::from("market"),
+            anchor_oracle_contract: HumanAddr::from("oracle"),
+            anc_token: HumanAddr::from("anc"),
+            terraswap_anc_ust: HumanAddr::from("ancpair"),
+            fee_collector: HumanAddr::from("collector"),
+            performance_fee: Decimal256::percent(10),
+            management_fee: Decimal256::percent(1),
+            max_total_deposit: Uint256::from(1_000_000_000_000u64),
+            max_user_deposit: Uint256::from(100_000_000_000u64),
+            min_deposit: Uint256::from(1_000_000u64),
+            max_slippage: Decimal256::percent(1),
+            collaterals: vec![],
+        }
+    }
+
+    fn instance() -> Extern<MockStorage, MockApi, MockQuerier> {
+        let mut deps = mock_dependencies(20, &[]);
+        let msg = InitMsg {
+            leverage_code_id: 7,
+            leverage_config: leverage_config(),
+        };
+        init(&mut deps, mock_env("owner", &[]), msg).unwrap();
+        deps
+    }
+
+    #[test]
+    fn init_stores_the_config() {
+        let deps = instance();
+
+        let config = query_config(&deps).unwrap();
+        assert_eq!(config.owner, HumanAddr::from("owner"));
+        assert_eq!(config.leverage_code_id, 7);
+        assert_eq!(config.leverage_config, leverage_config());
+    }
+
+    #[test]
+    fn create_leverage_registers_from_the_init_hook() {
+        let mut deps = instance();
+
+        let res = handle(
+            &mut deps,
+            mock_env("alice", &[]),
+            HandleMsg::CreateLeverage {},
+        )
+        .unwrap();
+        let hook = match &res.messages[..] {
+            [CosmosMsg::Wasm(WasmMsg::Instantiate { code_id, msg, .. })] => {
+                assert_eq!(*code_id, 7);
+                let msg: anchor_leverage::msg::InitMsg = from_binary(msg).unwrap();
+                assert_eq!(msg.owner, Some(HumanAddr::from("alice")));
+                // the client owns the instance, the factory owner keeps its fees
+                assert_eq!(msg.fee_admin, Some(HumanAddr::from("owner")));
+                msg.init_hook.unwrap()
+            }
+            messages => panic!("unexpected messages {:?}", messages),
+        };
+        assert_eq!(hook.contract_addr, HumanAddr::from(MOCK_CONTRACT_ADDR));
+
+        // the new contract calls back with the hook message
+        let register: HandleMsg = from_binary(&hook.msg).unwrap();
+        handle(&mut deps, mock_env("leverage", &[]), register).unwrap();
+
+        let leverage = query_leverage(&deps, HumanAddr::from("alice")).unwrap();
+        assert_eq!(leverage.contract_addr, HumanAddr::from("leverage"));
+        let leverages = query_leverages(&deps, None, None).unwrap().leverages;
+        assert_eq!(leverages, vec![leverage]);
+
+        // one leverage contract per owner
+        let res = handle(
+            &mut deps,
+            mock_env("alice", &[]),
+            HandleMsg::CreateLeverage {},
+        );
+        match res {
+            Err(StdError::GenericErr { msg, .. }) => assert!(msg.contains("already exists")),
+            _ => panic!("second leverage contract must be rejected"),
+        }
+    }
+
+    #[test]
+    fn register_rejects_unexpected_calls() {
+        let mut deps = instance();
+
+        // nothing is being instantiated
+        let res = handle(
+            &mut deps,
+            mock_env("leverage", &[]),
+            HandleMsg::Register {
+                owner: HumanAddr::from("alice"),
+            },
+        );
+        assert!(res.is_err());
+
+        handle(
+            &mut deps,
+            mock_env("alice", &[]),
+            HandleMsg::CreateLeverage {},
+        )
+        .unwrap();
+
+        // only the owner being instantiated can be registered
+        let res = handle(
+            &mut deps,
+            mock_env("mallory", &[]),
+            HandleMsg::Register {
+                owner: HumanAddr::from("mallory"),
+            },
+        );
+        match res {
+            Err(StdError::Unauthorized { .. }) => {}
+            _ => panic!("register for another owner must be rejected"),
+        }
+
+        handle(
+            &mut deps,
+            mock_env("leverage", &[]),
+            HandleMsg::Register {
+                owner: HumanAddr::from("alice"),
+            },
+        )
+        .unwrap();
+
+        // the pending owner is consumed by the first register
+        let res = handle(
+            &mut deps,
+            mock_env("mallory", &[]),
+            HandleMsg::Register {
+                owner: HumanAddr::from("alice"),
+            },
+        );
+        assert!(res.is_err());
+        let leverage = query_leverage(&deps, HumanAddr::from("alice")).unwrap();
+        assert_eq!(leverage.contract_addr, HumanAddr::from("leverage"));
+    }
+}
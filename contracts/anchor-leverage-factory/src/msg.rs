@@ -0,0 +1,61 @@
+use cosmwasm_std::HumanAddr;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use anchor_leverage::state::ConfigMsg;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InitMsg {
+    /// Code id of the stored anchor_leverage contract
+    pub leverage_code_id: u64,
+    /// Config shared by every instantiated leverage contract
+    pub leverage_config: ConfigMsg,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleMsg {
+    UpdateConfig {
+        owner: Option<HumanAddr>,
+        leverage_code_id: Option<u64>,
+        leverage_config: Option<Box<ConfigMsg>>,
+    },
+    /// Instantiates a leverage contract owned by the sender
+    CreateLeverage {},
+    /// Invoked by the created leverage contract from its init hook
+    Register { owner: HumanAddr },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsg {
+    Config {},
+    Leverage {
+        owner: HumanAddr,
+    },
+    Leverages {
+        start_after: Option<HumanAddr>,
+        limit: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ConfigResponse {
+    pub owner: HumanAddr,
+    pub leverage_code_id: u64,
+    pub leverage_config: ConfigMsg,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LeverageResponse {
+    pub owner: HumanAddr,
+    pub contract_addr: HumanAddr,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LeveragesResponse {
+    pub leverages: Vec<LeverageResponse>,
+}
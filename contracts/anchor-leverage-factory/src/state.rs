@@ -0,0 +1,92 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use anchor_leverage::state::ConfigMsg;
+use cosmwasm_std::{CanonicalAddr, Order, ReadonlyStorage, StdResult, Storage};
+use cosmwasm_storage::{Bucket, ReadonlyBucket, ReadonlySingleton, Singleton};
+
+pub static CONFIG_KEY: &[u8] = b"config";
+pub static TMP_OWNER_KEY: &[u8] = b"tmp_owner";
+pub static PREFIX_LEVERAGE: &[u8] = b"leverage";
+
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
+/// Config struct
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Config {
+    pub owner: CanonicalAddr,
+    pub leverage_code_id: u64,
+    /// Shared config every leverage contract is instantiated with
+    pub leverage_config: ConfigMsg,
+}
+
+/// Get config
+pub fn get_config<S: Storage>(storage: &S) -> StdResult<Config> {
+    ReadonlySingleton::new(storage, CONFIG_KEY).load()
+}
+
+/// Set config
+pub fn set_config<S: Storage>(storage: &mut S, config: &Config) -> StdResult<()> {
+    Singleton::new(storage, CONFIG_KEY).save(config)
+}
+
+/// Get the owner whose leverage contract is being instantiated
+pub fn get_tmp_owner<S: Storage>(storage: &S) -> StdResult<CanonicalAddr> {
+    ReadonlySingleton::new(storage, TMP_OWNER_KEY).load()
+}
+
+/// Set the owner whose leverage contract is being instantiated
+pub fn set_tmp_owner<S: Storage>(storage: &mut S, owner: &CanonicalAddr) -> StdResult<()> {
+    Singleton::new(storage, TMP_OWNER_KEY).save(owner)
+}
+
+/// Clear the pending owner once its contract has registered
+pub fn remove_tmp_owner<S: Storage>(storage: &mut S) {
+    Singleton::<S, CanonicalAddr>::new(storage, TMP_OWNER_KEY).remove()
+}
+
+/// Get the leverage contract of an owner, if any
+pub fn get_leverage<S: ReadonlyStorage>(
+    storage: &S,
+    owner: &CanonicalAddr,
+) -> StdResult<Option<CanonicalAddr>> {
+    ReadonlyBucket::new(PREFIX_LEVERAGE, storage).may_load(owner.as_slice())
+}
+
+/// Set the leverage contract of an owner
+pub fn set_leverage<S: Storage>(
+    storage: &mut S,
+    owner: &CanonicalAddr,
+    contract_addr: &CanonicalAddr,
+) -> StdResult<()> {
+    Bucket::new(PREFIX_LEVERAGE, storage).save(owner.as_slice(), contract_addr)
+}
+
+/// Read (owner, contract) pairs ordered by owner
+pub fn read_leverages<S: ReadonlyStorage>(
+    storage: &S,
+    start_after: Option<CanonicalAddr>,
+    limit: Option<u32>,
+) -> StdResult<Vec<(CanonicalAddr, CanonicalAddr)>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = calc_range_start(start_after);
+
+    ReadonlyBucket::new(PREFIX_LEVERAGE, storage)
+        .range(start.as_deref(), None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (k, v) = item?;
+            Ok((CanonicalAddr::from(k), v))
+        })
+        .collect()
+}
+
+// this will set the first key after the provided key, by appending a 1 byte
+fn calc_range_start(start_after: Option<CanonicalAddr>) -> Option<Vec<u8>> {
+    start_after.map(|addr| {
+        let mut v = addr.as_slice().to_vec();
+        v.push(1);
+        v
+    })
+}
@@ -16,7 +16,7 @@ fn main() {
         .unwrap()
         .to_path_buf();
     out_dir.push("schemas");
-    out_dir.push(format!("{}_schema", option_env!("CARGO_PKG_NAME").unwrap()));
+    out_dir.push(format!("{}_schema", env!("CARGO_PKG_NAME")));
     create_dir_all(&out_dir).unwrap();
     remove_schemas(&out_dir).unwrap();
 
@@ -27,4 +27,19 @@ fn main() {
     export_schema(&schema_for!(HandleAnswer), &out_dir);
     export_schema(&schema_for!(ConfigMsg), &out_dir);
     export_schema(&schema_for!(PossibleBorrowResponse), &out_dir);
+    export_schema(&schema_for!(Cw20HookMsg), &out_dir);
+    export_schema(&schema_for!(ValidatorsResponse), &out_dir);
+    export_schema(&schema_for!(CollateralsResponse), &out_dir);
+    export_schema(&schema_for!(StateResponse), &out_dir);
+    export_schema(&schema_for!(PositionResponse), &out_dir);
+    export_schema(&schema_for!(PnlResponse), &out_dir);
+    export_schema(&schema_for!(ApyResponse), &out_dir);
+    export_schema(&schema_for!(LiquidationsResponse), &out_dir);
+    export_schema(&schema_for!(SlashingsResponse), &out_dir);
+    export_schema(&schema_for!(UnbondRequestsResponse), &out_dir);
+    export_schema(&schema_for!(PositionHookMsg), &out_dir);
+    export_schema(&schema_for!(DepositCallbackMsg), &out_dir);
+    export_schema(&schema_for!(HooksResponse), &out_dir);
+    export_schema(&schema_for!(OperatorsResponse), &out_dir);
+    export_schema(&schema_for!(PositionsResponse), &out_dir);
 }
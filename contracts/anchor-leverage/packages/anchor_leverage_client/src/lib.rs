@@ -0,0 +1,785 @@
+//! Messages and queries of the anchor_leverage contract for other contracts
+//! to compose with. The loop steps (`DepositCollateral`, `Borrow`, ...) only
+//! accept the contract itself as sender and have no builders here
+
+use cosmwasm_bignumber::{Decimal256, Uint256};
+use cosmwasm_std::{
+    to_binary, Api, Binary, Coin, CosmosMsg, Extern, HumanAddr, Querier, QueryRequest, StdResult,
+    Storage, Uint128, WasmMsg, WasmQuery,
+};
+use cw20::Cw20HandleMsg;
+use moneymarket::custody::BorrowerResponse;
+use serde::de::DeserializeOwned;
+
+pub use anchor_leverage::msg::{
+    ApyResponse, CollateralStateResponse, CollateralsResponse, Cw20HookMsg, DepositCallbackMsg,
+    HandleMsg, HooksResponse, InitMsg, LiquidationResponse, LiquidationsResponse, OperatorResponse,
+    OperatorsResponse, PnlAmount, PnlResponse, PositionChange, PositionHookMsg, PositionResponse,
+    PositionSummary, PositionsOrder, PositionsResponse, PossibleBorrowResponse, QueryMsg,
+    SlashingResponse, SlashingsResponse, StateResponse, UnbondRequestsResponse, ValidatorsResponse,
+};
+pub use anchor_leverage::state::{
+    CollateralMsg, ConfigMsg, MintRouteMsg, OperatorScope, SwapRouteMsg, UnbondRequest, Validator,
+};
+
+/// Wraps `msg` for the leverage contract at `contract_addr`
+pub fn execute_msg(
+    contract_addr: &HumanAddr,
+    msg: &HandleMsg,
+    send: Vec<Coin>,
+) -> StdResult<CosmosMsg> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: contract_addr.clone(),
+        msg: to_binary(msg)?,
+        send,
+    }))
+}
+
+/// Deposits a native deposit asset, `deposit` must be in the collateral's denom.
+/// `callback` is a contract and the message it gets in a DepositCallbackMsg
+/// once the loop is done
+pub fn deposit(
+    contract_addr: &HumanAddr,
+    collateral: String,
+    earn_share: Option<Decimal256>,
+    deposit: Coin,
+    callback: Option<(HumanAddr, Binary)>,
+) -> StdResult<CosmosMsg> {
+    let (callback_contract, callback) = match callback {
+        Some((contract, msg)) => (Some(contract), Some(msg)),
+        None => (None, None),
+    };
+    execute_msg(
+        contract_addr,
+        &HandleMsg::Deposit {
+            collateral,
+            earn_share,
+            callback,
+            callback_contract,
+        },
+        vec![deposit],
+    )
+}
+
+/// Deposits a cw20 deposit asset by sending it to the contract with a Deposit hook
+pub fn deposit_cw20(
+    contract_addr: &HumanAddr,
+    token: &HumanAddr,
+    amount: Uint128,
+    collateral: String,
+    earn_share: Option<Decimal256>,
+    callback: Option<(HumanAddr, Binary)>,
+) -> StdResult<CosmosMsg> {
+    let (callback_contract, callback) = match callback {
+        Some((contract, msg)) => (Some(contract), Some(msg)),
+        None => (None, None),
+    };
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: token.clone(),
+        msg: to_binary(&Cw20HandleMsg::Send {
+            contract: contract_addr.clone(),
+            amount,
+            msg: Some(to_binary(&Cw20HookMsg::Deposit {
+                collateral,
+                earn_share,
+                callback,
+                callback_contract,
+            })?),
+        })?,
+        send: vec![],
+    }))
+}
+
+/// Exits the position, with `unbond` the rest is unbonded at the hub instead of sold.
+/// An operator passes the `owner` it acts for
+pub fn withdraw(
+    contract_addr: &HumanAddr,
+    collateral: String,
+    unbond: Option<bool>,
+    owner: Option<HumanAddr>,
+) -> StdResult<CosmosMsg> {
+    execute_msg(
+        contract_addr,
+        &HandleMsg::Withdraw {
+            collateral,
+            unbond,
+            owner,
+        },
+        vec![],
+    )
+}
+
+pub fn claim_unbonded(
+    contract_addr: &HumanAddr,
+    collateral: String,
+    owner: Option<HumanAddr>,
+) -> StdResult<CosmosMsg> {
+    execute_msg(
+        contract_addr,
+        &HandleMsg::ClaimUnbonded { collateral, owner },
+        vec![],
+    )
+}
+
+pub fn set_earn_share(
+    contract_addr: &HumanAddr,
+    collateral: String,
+    earn_share: Decimal256,
+    owner: Option<HumanAddr>,
+) -> StdResult<CosmosMsg> {
+    execute_msg(
+        contract_addr,
+        &HandleMsg::SetEarnShare {
+            collateral,
+            earn_share,
+            owner,
+        },
+        vec![],
+    )
+}
+
+/// Lets `operator` act for the sender within `scopes` until block time `expires`
+pub fn grant_operator(
+    contract_addr: &HumanAddr,
+    operator: &HumanAddr,
+    scopes: Vec<OperatorScope>,
+    expires: Option<u64>,
+) -> StdResult<CosmosMsg> {
+    execute_msg(
+        contract_addr,
+        &HandleMsg::GrantOperator {
+            operator: operator.clone(),
+            scopes,
+            expires,
+        },
+        vec![],
+    )
+}
+
+pub fn revoke_operator(contract_addr: &HumanAddr, operator: &HumanAddr) -> StdResult<CosmosMsg> {
+    execute_msg(
+        contract_addr,
+        &HandleMsg::RevokeOperator {
+            operator: operator.clone(),
+        },
+        vec![],
+    )
+}
+
+pub fn register_collateral(
+    contract_addr: &HumanAddr,
+    collateral: CollateralMsg,
+) -> StdResult<CosmosMsg> {
+    execute_msg(
+        contract_addr,
+        &HandleMsg::RegisterCollateral { collateral },
+        vec![],
+    )
+}
+
+pub fn update_validators(
+    contract_addr: &HumanAddr,
+    collateral: String,
+    validators: Vec<Validator>,
+) -> StdResult<CosmosMsg> {
+    execute_msg(
+        contract_addr,
+        &HandleMsg::UpdateValidators {
+            collateral,
+            validators,
+        },
+        vec![],
+    )
+}
+
+pub fn update_config(
+    contract_addr: &HumanAddr,
+    owner: Option<HumanAddr>,
+    max_total_deposit: Option<Uint256>,
+    max_user_deposit: Option<Uint256>,
+    min_deposit: Option<Uint256>,
+    max_slippage: Option<Decimal256>,
+) -> StdResult<CosmosMsg> {
+    execute_msg(
+        contract_addr,
+        &HandleMsg::UpdateConfig {
+            owner,
+            max_total_deposit,
+            max_user_deposit,
+            min_deposit,
+            max_slippage,
+        },
+        vec![],
+    )
+}
+
+/// Changes the fee settings, only the instance's fee admin may send it
+pub fn update_fees(
+    contract_addr: &HumanAddr,
+    fee_admin: Option<HumanAddr>,
+    fee_collector: Option<HumanAddr>,
+    performance_fee: Option<Decimal256>,
+    management_fee: Option<Decimal256>,
+) -> StdResult<CosmosMsg> {
+    execute_msg(
+        contract_addr,
+        &HandleMsg::UpdateFees {
+            fee_admin,
+            fee_collector,
+            performance_fee,
+            management_fee,
+        },
+        vec![],
+    )
+}
+
+pub fn harvest(contract_addr: &HumanAddr) -> StdResult<CosmosMsg> {
+    execute_msg(contract_addr, &HandleMsg::Harvest {}, vec![])
+}
+
+pub fn claim_fees(contract_addr: &HumanAddr) -> StdResult<CosmosMsg> {
+    execute_msg(contract_addr, &HandleMsg::ClaimFees {}, vec![])
+}
+
+pub fn reconcile(contract_addr: &HumanAddr) -> StdResult<CosmosMsg> {
+    execute_msg(contract_addr, &HandleMsg::Reconcile {}, vec![])
+}
+
+/// Subscribes `hook` to PositionHookMsg callbacks on the sender's positions
+pub fn add_hook(
+    contract_addr: &HumanAddr,
+    hook: &HumanAddr,
+    owner: Option<HumanAddr>,
+) -> StdResult<CosmosMsg> {
+    execute_msg(
+        contract_addr,
+        &HandleMsg::AddHook {
+            contract: hook.clone(),
+            owner,
+        },
+        vec![],
+    )
+}
+
+pub fn remove_hook(
+    contract_addr: &HumanAddr,
+    hook: &HumanAddr,
+    owner: Option<HumanAddr>,
+) -> StdResult<CosmosMsg> {
+    execute_msg(
+        contract_addr,
+        &HandleMsg::RemoveHook {
+            contract: hook.clone(),
+            owner,
+        },
+        vec![],
+    )
+}
+
+/// Smart query of the leverage contract at `contract_addr`
+pub fn query<S: Storage, A: Api, Q: Querier, T: DeserializeOwned>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    msg: &QueryMsg,
+) -> StdResult<T> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: contract_addr.clone(),
+        msg: to_binary(msg)?,
+    }))
+}
+
+/// `borrower` is the address holding the Anchor loan, usually the leverage contract itself,
+/// `position` a collateral and owner whose slashing haircut is taken off
+pub fn query_possible_borrow<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    borrower: &HumanAddr,
+    block_time: Option<u64>,
+    block_height: Option<u64>,
+    position: Option<(String, HumanAddr)>,
+) -> StdResult<PossibleBorrowResponse> {
+    let (collateral, owner) = match position {
+        Some((collateral, owner)) => (Some(collateral), Some(owner)),
+        None => (None, None),
+    };
+    query(
+        deps,
+        contract_addr,
+        &QueryMsg::PossibleBorrow {
+            contract_addr: borrower.clone(),
+            block_time,
+            block_height,
+            collateral,
+            owner,
+        },
+    )
+}
+
+/// Collateral `borrower` has with the custody of the collateral
+pub fn query_collateral<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    collateral: String,
+    borrower: &HumanAddr,
+) -> StdResult<BorrowerResponse> {
+    query(
+        deps,
+        contract_addr,
+        &QueryMsg::Collateral {
+            collateral,
+            contract_addr: borrower.clone(),
+        },
+    )
+}
+
+pub fn query_collaterals<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+) -> StdResult<CollateralsResponse> {
+    query(deps, contract_addr, &QueryMsg::Collaterals {})
+}
+
+pub fn query_validators<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    collateral: String,
+) -> StdResult<ValidatorsResponse> {
+    query(deps, contract_addr, &QueryMsg::Validators { collateral })
+}
+
+pub fn query_state<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+) -> StdResult<StateResponse> {
+    query(deps, contract_addr, &QueryMsg::State {})
+}
+
+pub fn query_position<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    collateral: String,
+    owner: &HumanAddr,
+) -> StdResult<PositionResponse> {
+    query(
+        deps,
+        contract_addr,
+        &QueryMsg::Position {
+            collateral,
+            owner: owner.clone(),
+        },
+    )
+}
+
+/// Positions in a collateral, `PositionsOrder::Risk` lists the highest LTV first
+pub fn query_positions<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    collateral: String,
+    start_after: Option<HumanAddr>,
+    limit: Option<u32>,
+    order_by: Option<PositionsOrder>,
+) -> StdResult<PositionsResponse> {
+    query(
+        deps,
+        contract_addr,
+        &QueryMsg::Positions {
+            collateral,
+            start_after,
+            limit,
+            order_by,
+        },
+    )
+}
+
+pub fn query_pnl<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    collateral: String,
+    owner: &HumanAddr,
+) -> StdResult<PnlResponse> {
+    query(
+        deps,
+        contract_addr,
+        &QueryMsg::Pnl {
+            collateral,
+            owner: owner.clone(),
+        },
+    )
+}
+
+pub fn query_liquidations<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<LiquidationsResponse> {
+    query(
+        deps,
+        contract_addr,
+        &QueryMsg::Liquidations { start_after, limit },
+    )
+}
+
+pub fn query_slashings<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<SlashingsResponse> {
+    query(
+        deps,
+        contract_addr,
+        &QueryMsg::Slashings { start_after, limit },
+    )
+}
+
+pub fn query_unbond_requests<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    collateral: String,
+    owner: &HumanAddr,
+) -> StdResult<UnbondRequestsResponse> {
+    query(
+        deps,
+        contract_addr,
+        &QueryMsg::UnbondRequests {
+            collateral,
+            owner: owner.clone(),
+        },
+    )
+}
+
+pub fn query_apy<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    collateral: String,
+    leverage: Option<Decimal256>,
+    earn_share: Option<Decimal256>,
+) -> StdResult<ApyResponse> {
+    query(
+        deps,
+        contract_addr,
+        &QueryMsg::Apy {
+            collateral,
+            leverage,
+            earn_share,
+        },
+    )
+}
+
+pub fn query_hooks<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    owner: &HumanAddr,
+) -> StdResult<HooksResponse> {
+    query(
+        deps,
+        contract_addr,
+        &QueryMsg::Hooks {
+            owner: owner.clone(),
+        },
+    )
+}
+
+pub fn query_operators<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    owner: &HumanAddr,
+) -> StdResult<OperatorsResponse> {
+    query(
+        deps,
+        contract_addr,
+        &QueryMsg::Operators {
+            owner: owner.clone(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    use cosmwasm_std::testing::{MockApi, MockStorage};
+    use cosmwasm_std::{coin, from_slice, Binary, Empty, QuerierResult, SystemError};
+    use terraswap::asset::AssetInfo;
+
+    const LEVERAGE: &str = "leverage";
+
+    fn leverage() -> HumanAddr {
+        HumanAddr::from(LEVERAGE)
+    }
+
+    /// JSON of the contract message `msg` executes with, and the coins it sends
+    fn executed(msg: StdResult<CosmosMsg>) -> (HumanAddr, String, Vec<Coin>) {
+        match msg.unwrap() {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr,
+                msg,
+                send,
+            }) => (
+                contract_addr,
+                String::from_utf8(msg.as_slice().to_vec()).unwrap(),
+                send,
+            ),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    fn executed_json(msg: StdResult<CosmosMsg>) -> String {
+        let (contract_addr, json, send) = executed(msg);
+        assert_eq!(contract_addr, leverage());
+        assert_eq!(send, vec![]);
+        json
+    }
+
+    /// Records the JSON of every smart query sent to the leverage contract and
+    /// fails it, the answers are the contract's business
+    #[derive(Default)]
+    struct RecordingQuerier {
+        sent: RefCell<Vec<String>>,
+    }
+
+    impl Querier for RecordingQuerier {
+        fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+            match from_slice(bin_request).unwrap() {
+                QueryRequest::<Empty>::Wasm(WasmQuery::Smart { contract_addr, msg }) => {
+                    assert_eq!(contract_addr, leverage());
+                    self.sent
+                        .borrow_mut()
+                        .push(String::from_utf8(msg.as_slice().to_vec()).unwrap());
+                    Err(SystemError::NoSuchContract {
+                        addr: contract_addr,
+                    })
+                }
+                request => panic!("unexpected query {:?}", request),
+            }
+        }
+    }
+
+    #[test]
+    fn deposits_serialize_to_the_deposit_messages() {
+        let (contract_addr, json, send) = executed(deposit(
+            &leverage(),
+            "bluna".to_string(),
+            Some(Decimal256::percent(20)),
+            coin(1_000_000, "uluna"),
+            Some((HumanAddr::from("vault"), to_binary("done").unwrap())),
+        ));
+        assert_eq!(contract_addr, leverage());
+        assert_eq!(
+            json,
+            r#"{"deposit":{"collateral":"bluna","earn_share":"0.2","callback":"ImRvbmUi","callback_contract":"vault"}}"#
+        );
+        assert_eq!(send, vec![coin(1_000_000, "uluna")]);
+
+        let (contract_addr, json, send) = executed(deposit_cw20(
+            &leverage(),
+            &HumanAddr::from("weth"),
+            Uint128(1_000_000),
+            "beth".to_string(),
+            None,
+            None,
+        ));
+        assert_eq!(contract_addr, HumanAddr::from("weth"));
+        assert_eq!(send, vec![]);
+        let hook = Binary::from(&br#"{"deposit":{"collateral":"beth","earn_share":null,"callback":null,"callback_contract":null}}"#[..]);
+        assert_eq!(
+            json,
+            format!(
+                r#"{{"send":{{"contract":"leverage","amount":"1000000","msg":"{}"}}}}"#,
+                hook.to_base64()
+            )
+        );
+    }
+
+    #[test]
+    fn handle_builders_serialize_to_the_handle_messages() {
+        assert_eq!(
+            executed_json(withdraw(&leverage(), "bluna".to_string(), Some(true), None)),
+            r#"{"withdraw":{"collateral":"bluna","unbond":true,"owner":null}}"#
+        );
+        assert_eq!(
+            executed_json(claim_unbonded(
+                &leverage(),
+                "bluna".to_string(),
+                Some(HumanAddr::from("owner"))
+            )),
+            r#"{"claim_unbonded":{"collateral":"bluna","owner":"owner"}}"#
+        );
+        assert_eq!(
+            executed_json(set_earn_share(
+                &leverage(),
+                "bluna".to_string(),
+                Decimal256::percent(30),
+                None
+            )),
+            r#"{"set_earn_share":{"collateral":"bluna","earn_share":"0.3","owner":null}}"#
+        );
+        assert_eq!(
+            executed_json(grant_operator(
+                &leverage(),
+                &HumanAddr::from("riskbot"),
+                vec![OperatorScope::Deleverage, OperatorScope::SetTriggers],
+                Some(100),
+            )),
+            r#"{"grant_operator":{"operator":"riskbot","scopes":["deleverage","set_triggers"],"expires":100}}"#
+        );
+        assert_eq!(
+            executed_json(revoke_operator(&leverage(), &HumanAddr::from("riskbot"))),
+            r#"{"revoke_operator":{"operator":"riskbot"}}"#
+        );
+        let collateral = CollateralMsg {
+            name: "bluna".to_string(),
+            deposit_asset: AssetInfo::NativeToken {
+                denom: "uluna".to_string(),
+            },
+            mint_route: MintRouteMsg::Hub {
+                hub_contract: HumanAddr::from("hub"),
+                validators: vec![],
+            },
+            token: HumanAddr::from("bluna"),
+            custody_contract: HumanAddr::from("custody"),
+            swap_route: SwapRouteMsg::Pair {
+                pair_contract: HumanAddr::from("lunapair"),
+            },
+            exit_pair: HumanAddr::from("blunapair"),
+            staking_apr: Decimal256::percent(7),
+        };
+        assert_eq!(
+            executed_json(register_collateral(&leverage(), collateral)),
+            concat!(
+                r#"{"register_collateral":{"collateral":{"name":"bluna","#,
+                r#""deposit_asset":{"native_token":{"denom":"uluna"}},"#,
+                r#""mint_route":{"hub":{"hub_contract":"hub","validators":[]}},"#,
+                r#""token":"bluna","custody_contract":"custody","#,
+                r#""swap_route":{"pair":{"pair_contract":"lunapair"}},"#,
+                r#""exit_pair":"blunapair","staking_apr":"0.07"}}}"#,
+            )
+        );
+        let validators = vec![Validator {
+            address: HumanAddr::from("val1"),
+            weight: 3,
+        }];
+        assert_eq!(
+            executed_json(update_validators(
+                &leverage(),
+                "bluna".to_string(),
+                validators
+            )),
+            r#"{"update_validators":{"collateral":"bluna","validators":[{"address":"val1","weight":3}]}}"#
+        );
+        assert_eq!(
+            executed_json(update_config(
+                &leverage(),
+                None,
+                Some(Uint256::from(1_000u64)),
+                None,
+                Some(Uint256::from(10u64)),
+                Some(Decimal256::percent(2)),
+            )),
+            concat!(
+                r#"{"update_config":{"owner":null,"max_total_deposit":"1000","#,
+                r#""max_user_deposit":null,"min_deposit":"10","max_slippage":"0.02"}}"#,
+            )
+        );
+        assert_eq!(
+            executed_json(update_fees(
+                &leverage(),
+                None,
+                Some(HumanAddr::from("collector")),
+                Some(Decimal256::percent(10)),
+                None,
+            )),
+            concat!(
+                r#"{"update_fees":{"fee_admin":null,"fee_collector":"collector","#,
+                r#""performance_fee":"0.1","management_fee":null}}"#,
+            )
+        );
+        assert_eq!(executed_json(harvest(&leverage())), r#"{"harvest":{}}"#);
+        assert_eq!(
+            executed_json(claim_fees(&leverage())),
+            r#"{"claim_fees":{}}"#
+        );
+        assert_eq!(executed_json(reconcile(&leverage())), r#"{"reconcile":{}}"#);
+        assert_eq!(
+            executed_json(add_hook(
+                &leverage(),
+                &HumanAddr::from("hook"),
+                Some(HumanAddr::from("owner"))
+            )),
+            r#"{"add_hook":{"contract":"hook","owner":"owner"}}"#
+        );
+        assert_eq!(
+            executed_json(remove_hook(&leverage(), &HumanAddr::from("hook"), None)),
+            r#"{"remove_hook":{"contract":"hook","owner":null}}"#
+        );
+    }
+
+    #[test]
+    fn query_builders_serialize_to_the_query_messages() {
+        let deps = Extern {
+            storage: MockStorage::default(),
+            api: MockApi::new(20),
+            querier: RecordingQuerier::default(),
+        };
+        let owner = HumanAddr::from("owner");
+        query_possible_borrow(
+            &deps,
+            &leverage(),
+            &leverage(),
+            Some(1),
+            None,
+            Some(("bluna".to_string(), owner.clone())),
+        )
+        .unwrap_err();
+        query_collateral(&deps, &leverage(), "bluna".to_string(), &leverage()).unwrap_err();
+        query_collaterals(&deps, &leverage()).unwrap_err();
+        query_validators(&deps, &leverage(), "bluna".to_string()).unwrap_err();
+        query_state(&deps, &leverage()).unwrap_err();
+        query_position(&deps, &leverage(), "bluna".to_string(), &owner).unwrap_err();
+        query_positions(
+            &deps,
+            &leverage(),
+            "bluna".to_string(),
+            Some(owner.clone()),
+            Some(10),
+            Some(PositionsOrder::Risk),
+        )
+        .unwrap_err();
+        query_pnl(&deps, &leverage(), "bluna".to_string(), &owner).unwrap_err();
+        query_liquidations(&deps, &leverage(), Some(4), Some(10)).unwrap_err();
+        query_slashings(&deps, &leverage(), None, Some(5)).unwrap_err();
+        query_apy(
+            &deps,
+            &leverage(),
+            "bluna".to_string(),
+            None,
+            Some(Decimal256::percent(20)),
+        )
+        .unwrap_err();
+        query_unbond_requests(&deps, &leverage(), "bluna".to_string(), &owner).unwrap_err();
+        query_hooks(&deps, &leverage(), &owner).unwrap_err();
+        query_operators(&deps, &leverage(), &owner).unwrap_err();
+
+        assert_eq!(
+            deps.querier.sent.into_inner(),
+            vec![
+                r#"{"possible_borrow":{"contract_addr":"leverage","block_time":1,"block_height":null,"collateral":"bluna","owner":"owner"}}"#,
+                r#"{"collateral":{"collateral":"bluna","contract_addr":"leverage"}}"#,
+                r#"{"collaterals":{}}"#,
+                r#"{"validators":{"collateral":"bluna"}}"#,
+                r#"{"state":{}}"#,
+                r#"{"position":{"collateral":"bluna","owner":"owner"}}"#,
+                r#"{"positions":{"collateral":"bluna","start_after":"owner","limit":10,"order_by":"risk"}}"#,
+                r#"{"pnl":{"collateral":"bluna","owner":"owner"}}"#,
+                r#"{"liquidations":{"start_after":4,"limit":10}}"#,
+                r#"{"slashings":{"start_after":null,"limit":5}}"#,
+                r#"{"apy":{"collateral":"bluna","leverage":null,"earn_share":"0.2"}}"#,
+                r#"{"unbond_requests":{"collateral":"bluna","owner":"owner"}}"#,
+                r#"{"hooks":{"owner":"owner"}}"#,
+                r#"{"operators":{"owner":"owner"}}"#,
+            ]
+        );
+    }
+}
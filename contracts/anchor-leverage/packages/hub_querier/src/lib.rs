@@ -1,6 +1,10 @@
-use cosmwasm_std::{Binary, CanonicalAddr, Decimal, HumanAddr, Uint128};
+use cosmwasm_std::{
+    to_binary, Api, Binary, CanonicalAddr, Decimal, Extern, HumanAddr, Querier, QueryRequest,
+    StdResult, Storage, Uint128, WasmQuery,
+};
 use cw20::Cw20ReceiveMsg;
 use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
@@ -125,7 +129,30 @@ pub enum Cw20HookMsg {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
+    Config {},
     State {},
+    WhitelistedValidators {},
+    CurrentBatch {},
+    WithdrawableUnbonded {
+        address: HumanAddr,
+        block_time: u64,
+    },
+    Parameters {},
+    UnbondRequests {
+        address: HumanAddr,
+    },
+    AllHistory {
+        start_from: Option<u64>,
+        limit: Option<u32>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    pub owner: HumanAddr,
+    pub reward_contract: Option<HumanAddr>,
+    pub token_contract: Option<HumanAddr>,
+    pub airdrop_registry_contract: Option<HumanAddr>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -139,3 +166,145 @@ pub struct StateResponse {
     pub last_unbonded_time: u64,
     pub last_processed_batch: u64,
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct WhitelistedValidatorsResponse {
+    pub validators: Vec<HumanAddr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CurrentBatchResponse {
+    pub id: u64,
+    pub requested_with_fee: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WithdrawableUnbondedResponse {
+    pub withdrawable: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Parameters {
+    pub epoch_period: u64,
+    pub underlying_coin_denom: String,
+    pub unbonding_period: u64,
+    pub peg_recovery_fee: Decimal,
+    pub er_threshold: Decimal,
+    pub reward_denom: String,
+}
+
+/// Batch id and amount of every unbond request of an address
+pub type UnbondRequest = Vec<(u64, Uint128)>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UnbondRequestsResponse {
+    pub address: HumanAddr,
+    pub requests: UnbondRequest,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UnbondHistory {
+    pub batch_id: u64,
+    pub time: u64,
+    pub amount: Uint128,
+    pub applied_exchange_rate: Decimal,
+    pub withdraw_rate: Decimal,
+    pub released: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AllHistoryResponse {
+    pub history: Vec<UnbondHistory>,
+}
+
+fn query<S: Storage, A: Api, Q: Querier, T: DeserializeOwned>(
+    deps: &Extern<S, A, Q>,
+    hub_contract: &HumanAddr,
+    msg: &QueryMsg,
+) -> StdResult<T> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: hub_contract.clone(),
+        msg: to_binary(msg)?,
+    }))
+}
+
+pub fn query_config<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    hub_contract: &HumanAddr,
+) -> StdResult<ConfigResponse> {
+    query(deps, hub_contract, &QueryMsg::Config {})
+}
+
+pub fn query_state<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    hub_contract: &HumanAddr,
+) -> StdResult<StateResponse> {
+    query(deps, hub_contract, &QueryMsg::State {})
+}
+
+pub fn query_whitelisted_validators<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    hub_contract: &HumanAddr,
+) -> StdResult<WhitelistedValidatorsResponse> {
+    query(deps, hub_contract, &QueryMsg::WhitelistedValidators {})
+}
+
+/// The batch unbond requests are currently added to
+pub fn query_current_batch<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    hub_contract: &HumanAddr,
+) -> StdResult<CurrentBatchResponse> {
+    query(deps, hub_contract, &QueryMsg::CurrentBatch {})
+}
+
+/// Underlying coin `address` can withdraw at `block_time` from released batches
+pub fn query_withdrawable_unbonded<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    hub_contract: &HumanAddr,
+    address: &HumanAddr,
+    block_time: u64,
+) -> StdResult<WithdrawableUnbondedResponse> {
+    query(
+        deps,
+        hub_contract,
+        &QueryMsg::WithdrawableUnbonded {
+            address: address.clone(),
+            block_time,
+        },
+    )
+}
+
+pub fn query_parameters<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    hub_contract: &HumanAddr,
+) -> StdResult<Parameters> {
+    query(deps, hub_contract, &QueryMsg::Parameters {})
+}
+
+pub fn query_unbond_requests<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    hub_contract: &HumanAddr,
+    address: &HumanAddr,
+) -> StdResult<UnbondRequestsResponse> {
+    query(
+        deps,
+        hub_contract,
+        &QueryMsg::UnbondRequests {
+            address: address.clone(),
+        },
+    )
+}
+
+pub fn query_all_history<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    hub_contract: &HumanAddr,
+    start_from: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<AllHistoryResponse> {
+    query(
+        deps,
+        hub_contract,
+        &QueryMsg::AllHistory { start_from, limit },
+    )
+}
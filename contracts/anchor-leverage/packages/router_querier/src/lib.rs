@@ -0,0 +1,150 @@
+use cosmwasm_std::{HumanAddr, Uint128};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use terraswap::asset::AssetInfo;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapOperation {
+    /// Swap between Terra natives at the market module
+    NativeSwap {
+        offer_denom: String,
+        ask_denom: String,
+    },
+    /// Swap on the Terraswap pair of the two assets
+    TerraSwap {
+        offer_asset_info: AssetInfo,
+        ask_asset_info: AssetInfo,
+    },
+}
+
+impl SwapOperation {
+    pub fn offer_asset_info(&self) -> AssetInfo {
+        match self {
+            SwapOperation::NativeSwap { offer_denom, .. } => AssetInfo::NativeToken {
+                denom: offer_denom.clone(),
+            },
+            SwapOperation::TerraSwap {
+                offer_asset_info, ..
+            } => offer_asset_info.clone(),
+        }
+    }
+
+    pub fn ask_asset_info(&self) -> AssetInfo {
+        match self {
+            SwapOperation::NativeSwap { ask_denom, .. } => AssetInfo::NativeToken {
+                denom: ask_denom.clone(),
+            },
+            SwapOperation::TerraSwap { ask_asset_info, .. } => ask_asset_info.clone(),
+        }
+    }
+
+    /// Same hop in the opposite direction
+    pub fn reverse(&self) -> SwapOperation {
+        match self {
+            SwapOperation::NativeSwap {
+                offer_denom,
+                ask_denom,
+            } => SwapOperation::NativeSwap {
+                offer_denom: ask_denom.clone(),
+                ask_denom: offer_denom.clone(),
+            },
+            SwapOperation::TerraSwap {
+                offer_asset_info,
+                ask_asset_info,
+            } => SwapOperation::TerraSwap {
+                offer_asset_info: ask_asset_info.clone(),
+                ask_asset_info: offer_asset_info.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HandleMsg {
+    /// Executes the operations in order, native offers are sent along
+    ExecuteSwapOperations {
+        operations: Vec<SwapOperation>,
+        minimum_receive: Option<Uint128>,
+        to: Option<HumanAddr>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw20HookMsg {
+    /// Executes the operations in order with the sent cw20 tokens
+    ExecuteSwapOperations {
+        operations: Vec<SwapOperation>,
+        minimum_receive: Option<Uint128>,
+        to: Option<HumanAddr>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsg {
+    Config {},
+    SimulateSwapOperations {
+        offer_amount: Uint128,
+        operations: Vec<SwapOperation>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    pub terraswap_factory: HumanAddr,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateSwapOperationsResponse {
+    pub amount: Uint128,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn native(denom: &str) -> AssetInfo {
+        AssetInfo::NativeToken {
+            denom: denom.to_string(),
+        }
+    }
+
+    fn token(contract_addr: &str) -> AssetInfo {
+        AssetInfo::Token {
+            contract_addr: HumanAddr::from(contract_addr),
+        }
+    }
+
+    #[test]
+    fn reverse_swaps_offer_and_ask() {
+        let operation = SwapOperation::NativeSwap {
+            offer_denom: "uusd".to_string(),
+            ask_denom: "uluna".to_string(),
+        };
+        assert_eq!(
+            operation.reverse(),
+            SwapOperation::NativeSwap {
+                offer_denom: "uluna".to_string(),
+                ask_denom: "uusd".to_string(),
+            }
+        );
+        assert_eq!(operation.reverse().offer_asset_info(), native("uluna"));
+        assert_eq!(operation.reverse().ask_asset_info(), native("uusd"));
+
+        let operation = SwapOperation::TerraSwap {
+            offer_asset_info: native("uluna"),
+            ask_asset_info: token("weth"),
+        };
+        assert_eq!(
+            operation.reverse(),
+            SwapOperation::TerraSwap {
+                offer_asset_info: token("weth"),
+                ask_asset_info: native("uluna"),
+            }
+        );
+        assert_eq!(operation.reverse().reverse(), operation);
+    }
+}
@@ -1,50 +1,106 @@
+use std::cmp::min;
 use std::ops::Mul;
 
 use cosmwasm_bignumber::{Decimal256, Uint256};
 use cosmwasm_std::{
-    coin, log, to_binary, Api, Binary, Coin, CosmosMsg, Empty, Env, Extern, HandleResponse,
-    HumanAddr, InitResponse, Querier, StdError, StdResult, Storage, Uint128, WasmMsg,
+    coin, from_binary, log, to_binary, Api, BankMsg, Binary, CanonicalAddr, CosmosMsg, Empty, Env,
+    Extern, HandleResponse, HumanAddr, InitResponse, LogAttribute, Querier, StdError, StdResult,
+    Storage, Uint128, WasmMsg,
 };
+use cw20::Cw20ReceiveMsg;
+use router_querier::SwapOperation;
+use terraswap::asset::{AssetInfo, AssetInfoRaw};
 
-use crate::msg::{HandleAnswer, HandleMsg, InitMsg, PossibleBorrowResponse, QueryMsg};
+use crate::msg::{
+    ApyResponse, CollateralStateResponse, CollateralsResponse, Cw20HookMsg, DepositCallbackMsg,
+    HandleAnswer, HandleMsg, HooksResponse, InitMsg, LiquidationResponse, LiquidationsResponse,
+    OperatorResponse, OperatorsResponse, PnlAmount, PnlResponse, PositionChange, PositionHookMsg,
+    PositionResponse, PositionSummary, PositionsOrder, PositionsResponse, PossibleBorrowResponse,
+    QueryMsg, SlashingResponse, SlashingsResponse, StateResponse, UnbondRequestsResponse,
+    ValidatorsResponse,
+};
 use crate::querier;
-use crate::state::{get_config, set_config, Config};
+use crate::state::{
+    get_collateral, get_collateral_state, get_collaterals, get_config, get_hooks, get_operators,
+    get_position, get_realized_pnl, get_state, get_unbond_requests, get_validators,
+    may_get_collateral, may_get_deposit_callback, may_get_operator, push_liquidation,
+    push_slashing, read_hooks, read_liquidations, read_positions, read_positions_by_risk,
+    read_slashings, remove_deposit_callback, remove_operator, remove_position, set_collateral,
+    set_collateral_state, set_config, set_deposit_callback, set_hooks, set_operator, set_position,
+    set_realized_pnl, set_state, set_unbond_requests, set_validators, Collateral, CollateralMsg,
+    Config, DepositCallback, Liquidation, MintRoute, MintRouteMsg, Operator, OperatorScope,
+    Slashing, State, SwapRoute, SwapRouteMsg, UnbondRequest, Validator,
+};
 
 pub const DECIMAL_FRACTIONAL: u128 = 1_000_000_000_000_000_000;
 pub const BORROW_LTV_PERCENTAGE: u64 = 70; // 70% of 50% of TVL, borrow limit is at 50% of LTV, we use recommended 35%
 pub const STOP_SWAPPING_ON: u128 = 10_000_000;
-pub const ACCEPTED_DENOM: &str = "uluna";
+pub const DELEVERAGE_SAFETY_PERCENTAGE: u64 = 90; // unlock at most 90% of the collateral the borrow limit frees up
 pub const TERRASWAP_PAIR: &str = "uusd";
+pub const BLOCKS_PER_YEAR: u128 = 4_656_810; // same estimate the Anchor market uses
+pub const MAX_HOOKS: usize = 5;
+pub const HOOK_OWNERS_PER_CALL: u32 = 10;
+pub const LIQUIDATIONS_PER_CALL: u32 = 30; // the storage page limit, the rest waits for the next call
 
 /// Contract instantiation tx
 /// tx inputs are specified in InitMsg in msg.rs file
 pub fn init<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
-    _env: Env,
+    env: Env,
     msg: InitMsg,
 ) -> StdResult<InitResponse> {
-    set_config(
-        &mut deps.storage,
-        &Config {
-            basset_hub_contract: deps.api.canonical_address(&msg.config.basset_hub_contract)?,
-            basset_token_contract: deps
-                .api
-                .canonical_address(&msg.config.basset_token_contract)?,
-            basset_collateral_contract: deps
-                .api
-                .canonical_address(&msg.config.basset_collateral_contract)?,
-            anchor_overseer_contract: deps
-                .api
-                .canonical_address(&msg.config.anchor_overseer_contract)?,
-            anchor_market_contract: deps
-                .api
-                .canonical_address(&msg.config.anchor_market_contract)?,
-            terraswap_luna_ust: deps.api.canonical_address(&msg.config.terraswap_luna_ust)?,
-            preferred_validator: msg.config.preferred_validator,
-        },
-    )?;
+    validate_fee(msg.config.performance_fee)?;
+    validate_fee(msg.config.management_fee)?;
+    validate_slippage(msg.config.max_slippage)?;
+
+    let config = Config {
+        owner: deps
+            .api
+            .canonical_address(msg.owner.as_ref().unwrap_or(&env.message.sender))?,
+        contract_addr: deps.api.canonical_address(&env.contract.address)?,
+        anchor_overseer_contract: deps
+            .api
+            .canonical_address(&msg.config.anchor_overseer_contract)?,
+        anchor_market_contract: deps
+            .api
+            .canonical_address(&msg.config.anchor_market_contract)?,
+        anchor_oracle_contract: deps
+            .api
+            .canonical_address(&msg.config.anchor_oracle_contract)?,
+        anc_token: deps.api.canonical_address(&msg.config.anc_token)?,
+        terraswap_anc_ust: deps.api.canonical_address(&msg.config.terraswap_anc_ust)?,
+        fee_admin: deps
+            .api
+            .canonical_address(msg.fee_admin.as_ref().unwrap_or(&env.message.sender))?,
+        fee_collector: deps.api.canonical_address(&msg.config.fee_collector)?,
+        performance_fee: msg.config.performance_fee,
+        management_fee: msg.config.management_fee,
+        max_total_deposit: msg.config.max_total_deposit,
+        max_user_deposit: msg.config.max_user_deposit,
+        min_deposit: msg.config.min_deposit,
+        max_slippage: msg.config.max_slippage,
+    };
+    validate_deposit_limits(&config)?;
+    validate_contracts(deps, &config)?;
+    set_config(&mut deps.storage, &config)?;
+    set_state(&mut deps.storage, &State::default())?;
+    for collateral in msg.config.collaterals.iter() {
+        store_collateral(deps, collateral)?;
+    }
+
+    let mut messages = vec![];
+    if let Some(init_hook) = msg.init_hook {
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: init_hook.contract_addr,
+            msg: init_hook.msg,
+            send: vec![],
+        }));
+    }
 
-    Ok(InitResponse::default())
+    Ok(InitResponse {
+        messages,
+        log: vec![],
+    })
 }
 
 /// General handler for contract tx input
@@ -54,13 +110,164 @@ pub fn handle<S: Storage, A: Api, Q: Querier>(
     env: Env,
     msg: HandleMsg,
 ) -> StdResult<HandleResponse<Empty>> {
-    match msg {
-        HandleMsg::Deposit {} => deposit(deps, env),
-        HandleMsg::DepositCollateral {} => deposit_collateral(deps, env),
-        HandleMsg::Borrow {} => borrow(deps, env),
-        HandleMsg::Swap { amount } => swap(deps, env, amount),
-        HandleMsg::Redeposit {} => redeposit(deps, env),
+    // hooks and callbacks sent mid-loop must not start another loop or move
+    // balances the running one reads before it is done
+    if env.message.sender != env.contract.address && get_state(&deps.storage)?.loop_in_progress {
+        return Err(StdError::generic_err(
+            "A deposit or withdraw is in progress, try again once it is done",
+        ));
     }
+
+    // liquidations are caught up with before any handler reads the pooled state
+    let liquidation_log = reconcile(deps, &env)?;
+
+    let mut response = match msg {
+        HandleMsg::Deposit {
+            collateral,
+            earn_share,
+            callback,
+            callback_contract,
+        } => deposit(
+            deps,
+            env,
+            collateral,
+            earn_share,
+            deposit_callback(callback, callback_contract)?,
+        ),
+        HandleMsg::Receive(msg) => receive_cw20(deps, env, msg),
+        HandleMsg::DepositCollateral {
+            collateral,
+            owner,
+            iteration,
+            balance_before,
+        } => deposit_collateral(deps, env, collateral, owner, iteration, balance_before),
+        HandleMsg::Borrow {
+            collateral,
+            owner,
+            iteration,
+        } => borrow(deps, env, collateral, owner, iteration),
+        HandleMsg::CreditEarn {
+            collateral,
+            owner,
+            iteration,
+        } => credit_earn(deps, env, collateral, owner, iteration),
+        HandleMsg::Swap {
+            collateral,
+            amount,
+            owner,
+            iteration,
+        } => swap(deps, env, collateral, amount, owner, iteration),
+        HandleMsg::Redeposit {
+            collateral,
+            owner,
+            iteration,
+            balance_before,
+        } => redeposit(deps, env, collateral, owner, iteration, balance_before),
+        HandleMsg::RegisterCollateral { collateral } => register_collateral(deps, env, collateral),
+        HandleMsg::UpdateValidators {
+            collateral,
+            validators,
+        } => update_validators(deps, env, collateral, validators),
+        HandleMsg::UpdateConfig {
+            owner,
+            max_total_deposit,
+            max_user_deposit,
+            min_deposit,
+            max_slippage,
+        } => update_config(
+            deps,
+            env,
+            owner,
+            max_total_deposit,
+            max_user_deposit,
+            min_deposit,
+            max_slippage,
+        ),
+        HandleMsg::UpdateFees {
+            fee_admin,
+            fee_collector,
+            performance_fee,
+            management_fee,
+        } => update_fees(
+            deps,
+            env,
+            fee_admin,
+            fee_collector,
+            performance_fee,
+            management_fee,
+        ),
+        HandleMsg::Withdraw {
+            collateral,
+            unbond,
+            owner,
+        } => withdraw(deps, env, collateral, unbond.unwrap_or(false), owner),
+        HandleMsg::Deleverage {
+            collateral,
+            owner,
+            iteration,
+            unbond,
+        } => deleverage(deps, env, collateral, owner, iteration, unbond),
+        HandleMsg::SwapToStable {
+            collateral,
+            owner,
+            iteration,
+            unbond,
+            balance_before,
+        } => swap_to_stable(
+            deps,
+            env,
+            collateral,
+            owner,
+            iteration,
+            unbond,
+            balance_before,
+        ),
+        HandleMsg::Repay {
+            collateral,
+            owner,
+            iteration,
+            unbond,
+            balance_before,
+        } => repay(
+            deps,
+            env,
+            collateral,
+            owner,
+            iteration,
+            unbond,
+            balance_before,
+        ),
+        HandleMsg::ClaimUnbonded { collateral, owner } => {
+            claim_unbonded(deps, env, collateral, owner)
+        }
+        HandleMsg::ForwardUnbonded {
+            collateral,
+            owner,
+            balance_before,
+        } => forward_unbonded(deps, env, collateral, owner, balance_before),
+        HandleMsg::Harvest {} => harvest(deps, env),
+        HandleMsg::SellRewards { balance_before } => sell_rewards(deps, env, balance_before),
+        HandleMsg::CompoundRewards { balance_before } => {
+            compound_rewards(deps, env, balance_before)
+        }
+        HandleMsg::ClaimFees {} => claim_fees(deps, env),
+        HandleMsg::Reconcile {} => notify_liquidations(deps),
+        HandleMsg::AddHook { contract, owner } => add_hook(deps, env, contract, owner),
+        HandleMsg::RemoveHook { contract, owner } => remove_hook(deps, env, contract, owner),
+        HandleMsg::SetEarnShare {
+            collateral,
+            earn_share,
+            owner,
+        } => set_earn_share(deps, env, collateral, earn_share, owner),
+        HandleMsg::GrantOperator {
+            operator,
+            scopes,
+            expires,
+        } => grant_operator(deps, env, operator, scopes, expires),
+        HandleMsg::RevokeOperator { operator } => revoke_operator(deps, env, operator),
+    }?;
+    response.log.extend(liquidation_log);
+    Ok(response)
 }
 
 pub fn query<S: Storage, A: Api, Q: Querier>(
@@ -72,79 +279,292 @@ pub fn query<S: Storage, A: Api, Q: Querier>(
             contract_addr,
             block_time,
             block_height,
+            collateral,
+            owner,
         } => to_binary(&query_possible_borrow(
-            &deps,
+            deps,
             &contract_addr,
             block_time,
             block_height,
+            collateral,
+            owner,
         )?),
         QueryMsg::Collateral {
+            collateral,
             contract_addr,
-        } => to_binary(&query_collateral(
-            &deps,
-            &contract_addr,
+        } => to_binary(&query_collateral(deps, &collateral, &contract_addr)?),
+        QueryMsg::Collaterals {} => to_binary(&query_collaterals(deps)?),
+        QueryMsg::Validators { collateral } => to_binary(&ValidatorsResponse {
+            validators: get_validators(&deps.storage, &collateral)?,
+        }),
+        QueryMsg::State {} => to_binary(&query_state(deps)?),
+        QueryMsg::Position { collateral, owner } => {
+            to_binary(&query_position(deps, collateral, owner)?)
+        }
+        QueryMsg::Pnl { collateral, owner } => to_binary(&query_pnl(deps, collateral, owner)?),
+        QueryMsg::Liquidations { start_after, limit } => {
+            to_binary(&query_liquidations(deps, start_after, limit)?)
+        }
+        QueryMsg::Slashings { start_after, limit } => {
+            to_binary(&query_slashings(deps, start_after, limit)?)
+        }
+        QueryMsg::UnbondRequests { collateral, owner } => {
+            to_binary(&query_unbond_requests(deps, collateral, owner)?)
+        }
+        QueryMsg::Hooks { owner } => to_binary(&query_hooks(deps, owner)?),
+        QueryMsg::Operators { owner } => to_binary(&query_operators(deps, owner)?),
+        QueryMsg::Positions {
+            collateral,
+            start_after,
+            limit,
+            order_by,
+        } => to_binary(&query_positions(
+            deps,
+            collateral,
+            start_after,
+            limit,
+            order_by,
         )?),
+        QueryMsg::Apy {
+            collateral,
+            leverage,
+            earn_share,
+        } => to_binary(&query_apy(deps, collateral, leverage, earn_share)?),
     }
 }
 
 fn deposit<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
+    collateral: String,
+    earn_share: Option<Decimal256>,
+    callback: Option<(HumanAddr, Binary)>,
 ) -> StdResult<HandleResponse> {
-    let received = env.message.sent_funds.get(0);
-    if env.message.sent_funds.len() != 1 || received.unwrap().denom.ne(ACCEPTED_DENOM) {
+    let denom = match get_collateral(&deps.storage, &collateral)?
+        .deposit_asset
+        .to_normal(deps)?
+    {
+        AssetInfo::NativeToken { denom } => denom,
+        AssetInfo::Token { contract_addr } => {
+            return Err(StdError::generic_err(format!(
+                "'{}' is deposited by sending '{}'",
+                collateral, contract_addr
+            )))
+        }
+    };
+
+    let received = env.message.sent_funds.first();
+    if env.message.sent_funds.len() != 1 || received.unwrap().denom.ne(&denom) {
         Err(StdError::generic_err(format!(
             "Only '{}' is accepted",
-            ACCEPTED_DENOM
+            denom
         )))
     } else {
-        deposit_msgs(deps, &env, received.unwrap())
+        let amount = received.unwrap().amount;
+        let owner = env.message.sender.clone();
+        deposit_asset(deps, &env, collateral, earn_share, amount, owner, callback)
+    }
+}
+
+fn receive_cw20<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    cw20_msg: Cw20ReceiveMsg,
+) -> StdResult<HandleResponse> {
+    match cw20_msg.msg {
+        Some(msg) => match from_binary(&msg)? {
+            Cw20HookMsg::Deposit {
+                collateral,
+                earn_share,
+                callback,
+                callback_contract,
+            } => {
+                let token_raw = deps.api.canonical_address(&env.message.sender)?;
+                if get_collateral(&deps.storage, &collateral)?.deposit_asset
+                    != (AssetInfoRaw::Token {
+                        contract_addr: token_raw,
+                    })
+                {
+                    return Err(StdError::unauthorized());
+                }
+
+                deposit_asset(
+                    deps,
+                    &env,
+                    collateral,
+                    earn_share,
+                    cw20_msg.amount,
+                    cw20_msg.sender,
+                    deposit_callback(callback, callback_contract)?,
+                )
+            }
+        },
+        None => Err(StdError::generic_err("Hook message is required")),
+    }
+}
+
+/// Records the owner's cost basis and strategy and starts the leverage loop
+/// with the deposit, a position keeps its earn share unless a new one is given
+fn deposit_asset<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    collateral: String,
+    earn_share: Option<Decimal256>,
+    amount: Uint128,
+    owner: HumanAddr,
+    callback: Option<(HumanAddr, Binary)>,
+) -> StdResult<HandleResponse> {
+    let config = get_config(&deps.storage)?;
+    let registered = get_collateral(&deps.storage, &collateral)?;
+    let owner_raw = deps.api.canonical_address(&owner)?;
+    let mut position = get_position(&deps.storage, &collateral, &owner_raw)?;
+    if let Some(earn_share) = earn_share {
+        if earn_share >= Decimal256::one() {
+            return Err(StdError::generic_err("Earn share must be lower than 1"));
+        }
+        position.earn_share = earn_share;
+    }
+    let price = query_deposit_price(deps, &config, &registered)?;
+    let value = Uint256::from(amount) * price;
+    validate_deposit(deps, &config, &owner_raw, value)?;
+
+    let mut state = get_state(&deps.storage)?;
+    state.total_deposit += value;
+    state.loop_in_progress = true;
+    set_state(&mut deps.storage, &state)?;
+    position.cost_basis += Uint256::from(amount);
+    position.cost_basis_uusd += value;
+    set_position(&mut deps.storage, &collateral, &owner_raw, &position)?;
+    if let Some((contract, msg)) = callback {
+        set_deposit_callback(
+            &mut deps.storage,
+            &collateral,
+            &owner_raw,
+            &DepositCallback {
+                contract: deps.api.canonical_address(&contract)?,
+                msg,
+                bonded: Uint256::zero(),
+                borrowed: Uint256::zero(),
+            },
+        )?;
     }
+
+    deposit_msgs(deps, env, collateral, amount, owner, 0)
 }
 
 fn deposit_collateral<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
+    collateral: String,
+    owner: HumanAddr,
+    iteration: u32,
+    balance_before: Uint128,
 ) -> StdResult<HandleResponse> {
     if env.message.sender != env.contract.address {
         return Err(StdError::unauthorized());
     }
 
     let config = get_config(&deps.storage)?;
-    let bonded = querier::query_bonded_asset(
-        &deps,
-        &deps.api.human_address(&config.basset_collateral_contract)?,
-        &env.contract.address,
+    let registered = get_collateral(&deps.storage, &collateral)?;
+    let basset_token = deps.api.human_address(&registered.token)?;
+    let mut collateral_state = get_collateral_state(&deps.storage, &collateral)?;
+    // bAsset fees and whatever else the contract holds stay where they are
+    let bonded = (querier::query_bonded_asset(deps, &basset_token, &env.contract.address)?
+        - balance_before)?;
+
+    let owner_raw = deps.api.canonical_address(&owner)?;
+    let mut position = get_position(&deps.storage, &collateral, &owner_raw)?;
+    let balance = from_shares(
+        position.collateral_shares,
+        collateral_state.total_collateral_shares,
+        collateral_state.total_collateral,
+    );
+    let exchange_rate = query_mint_rate(deps, &registered)?;
+    position.exchange_rate = if balance.is_zero() || position.exchange_rate.is_zero() {
+        exchange_rate
+    } else {
+        Decimal256::from_uint256(
+            balance * position.exchange_rate + Uint256::from(bonded) * exchange_rate,
+        ) / Decimal256::from_uint256(balance + bonded.into())
+    };
+    let shares = to_shares(
+        bonded.into(),
+        collateral_state.total_collateral_shares,
+        collateral_state.total_collateral,
+    )?;
+    position.collateral_shares += shares;
+    collateral_state.total_collateral_shares += shares;
+    collateral_state.total_collateral += bonded.into();
+    set_position(&mut deps.storage, &collateral, &owner_raw, &position)?;
+    set_collateral_state(&mut deps.storage, &collateral, &collateral_state)?;
+    track_deposit_callback(
+        deps,
+        &collateral,
+        &owner_raw,
+        bonded.into(),
+        Uint256::zero(),
     )?;
 
-    Ok(HandleResponse {
-        messages: vec![
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: deps.api.human_address(&config.basset_collateral_contract)?,
-                send: vec![],
-                msg: querier::deposit_basset_collateral(
-                    &deps.api.human_address(&config.basset_token_contract)?,
-                    bonded.into(),
-                )?,
-            }),
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: deps.api.human_address(&config.anchor_overseer_contract)?,
-                send: vec![],
-                msg: querier::overseer_lock_collateral(
-                    &deps.api.human_address(&config.basset_collateral_contract)?,
-                    bonded.into(),
-                )?,
-            }),
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: env.contract.address,
+    let loan =
+        querier::query_loan_amount(deps, &config, &env.contract.address, Some(env.block.height))?;
+    let mut logs = vec![log("action", "deposit_collateral"), log("bonded", bonded)];
+    logs.extend(step_log(
+        deps,
+        &collateral,
+        &owner,
+        iteration,
+        loan,
+        Queried {
+            exchange_rate: Some(exchange_rate),
+            ..Queried::default()
+        },
+    )?);
+
+    let mut messages = vec![
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: basset_token.clone(),
+            send: vec![],
+            msg: querier::deposit_basset_collateral(
+                &deps.api.human_address(&registered.custody_contract)?,
+                bonded,
+            )?,
+        }),
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: deps.api.human_address(&config.anchor_overseer_contract)?,
+            send: vec![],
+            msg: querier::overseer_lock_collateral(&basset_token, bonded.into())?,
+        }),
+    ];
+    // the hub only lowers its exchange rate for a slashing once asked to look
+    if let MintRoute::Hub { hub_contract } = &registered.mint_route {
+        let hub_contract = deps.api.human_address(hub_contract)?;
+        if querier::query_hub_slashed(deps, &hub_contract)? {
+            messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: hub_contract,
                 send: vec![],
-                msg: to_binary(&HandleMsg::Borrow {})?,
-            }),
-        ],
-        log: vec![
-            log("action", "deposit_collateral"),
-            log("bonded", bonded.to_string()),
-        ],
+                msg: querier::check_slashing()?,
+            }));
+        }
+    }
+    messages.extend(hook_msgs(
+        deps,
+        &owner,
+        &collateral,
+        PositionChange::Deposit,
+    )?);
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address,
+        send: vec![],
+        msg: to_binary(&HandleMsg::Borrow {
+            collateral,
+            owner,
+            iteration,
+        })?,
+    }));
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
         data: Some(to_binary(&HandleAnswer::Deposit)?),
     })
 }
@@ -152,85 +572,281 @@ fn deposit_collateral<S: Storage, A: Api, Q: Querier>(
 fn borrow<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
+    collateral: String,
+    owner: HumanAddr,
+    iteration: u32,
 ) -> StdResult<HandleResponse> {
     if env.message.sender != env.contract.address {
         return Err(StdError::unauthorized());
     }
 
+    let slashing_log = record_slashing(deps, &env, &collateral)?;
     let config = get_config(&deps.storage)?;
+    let owner_raw = deps.api.canonical_address(&owner)?;
     let possible_borrow = get_possible_borrow(
         deps,
         &config,
         &env.contract.address,
         Some(env.block.time),
         Some(env.block.height),
+        Some((&collateral, &owner_raw)),
+    )?;
+    let mut position = get_position(&deps.storage, &collateral, &owner_raw)?;
+
+    // the market sends the loan minus its own tax estimate, out of which the
+    // earn deposit and the swap can send whatever leaves room for their tax
+    let borrow_after_tax = Uint256::from(
+        moneymarket::querier::deduct_tax(
+            deps,
+            coin(possible_borrow.borrow_amount.into(), TERRASWAP_PAIR),
+        )?
+        .amount,
+    );
+    let earn_amount = querier::deduct_tax(deps, borrow_after_tax * position.earn_share)?;
+    let swap_amount = querier::deduct_tax(
+        deps,
+        borrow_after_tax - earn_amount - querier::compute_tax(deps, earn_amount)?,
+    )?;
+
+    // a loan too small to swap and deposit again ends the loop unborrowed,
+    // so no stable is left behind that the next round would not pick up
+    if swap_amount <= Uint256::from(STOP_SWAPPING_ON) {
+        let mut logs = vec![log("action", "borrow"), log("borrow_amount", "0")];
+        logs.extend(slashing_log);
+        logs.extend(step_log(
+            deps,
+            &collateral,
+            &owner,
+            iteration,
+            possible_borrow.already_borrowed,
+            Queried::default(),
+        )?);
+        return Ok(HandleResponse {
+            messages: finish_deposit(deps, &collateral, &owner, iteration)?,
+            log: logs,
+            data: Some(to_binary(&HandleAnswer::Borrow)?),
+        });
+    }
+
+    let mut state = get_state(&deps.storage)?;
+    let shares = to_shares(
+        possible_borrow.borrow_amount,
+        state.total_debt_shares,
+        possible_borrow.already_borrowed,
     )?;
-    let borrow_after_tax = moneymarket::querier::deduct_tax(
-        &deps,
-        coin(possible_borrow.borrow_amount.into(), TERRASWAP_PAIR),
+    position.debt_shares += shares;
+    state.total_debt_shares += shares;
+    set_position(&mut deps.storage, &collateral, &owner_raw, &position)?;
+    set_state(&mut deps.storage, &state)?;
+    track_deposit_callback(
+        deps,
+        &collateral,
+        &owner_raw,
+        Uint256::zero(),
+        possible_borrow.borrow_amount,
     )?;
 
+    let anchor_market_contract = deps.api.human_address(&config.anchor_market_contract)?;
+    let mut messages = vec![CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: anchor_market_contract.clone(),
+        send: vec![],
+        msg: querier::anchor_borrow(possible_borrow.borrow_amount)?,
+    })];
+    if !earn_amount.is_zero() {
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: anchor_market_contract,
+            send: vec![coin(earn_amount.into(), TERRASWAP_PAIR)],
+            msg: querier::anchor_deposit_stable()?,
+        }));
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: env.contract.address.clone(),
+            send: vec![],
+            msg: to_binary(&HandleMsg::CreditEarn {
+                collateral: collateral.clone(),
+                owner: owner.clone(),
+                iteration,
+            })?,
+        }));
+    }
+
+    let mut logs = vec![
+        log("action", "borrow"),
+        log("borrow_amount", possible_borrow.borrow_amount.to_string()),
+        log("borrow_amount_after_tax", borrow_after_tax),
+        log("earn_amount", earn_amount),
+        log("swap_amount", swap_amount),
+    ];
+    logs.extend(slashing_log);
+    logs.extend(step_log(
+        deps,
+        &collateral,
+        &owner,
+        iteration,
+        possible_borrow.already_borrowed + possible_borrow.borrow_amount,
+        Queried::default(),
+    )?);
+
+    messages.extend(hook_msgs(
+        deps,
+        &owner,
+        &collateral,
+        PositionChange::Borrow,
+    )?);
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address,
+        send: vec![],
+        msg: to_binary(&HandleMsg::Swap {
+            collateral,
+            amount: swap_amount.into(),
+            owner,
+            iteration,
+        })?,
+    }));
+
     Ok(HandleResponse {
-        messages: vec![
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: deps.api.human_address(&config.anchor_market_contract)?,
-                send: vec![],
-                msg: querier::anchor_borrow(possible_borrow.borrow_amount)?,
-            }),
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: env.contract.address,
-                send: vec![],
-                msg: to_binary(&HandleMsg::Swap {
-                    amount: borrow_after_tax.amount,
-                })?,
-            }),
-        ],
-        log: vec![
-            log("action", "borrow"),
-            log("borrow_amount", possible_borrow.borrow_amount.to_string()),
-            log(
-                "borrow_amount_after_tax",
-                borrow_after_tax.amount.to_string(),
-            ),
-        ],
+        messages,
+        log: logs,
         data: Some(to_binary(&HandleAnswer::Borrow)?),
     })
 }
 
+/// Records a drop of the hub exchange rate since the last deposit as a
+/// slashing and remembers the rate this deposit was made at
+fn record_slashing<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    collateral: &str,
+) -> StdResult<Vec<LogAttribute>> {
+    let registered = get_collateral(&deps.storage, collateral)?;
+    if let MintRoute::Converter { .. } = registered.mint_route {
+        return Ok(vec![]);
+    }
+
+    let exchange_rate = query_mint_rate(deps, &registered)?;
+    let mut collateral_state = get_collateral_state(&deps.storage, collateral)?;
+    let mut logs = vec![];
+    if !collateral_state.exchange_rate.is_zero() && exchange_rate < collateral_state.exchange_rate {
+        let mut state = get_state(&deps.storage)?;
+        push_slashing(
+            &mut deps.storage,
+            state.slashings,
+            &Slashing {
+                collateral: collateral.to_string(),
+                block_height: env.block.height,
+                previous_rate: collateral_state.exchange_rate,
+                exchange_rate,
+            },
+        )?;
+        state.slashings += 1;
+        set_state(&mut deps.storage, &state)?;
+        logs.push(log("slashing", collateral));
+        logs.push(log("previous_rate", collateral_state.exchange_rate));
+    }
+    collateral_state.exchange_rate = exchange_rate;
+    set_collateral_state(&mut deps.storage, collateral, &collateral_state)?;
+
+    Ok(logs)
+}
+
+/// Credits the owner with the aUST the market minted for the parked loan
+fn credit_earn<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    collateral: String,
+    owner: HumanAddr,
+    iteration: u32,
+) -> StdResult<HandleResponse> {
+    if env.message.sender != env.contract.address {
+        return Err(StdError::unauthorized());
+    }
+
+    let config = get_config(&deps.storage)?;
+    let mut state = get_state(&deps.storage)?;
+    let aust_token = querier::query_market_config(deps, &config)?.aterra_contract;
+    let minted = Uint256::from(terraswap::querier::query_token_balance(
+        deps,
+        &aust_token,
+        &env.contract.address,
+    )?) - state.total_aust;
+
+    let owner_raw = deps.api.canonical_address(&owner)?;
+    let mut position = get_position(&deps.storage, &collateral, &owner_raw)?;
+    position.aust += minted;
+    state.total_aust += minted;
+    set_position(&mut deps.storage, &collateral, &owner_raw, &position)?;
+    set_state(&mut deps.storage, &state)?;
+
+    let loan =
+        querier::query_loan_amount(deps, &config, &env.contract.address, Some(env.block.height))?;
+    let mut logs = vec![log("action", "credit_earn"), log("aust", minted)];
+    logs.extend(step_log(
+        deps,
+        &collateral,
+        &owner,
+        iteration,
+        loan,
+        Queried::default(),
+    )?);
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: logs,
+        data: None,
+    })
+}
+
 fn swap<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
+    collateral: String,
     amount: Uint128,
+    owner: HumanAddr,
+    iteration: u32,
 ) -> StdResult<HandleResponse> {
     if env.message.sender != env.contract.address {
         return Err(StdError::unauthorized());
     }
 
     let config = get_config(&deps.storage)?;
+    let registered = get_collateral(&deps.storage, &collateral)?;
 
-    let amount: Uint128 = Uint256::from(amount.u128())
-        .mul(Decimal256::from_ratio(998, 1000))
-        .into();
+    let (swap, received) = route_swap_msg(deps, &registered, &stable_asset(), amount, None)?;
+    let mut messages = vec![swap];
+    let balance_before = registered
+        .deposit_asset
+        .to_normal(deps)?
+        .query_pool(deps, &env.contract.address)?;
 
-    let mut messages=vec![
-        CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: deps.api.human_address(&config.terraswap_luna_ust)?,
-            send: vec![coin(amount.u128(), TERRASWAP_PAIR)],
-            msg: querier::swap_to_collateral(amount)?,
-        }),
+    let loan =
+        querier::query_loan_amount(deps, &config, &env.contract.address, Some(env.block.height))?;
+    let mut logs = vec![
+        log("action", "swap"),
+        log("swap_amount", amount),
+        log("swap_price", swap_price(amount, received)),
     ];
+    logs.extend(step_log(
+        deps,
+        &collateral,
+        &owner,
+        iteration,
+        loan,
+        Queried::default(),
+    )?);
+
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address,
+        send: vec![],
+        msg: to_binary(&HandleMsg::Redeposit {
+            collateral,
+            owner,
+            iteration,
+            balance_before,
+        })?,
+    }));
 
-    if amount.u128() > STOP_SWAPPING_ON {
-        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: env.contract.address,
-            send: vec![],
-            msg: to_binary(&HandleMsg::Redeposit {})?,
-        }));
-    }
-    
     Ok(HandleResponse {
         messages,
-        log: vec![log("action", "swap"), log("swap_amount", amount)],
+        log: logs,
         data: Some(to_binary(&HandleAnswer::Borrow)?),
     })
 }
@@ -238,81 +854,5891 @@ fn swap<S: Storage, A: Api, Q: Querier>(
 fn redeposit<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
+    collateral: String,
+    owner: HumanAddr,
+    iteration: u32,
+    balance_before: Uint128,
 ) -> StdResult<HandleResponse> {
-    let received = deps
-        .querier
-        .query_balance(env.contract.address.clone(), ACCEPTED_DENOM)?;
-    deposit_msgs(deps, &env, &received)
+    if env.message.sender != env.contract.address {
+        return Err(StdError::unauthorized());
+    }
+
+    let received = (get_collateral(&deps.storage, &collateral)?
+        .deposit_asset
+        .to_normal(deps)?
+        .query_pool(deps, &env.contract.address)?
+        - balance_before)?;
+    deposit_msgs(deps, &env, collateral, received, owner, iteration + 1)
 }
 
 fn deposit_msgs<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: &Env,
-    received: &Coin,
+    collateral: String,
+    amount: Uint128,
+    owner: HumanAddr,
+    iteration: u32,
 ) -> StdResult<HandleResponse> {
     let config = get_config(&deps.storage)?;
+    let registered = get_collateral(&deps.storage, &collateral)?;
+    let deposit_asset = registered.deposit_asset.to_normal(deps)?;
+
+    let loan =
+        querier::query_loan_amount(deps, &config, &env.contract.address, Some(env.block.height))?;
+    let mut logs = vec![
+        log("action", "deposit"),
+        log("deposited", format!("{} {}", amount, deposit_asset)),
+    ];
+    logs.extend(step_log(
+        deps,
+        &collateral,
+        &owner,
+        iteration,
+        loan,
+        Queried::default(),
+    )?);
+
+    // the bAsset comes from whichever of minting and buying on the exit pair
+    // returns more, the buy is bounded by the same simulation it is chosen on,
+    // and a pair that cannot be simulated leaves minting
+    let minted = query_mint_return(deps, &registered, amount)?;
+    let exit_pair = deps.api.human_address(&registered.exit_pair)?;
+    let buy = match swap_msg(deps, &deposit_asset, &exit_pair, amount, None) {
+        Ok((buy, bought)) => Some((buy, Uint256::from(bought))),
+        Err(err) => {
+            logs.push(log("exit_pair_error", err));
+            None
+        }
+    };
+    let mut messages = match buy {
+        Some((buy, bought)) if bought > minted => {
+            logs.push(log("mint_route", "pair"));
+            logs.push(log("basset_expected", bought));
+            vec![buy]
+        }
+        _ => {
+            logs.push(log("mint_route", "mint"));
+            logs.push(log("basset_expected", minted));
+            mint_msgs(deps, &collateral, &registered, amount)?
+        }
+    };
+    let balance_before = querier::query_bonded_asset(
+        deps,
+        &deps.api.human_address(&registered.token)?,
+        &env.contract.address,
+    )?;
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address.clone(),
+        send: vec![],
+        msg: to_binary(&HandleMsg::DepositCollateral {
+            collateral,
+            owner,
+            iteration,
+            balance_before,
+        })?,
+    }));
 
     Ok(HandleResponse {
-        messages: vec![
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: deps.api.human_address(&config.basset_hub_contract)?,
-                send: vec![coin(received.amount.u128(), &received.denom)],
-                msg: querier::bond_luna(&config.preferred_validator)?,
-            }),
-            CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: env.contract.address.clone(),
-                send: vec![],
-                msg: to_binary(&HandleMsg::DepositCollateral {})?,
-            }),
-        ],
-        log: vec![
-            log("action", "deposit"),
-            log(
-                "deposited",
-                format!("{} {}", received.amount, received.denom),
-            ),
-        ],
+        messages,
+        log: logs,
         data: Some(to_binary(&HandleAnswer::Deposit)?),
     })
 }
 
-pub fn query_possible_borrow<S: Storage, A: Api, Q: Querier>(
+/// Mints the collateral's bAsset from `amount` of its deposit asset, a hub
+/// bonds the deposit spread over the validators, a converter takes it as cw20
+fn mint_msgs<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
-    contract_addr: &HumanAddr,
-    block_time: Option<u64>,
-    block_height: Option<u64>,
-) -> StdResult<PossibleBorrowResponse> {
-    let config = get_config(&deps.storage)?;
-    get_possible_borrow(deps, &config, contract_addr, block_time, block_height)
+    collateral: &str,
+    registered: &Collateral,
+    amount: Uint128,
+) -> StdResult<Vec<CosmosMsg>> {
+    match (
+        &registered.mint_route,
+        registered.deposit_asset.to_normal(deps)?,
+    ) {
+        (MintRoute::Hub { hub_contract }, AssetInfo::NativeToken { denom }) => {
+            let hub_contract = deps.api.human_address(hub_contract)?;
+            split_by_weight(amount, &get_validators(&deps.storage, collateral)?)
+                .into_iter()
+                .map(|(validator, amount)| {
+                    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+                        contract_addr: hub_contract.clone(),
+                        send: vec![coin(amount.u128(), &denom)],
+                        msg: querier::bond_luna(&validator)?,
+                    }))
+                })
+                .collect()
+        }
+        (
+            MintRoute::Converter { contract_addr, msg },
+            AssetInfo::Token {
+                contract_addr: token,
+            },
+        ) => Ok(vec![CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: token,
+            send: vec![],
+            msg: querier::convert_to_basset(&deps.api.human_address(contract_addr)?, amount, msg)?,
+        })]),
+        _ => Err(StdError::generic_err(format!(
+            "Collateral '{}' cannot be minted from its deposit asset",
+            collateral
+        ))),
+    }
 }
 
-pub fn get_possible_borrow<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>,
-    config: &Config,
-    contract_addr: &HumanAddr,
-    block_time: Option<u64>,
-    block_height: Option<u64>,
-) -> StdResult<PossibleBorrowResponse> {
-    // TODO: not safe enough for use by several people, possible fix: to factor a contract per client
-    let borrow_limit = querier::query_borrow_limit(deps, &config, &contract_addr, block_time)?;
-    let already_borrowed = querier::query_loan_amount(deps, &config, &contract_addr, block_height)?;
-    let borrow_amount = borrow_limit.mul(Decimal256::percent(BORROW_LTV_PERCENTAGE)) - already_borrowed;
-    Ok(PossibleBorrowResponse {
-        borrow_limit,
-        already_borrowed,
-        borrow_amount,
-    })
+fn withdraw<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    collateral: String,
+    unbond: bool,
+    owner: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let owner = acting_owner(deps, &env, owner, OperatorScope::Deleverage)?;
+    let position = get_position(
+        &deps.storage,
+        &collateral,
+        &deps.api.canonical_address(&owner)?,
+    )?;
+    if position.collateral_shares.is_zero() {
+        return Err(StdError::generic_err("No position to withdraw"));
+    }
+    if unbond {
+        hub_contract(
+            deps,
+            &collateral,
+            &get_collateral(&deps.storage, &collateral)?,
+        )?;
+    }
+    let mut state = get_state(&deps.storage)?;
+    state.loop_in_progress = true;
+    set_state(&mut deps.storage, &state)?;
+
+    deleverage_msgs(deps, &env, collateral, owner, 0, unbond)
 }
 
-pub fn query_collateral<S: Storage, A: Api, Q: Querier>(
-    deps: &Extern<S, A, Q>,
-    contract_addr: &HumanAddr,
-) -> StdResult<moneymarket::custody::BorrowerResponse> {
-    let config = get_config(&deps.storage)?;
-    querier::query_collateral(deps, &config, &contract_addr)
+fn deleverage<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    collateral: String,
+    owner: HumanAddr,
+    iteration: u32,
+    unbond: bool,
+) -> StdResult<HandleResponse> {
+    if env.message.sender != env.contract.address {
+        return Err(StdError::unauthorized());
+    }
+
+    deleverage_msgs(deps, &env, collateral, owner, iteration, unbond)
 }
 
-#[cfg(test)]
-mod tests {
-    // TODO: Add test cases
+/// One unwinding step of the owner's position: while it has debt, unlock as much
+/// collateral as the borrow limit allows and sell it to repay, after repaying
+/// with whatever uusd the withdraw came with and the parked aUST; once the debt is
+/// gone, release the rest minus the management fee and sell it to the owner,
+/// or with `unbond` unbond it at the hub for the owner to claim later
+fn deleverage_msgs<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    collateral: String,
+    owner: HumanAddr,
+    iteration: u32,
+    unbond: bool,
+) -> StdResult<HandleResponse> {
+    let config = get_config(&deps.storage)?;
+    let registered = get_collateral(&deps.storage, &collateral)?;
+    let mut state = get_state(&deps.storage)?;
+    let mut collateral_state = get_collateral_state(&deps.storage, &collateral)?;
+    let owner_raw = deps.api.canonical_address(&owner)?;
+    let mut position = get_position(&deps.storage, &collateral, &owner_raw)?;
+
+    let balance = from_shares(
+        position.collateral_shares,
+        collateral_state.total_collateral_shares,
+        collateral_state.total_collateral,
+    );
+    let loan =
+        querier::query_loan_amount(deps, &config, &env.contract.address, Some(env.block.height))?;
+    let debt = from_shares(position.debt_shares, state.total_debt_shares, loan);
+
+    // uusd sent along and parked aUST are unwound first, repay settles the debt
+    // with what they come to, which also works where no collateral can be unlocked
+    let sent = env
+        .message
+        .sent_funds
+        .iter()
+        .find(|coin| coin.denom == TERRASWAP_PAIR)
+        .map(|coin| coin.amount)
+        .unwrap_or_else(Uint128::zero);
+    if !position.aust.is_zero() || !sent.is_zero() {
+        let aust = position.aust;
+        let mut messages = vec![];
+        if !aust.is_zero() {
+            position.aust = Uint256::zero();
+            state.total_aust = state.total_aust - aust;
+            set_position(&mut deps.storage, &collateral, &owner_raw, &position)?;
+            set_state(&mut deps.storage, &state)?;
+
+            let anchor_market_contract = deps.api.human_address(&config.anchor_market_contract)?;
+            messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: querier::query_market_config(deps, &config)?.aterra_contract,
+                send: vec![],
+                msg: querier::redeem_aust(&anchor_market_contract, aust.into())?,
+            }));
+        }
+
+        let mut logs = vec![
+            log(
+                "action",
+                if aust.is_zero() {
+                    "repay_sent"
+                } else {
+                    "redeem_earn"
+                },
+            ),
+            log("aust", aust),
+            log("sent", sent),
+            log("debt", debt),
+        ];
+        logs.extend(step_log(
+            deps,
+            &collateral,
+            &owner,
+            iteration,
+            loan,
+            Queried::default(),
+        )?);
+        let balance_before = (deps
+            .querier
+            .query_balance(env.contract.address.clone(), TERRASWAP_PAIR)?
+            .amount
+            - sent)?;
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: env.contract.address.clone(),
+            send: vec![],
+            msg: to_binary(&HandleMsg::Repay {
+                collateral,
+                owner,
+                iteration,
+                unbond,
+                balance_before,
+            })?,
+        }));
+        return Ok(HandleResponse {
+            messages,
+            log: logs,
+            data: None,
+        });
+    }
+
+    let basset_token = deps.api.human_address(&registered.token)?;
+    let exit_pair = deps.api.human_address(&registered.exit_pair)?;
+    let basset_asset = AssetInfo::Token {
+        contract_addr: basset_token.clone(),
+    };
+
+    if debt.is_zero() {
+        let fee = balance * config.management_fee;
+        collateral_state.total_collateral = collateral_state.total_collateral - balance;
+        collateral_state.total_collateral_shares =
+            collateral_state.total_collateral_shares - position.collateral_shares;
+        collateral_state.fee_basset += fee;
+        state.total_debt_shares = state.total_debt_shares - position.debt_shares;
+        state.total_deposit = state.total_deposit - position.cost_basis_uusd;
+        state.loop_in_progress = false;
+        set_collateral_state(&mut deps.storage, &collateral, &collateral_state)?;
+        set_state(&mut deps.storage, &state)?;
+        remove_position(&mut deps.storage, &collateral, &owner_raw)?;
+
+        let mut realized_pnl = get_realized_pnl(&deps.storage, &collateral, &owner_raw)?;
+        realized_pnl.cost += position.cost_basis;
+        realized_pnl.cost_uusd += position.cost_basis_uusd;
+
+        let mut messages = vec![];
+        if !balance.is_zero() && unbond {
+            let hub_contract = hub_contract(deps, &collateral, &registered)?;
+            let unbonding = balance - fee;
+            let expected = unbonding * query_mint_rate(deps, &registered)?;
+            realized_pnl.proceeds += expected;
+            realized_pnl.proceeds_uusd +=
+                expected * query_deposit_price(deps, &config, &registered)?;
+
+            // the hub adds the request to its current batch before it may close it
+            let mut requests = get_unbond_requests(&deps.storage, &collateral, &owner_raw)?;
+            requests.push(UnbondRequest {
+                batch_id: hub_querier::query_current_batch(deps, &hub_contract)?.id,
+                amount: unbonding,
+            });
+            set_unbond_requests(&mut deps.storage, &collateral, &owner_raw, &requests)?;
+
+            messages.extend(release_collateral_msgs(
+                deps,
+                &config,
+                &registered,
+                balance,
+            )?);
+            messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: basset_token.clone(),
+                send: vec![],
+                msg: querier::unbond_basset(&hub_contract, unbonding.into())?,
+            }));
+        } else if !balance.is_zero() {
+            let sold = Uint256::from(querier::simulate_basset_sale(
+                deps,
+                &exit_pair,
+                &basset_token,
+                (balance - fee).into(),
+            )?);
+            realized_pnl.proceeds += sold;
+            realized_pnl.proceeds_uusd += sold * query_deposit_price(deps, &config, &registered)?;
+
+            messages.extend(release_collateral_msgs(
+                deps,
+                &config,
+                &registered,
+                balance,
+            )?);
+            messages.push(
+                swap_msg(
+                    deps,
+                    &basset_asset,
+                    &exit_pair,
+                    (balance - fee).into(),
+                    Some(owner.clone()),
+                )?
+                .0,
+            );
+        }
+        set_realized_pnl(&mut deps.storage, &collateral, &owner_raw, &realized_pnl)?;
+        messages.extend(hook_msgs(
+            deps,
+            &owner,
+            &collateral,
+            PositionChange::Withdraw,
+        )?);
+        // hooks are only kept for owners of a position, like AddHook requires
+        if !has_position(deps, &owner_raw)? {
+            set_hooks(&mut deps.storage, &owner_raw, &[])?;
+        }
+
+        let mut logs = vec![
+            log("action", if unbond { "unbond" } else { "withdraw" }),
+            log("balance", balance),
+            log("management_fee", fee),
+        ];
+        logs.extend(step_log(
+            deps,
+            &collateral,
+            &owner,
+            iteration,
+            loan,
+            Queried::default(),
+        )?);
+        return Ok(HandleResponse {
+            messages,
+            log: logs,
+            data: None,
+        });
+    }
+
+    let borrow_limit =
+        querier::query_borrow_limit(deps, &config, &env.contract.address, Some(env.block.time))?;
+    let price = querier::query_basset_price(deps, &config, &basset_token)?;
+    // every unit of bAsset unlocked lowers the borrow limit by its price times max ltv
+    let unlockable = if borrow_limit > loan {
+        let max_ltv = querier::query_max_ltv(deps, &config, &basset_token)?;
+        (borrow_limit - loan) / (price * max_ltv)
+            * Decimal256::percent(DELEVERAGE_SAFETY_PERCENTAGE)
+    } else {
+        Uint256::zero()
+    };
+    let amount = min(balance, unlockable);
+    if amount.is_zero() {
+        return Err(StdError::generic_err(
+            "Position cannot be deleveraged at the current LTV, send uusd to repay first",
+        ));
+    }
+
+    let shares = to_shares(
+        amount,
+        collateral_state.total_collateral_shares,
+        collateral_state.total_collateral,
+    )?;
+    position.collateral_shares = position.collateral_shares - shares;
+    collateral_state.total_collateral_shares = collateral_state.total_collateral_shares - shares;
+    collateral_state.total_collateral = collateral_state.total_collateral - amount;
+    set_position(&mut deps.storage, &collateral, &owner_raw, &position)?;
+    set_collateral_state(&mut deps.storage, &collateral, &collateral_state)?;
+
+    let mut logs = vec![
+        log("action", "deleverage"),
+        log("unlocked", amount),
+        log("debt", debt),
+    ];
+    logs.extend(step_log(
+        deps,
+        &collateral,
+        &owner,
+        iteration,
+        loan,
+        Queried {
+            basset_price: Some(price),
+            ..Queried::default()
+        },
+    )?);
+
+    let balance_before = registered
+        .deposit_asset
+        .to_normal(deps)?
+        .query_pool(deps, &env.contract.address)?;
+    let mut messages = release_collateral_msgs(deps, &config, &registered, amount)?;
+    messages.push(swap_msg(deps, &basset_asset, &exit_pair, amount.into(), None)?.0);
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address.clone(),
+        send: vec![],
+        msg: to_binary(&HandleMsg::SwapToStable {
+            collateral,
+            owner,
+            iteration,
+            unbond,
+            balance_before,
+        })?,
+    }));
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+fn swap_to_stable<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    collateral: String,
+    owner: HumanAddr,
+    iteration: u32,
+    unbond: bool,
+    balance_before: Uint128,
+) -> StdResult<HandleResponse> {
+    if env.message.sender != env.contract.address {
+        return Err(StdError::unauthorized());
+    }
+
+    let config = get_config(&deps.storage)?;
+    let registered = get_collateral(&deps.storage, &collateral)?;
+    let deposit_asset = registered.deposit_asset.to_normal(deps)?;
+    let received = (deposit_asset.query_pool(deps, &env.contract.address)? - balance_before)?;
+    let (swap, returned) = route_swap_msg(deps, &registered, &deposit_asset, received, None)?;
+    let stable_before = deps
+        .querier
+        .query_balance(env.contract.address.clone(), TERRASWAP_PAIR)?
+        .amount;
+
+    let loan =
+        querier::query_loan_amount(deps, &config, &env.contract.address, Some(env.block.height))?;
+    let mut logs = vec![
+        log("action", "swap_to_stable"),
+        log("swap_amount", received),
+        log("swap_price", swap_price(returned, received)),
+    ];
+    logs.extend(step_log(
+        deps,
+        &collateral,
+        &owner,
+        iteration,
+        loan,
+        Queried::default(),
+    )?);
+
+    Ok(HandleResponse {
+        messages: vec![
+            swap,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: env.contract.address,
+                send: vec![],
+                msg: to_binary(&HandleMsg::Repay {
+                    collateral,
+                    owner,
+                    iteration,
+                    unbond,
+                    balance_before: stable_before,
+                })?,
+            }),
+        ],
+        log: logs,
+        data: None,
+    })
+}
+
+/// Repays the owner's share of the loan with the stable the sale returned,
+/// whatever exceeds the debt is swapped back to the deposit asset for the owner
+fn repay<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    collateral: String,
+    owner: HumanAddr,
+    iteration: u32,
+    unbond: bool,
+    balance_before: Uint128,
+) -> StdResult<HandleResponse> {
+    if env.message.sender != env.contract.address {
+        return Err(StdError::unauthorized());
+    }
+
+    let config = get_config(&deps.storage)?;
+    let registered = get_collateral(&deps.storage, &collateral)?;
+    let mut state = get_state(&deps.storage)?;
+    let owner_raw = deps.api.canonical_address(&owner)?;
+    let mut position = get_position(&deps.storage, &collateral, &owner_raw)?;
+
+    let balance = deps
+        .querier
+        .query_balance(env.contract.address.clone(), TERRASWAP_PAIR)?
+        .amount;
+    let available = Uint256::from((balance - balance_before)?);
+    let available_after_tax = querier::deduct_tax(deps, available)?;
+
+    let loan =
+        querier::query_loan_amount(deps, &config, &env.contract.address, Some(env.block.height))?;
+    let debt = from_shares(position.debt_shares, state.total_debt_shares, loan);
+    let repay_amount = min(available_after_tax, debt);
+    let burned = if repay_amount == debt {
+        position.debt_shares
+    } else {
+        position.debt_shares.multiply_ratio(repay_amount.0, debt.0)
+    };
+    position.debt_shares = position.debt_shares - burned;
+    state.total_debt_shares = state.total_debt_shares - burned;
+    set_position(&mut deps.storage, &collateral, &owner_raw, &position)?;
+    set_state(&mut deps.storage, &state)?;
+
+    let mut messages = vec![];
+    if !repay_amount.is_zero() {
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: deps.api.human_address(&config.anchor_market_contract)?,
+            send: vec![coin(repay_amount.into(), TERRASWAP_PAIR)],
+            msg: querier::anchor_repay()?,
+        }));
+    }
+
+    let repaid_with_tax = repay_amount + querier::compute_tax(deps, repay_amount)?;
+    if available > repaid_with_tax {
+        let surplus = querier::deduct_tax(deps, available - repaid_with_tax)?;
+        if !surplus.is_zero() {
+            let mut realized_pnl = get_realized_pnl(&deps.storage, &collateral, &owner_raw)?;
+            realized_pnl.proceeds_uusd += surplus;
+            realized_pnl.proceeds += surplus / query_deposit_price(deps, &config, &registered)?;
+            set_realized_pnl(&mut deps.storage, &collateral, &owner_raw, &realized_pnl)?;
+
+            messages.push(
+                route_swap_msg(
+                    deps,
+                    &registered,
+                    &stable_asset(),
+                    surplus.into(),
+                    Some(owner.clone()),
+                )?
+                .0,
+            );
+        }
+    }
+
+    let mut logs = vec![log("action", "repay"), log("repay_amount", repay_amount)];
+    logs.extend(step_log(
+        deps,
+        &collateral,
+        &owner,
+        iteration,
+        loan - repay_amount,
+        Queried::default(),
+    )?);
+
+    if !repay_amount.is_zero() {
+        messages.extend(hook_msgs(
+            deps,
+            &owner,
+            &collateral,
+            PositionChange::Deleverage,
+        )?);
+    }
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address,
+        send: vec![],
+        msg: to_binary(&HandleMsg::Deleverage {
+            collateral,
+            owner,
+            iteration: iteration + 1,
+            unbond,
+        })?,
+    }));
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+fn claim_unbonded<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    collateral: String,
+    owner: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let owner = acting_owner(deps, &env, owner, OperatorScope::Deleverage)?;
+    let registered = get_collateral(&deps.storage, &collateral)?;
+    let hub_contract = hub_contract(deps, &collateral, &registered)?;
+    let owner_raw = deps.api.canonical_address(&owner)?;
+    if get_unbond_requests(&deps.storage, &collateral, &owner_raw)?.is_empty() {
+        return Err(StdError::generic_err("No unbond requests to claim"));
+    }
+
+    // the hub refuses a withdrawal with nothing released, which happens when
+    // another owner's claim already withdrew the batch
+    let mut messages = vec![];
+    if !hub_querier::query_withdrawable_unbonded(
+        deps,
+        &hub_contract,
+        &env.contract.address,
+        env.block.time,
+    )?
+    .withdrawable
+    .is_zero()
+    {
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: hub_contract,
+            send: vec![],
+            msg: querier::withdraw_unbonded()?,
+        }));
+    }
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address.clone(),
+        send: vec![],
+        msg: to_binary(&HandleMsg::ForwardUnbonded {
+            collateral: collateral.clone(),
+            owner: owner.clone(),
+            balance_before: registered
+                .deposit_asset
+                .to_normal(deps)?
+                .query_pool(deps, &env.contract.address)?,
+        })?,
+    }));
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "claim_unbonded"),
+            log("collateral", collateral),
+            log("owner", owner),
+        ],
+        data: None,
+    })
+}
+
+/// Pays the owner's requests in released batches at the batch withdraw rate,
+/// the rest stays pending for a later claim
+fn forward_unbonded<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    collateral: String,
+    owner: HumanAddr,
+    balance_before: Uint128,
+) -> StdResult<HandleResponse> {
+    if env.message.sender != env.contract.address {
+        return Err(StdError::unauthorized());
+    }
+
+    let registered = get_collateral(&deps.storage, &collateral)?;
+    let hub_contract = hub_contract(deps, &collateral, &registered)?;
+    let deposit_asset = registered.deposit_asset.to_normal(deps)?;
+    let mut collateral_state = get_collateral_state(&deps.storage, &collateral)?;
+    collateral_state.unbonded +=
+        Uint256::from((deposit_asset.query_pool(deps, &env.contract.address)? - balance_before)?);
+
+    let owner_raw = deps.api.canonical_address(&owner)?;
+    let mut claimable = Uint256::zero();
+    let mut pending = vec![];
+    for request in get_unbond_requests(&deps.storage, &collateral, &owner_raw)? {
+        let released = hub_querier::query_all_history(
+            deps,
+            &hub_contract,
+            request.batch_id.checked_sub(1),
+            Some(1),
+        )?
+        .history
+        .into_iter()
+        .find(|history| history.batch_id == request.batch_id && history.released);
+        match released {
+            Some(history) => claimable += request.amount * Decimal256::from(history.withdraw_rate),
+            None => pending.push(request),
+        }
+    }
+    if claimable.is_zero() {
+        return Err(StdError::generic_err("No unbonding is released yet"));
+    }
+
+    // the hub rounds every request on its own, never pay out more than it sent
+    let claimed = min(claimable, collateral_state.unbonded);
+    collateral_state.unbonded = collateral_state.unbonded - claimed;
+    set_collateral_state(&mut deps.storage, &collateral, &collateral_state)?;
+    set_unbond_requests(&mut deps.storage, &collateral, &owner_raw, &pending)?;
+
+    let denom = match deposit_asset {
+        AssetInfo::NativeToken { denom } => denom,
+        AssetInfo::Token { .. } => {
+            return Err(StdError::generic_err("Hub deposit asset must be native"))
+        }
+    };
+
+    Ok(HandleResponse {
+        messages: vec![CosmosMsg::Bank(BankMsg::Send {
+            from_address: env.contract.address,
+            to_address: owner.clone(),
+            amount: vec![coin(claimed.into(), &denom)],
+        })],
+        log: vec![
+            log("action", "forward_unbonded"),
+            log("collateral", collateral),
+            log("owner", owner),
+            log("claimed", format!("{}{}", claimed, denom)),
+            log("pending", pending.len()),
+        ],
+        data: None,
+    })
+}
+
+fn harvest<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let config = get_config(&deps.storage)?;
+    let state = get_state(&deps.storage)?;
+    if state.total_debt_shares.is_zero() {
+        return Err(StdError::generic_err("No position to harvest for"));
+    }
+    let balance_before = deps
+        .querier
+        .query_balance(env.contract.address.clone(), TERRASWAP_PAIR)?
+        .amount;
+
+    Ok(HandleResponse {
+        messages: vec![
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: deps.api.human_address(&config.anchor_market_contract)?,
+                send: vec![],
+                msg: querier::claim_anc_rewards()?,
+            }),
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: env.contract.address,
+                send: vec![],
+                msg: to_binary(&HandleMsg::SellRewards { balance_before })?,
+            }),
+        ],
+        log: vec![log("action", "harvest")],
+        data: None,
+    })
+}
+
+fn sell_rewards<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    balance_before: Uint128,
+) -> StdResult<HandleResponse> {
+    if env.message.sender != env.contract.address {
+        return Err(StdError::unauthorized());
+    }
+
+    let config = get_config(&deps.storage)?;
+    let anc_token = deps.api.human_address(&config.anc_token)?;
+    let claimed = terraswap::querier::query_token_balance(deps, &anc_token, &env.contract.address)?;
+
+    let mut messages = vec![];
+    if !claimed.is_zero() {
+        messages.push(
+            swap_msg(
+                deps,
+                &AssetInfo::Token {
+                    contract_addr: anc_token,
+                },
+                &deps.api.human_address(&config.terraswap_anc_ust)?,
+                claimed,
+                None,
+            )?
+            .0,
+        );
+    }
+    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address,
+        send: vec![],
+        msg: to_binary(&HandleMsg::CompoundRewards { balance_before })?,
+    }));
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![log("action", "sell_rewards"), log("claimed", claimed)],
+        data: None,
+    })
+}
+
+/// Takes the performance fee from sold rewards and repays the pooled loan
+/// with the rest, which lowers the debt behind every debt share alike
+fn compound_rewards<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    balance_before: Uint128,
+) -> StdResult<HandleResponse> {
+    if env.message.sender != env.contract.address {
+        return Err(StdError::unauthorized());
+    }
+
+    let config = get_config(&deps.storage)?;
+    let mut state = get_state(&deps.storage)?;
+    // fees and stable parked by other handlers sit in both balances alike
+    let balance = deps
+        .querier
+        .query_balance(env.contract.address.clone(), TERRASWAP_PAIR)?
+        .amount;
+    let rewards = Uint256::from((balance - balance_before)?);
+    let fee = rewards * config.performance_fee;
+    state.fee_stable += fee;
+    set_state(&mut deps.storage, &state)?;
+
+    let loan =
+        querier::query_loan_amount(deps, &config, &env.contract.address, Some(env.block.height))?;
+    let repay_amount = min(querier::deduct_tax(deps, rewards - fee)?, loan);
+    let mut messages = vec![];
+    if !repay_amount.is_zero() {
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: deps.api.human_address(&config.anchor_market_contract)?,
+            send: vec![coin(repay_amount.into(), TERRASWAP_PAIR)],
+            msg: querier::anchor_repay()?,
+        }));
+    }
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "compound_rewards"),
+            log("rewards", rewards),
+            log("performance_fee", fee),
+            log("repay_amount", repay_amount),
+        ],
+        data: None,
+    })
+}
+
+fn claim_fees<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let config = get_config(&deps.storage)?;
+    let sender_raw = deps.api.canonical_address(&env.message.sender)?;
+    if sender_raw != config.owner && sender_raw != config.fee_collector {
+        return Err(StdError::unauthorized());
+    }
+
+    let mut state = get_state(&deps.storage)?;
+    let fee_collector = deps.api.human_address(&config.fee_collector)?;
+
+    let mut messages = vec![];
+    let mut logs = vec![log("action", "claim_fees")];
+    for (collateral, registered) in get_collaterals(&deps.storage)? {
+        let mut collateral_state = get_collateral_state(&deps.storage, &collateral)?;
+        if collateral_state.fee_basset.is_zero() {
+            continue;
+        }
+
+        messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: deps.api.human_address(&registered.token)?,
+            send: vec![],
+            msg: querier::transfer_basset(&fee_collector, collateral_state.fee_basset.into())?,
+        }));
+        logs.push(log(
+            "fee_basset",
+            format!("{} {}", collateral_state.fee_basset, collateral),
+        ));
+        collateral_state.fee_basset = Uint256::zero();
+        set_collateral_state(&mut deps.storage, &collateral, &collateral_state)?;
+    }
+    if !state.fee_stable.is_zero() {
+        messages.push(CosmosMsg::Bank(BankMsg::Send {
+            from_address: env.contract.address,
+            to_address: fee_collector,
+            amount: vec![coin(
+                querier::deduct_tax(deps, state.fee_stable)?.into(),
+                TERRASWAP_PAIR,
+            )],
+        }));
+    }
+
+    logs.push(log("fee_stable", state.fee_stable));
+    state.fee_stable = Uint256::zero();
+    set_state(&mut deps.storage, &state)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: logs,
+        data: None,
+    })
+}
+
+/// Compares the pooled collateral with what custody still holds; whatever a
+/// liquidation seized is taken off the pool so every collateral share loses
+/// pro-rata, while the loan it repaid already shrinks every debt share alike.
+/// A pool seized or repaid in full voids its shares, so the next deposit or
+/// loan does not mint into a pool its old shares still claim part of
+fn reconcile<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+) -> StdResult<Vec<LogAttribute>> {
+    let config = get_config(&deps.storage)?;
+    let mut state = get_state(&deps.storage)?;
+
+    let mut logs = vec![];
+    for (collateral, registered) in get_collaterals(&deps.storage)? {
+        let mut collateral_state = get_collateral_state(&deps.storage, &collateral)?;
+        if collateral_state.total_collateral.is_zero() {
+            continue;
+        }
+
+        let held = querier::query_collateral(
+            deps,
+            &deps.api.human_address(&registered.custody_contract)?,
+            &env.contract.address,
+        )?
+        .balance;
+        if held >= collateral_state.total_collateral {
+            continue;
+        }
+
+        let seized = collateral_state.total_collateral - held;
+        collateral_state.total_collateral = held;
+        if held.is_zero() {
+            collateral_state.total_collateral_shares = Uint256::zero();
+            collateral_state.wipes += 1;
+            logs.push(log("wiped_collateral", &collateral));
+        }
+        set_collateral_state(&mut deps.storage, &collateral, &collateral_state)?;
+
+        let loan = querier::query_loan_amount(
+            deps,
+            &config,
+            &env.contract.address,
+            Some(env.block.height),
+        )?;
+        push_liquidation(
+            &mut deps.storage,
+            state.liquidations,
+            &Liquidation {
+                collateral: collateral.clone(),
+                block_height: env.block.height,
+                seized,
+                loan,
+            },
+        )?;
+        state.liquidations += 1;
+        logs.push(log("liquidation", format!("{} {}", seized, collateral)));
+    }
+    if !state.total_debt_shares.is_zero()
+        && querier::query_loan_amount(deps, &config, &env.contract.address, Some(env.block.height))?
+            .is_zero()
+    {
+        state.total_debt_shares = Uint256::zero();
+        state.debt_wipes += 1;
+        logs.push(log("wiped_debt", state.debt_wipes));
+    }
+    if !logs.is_empty() {
+        set_state(&mut deps.storage, &state)?;
+    }
+
+    Ok(logs)
+}
+
+/// Sends the hooks of the depositors of each collateral liquidated since the
+/// last call their callback. Kept out of the reconcile every handler runs, so
+/// a failing hook only holds up the notifications and not the contract
+fn notify_liquidations<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+) -> StdResult<HandleResponse> {
+    // a page of liquidations is notified to a page of owners with hooks per
+    // call, the next call picks up the owners after the last one notified
+    let mut state = get_state(&deps.storage)?;
+    let mut pending = 0;
+    if state.notifying.is_empty() {
+        let liquidations = read_liquidations(
+            &deps.storage,
+            state.notified_liquidations.checked_sub(1),
+            Some(LIQUIDATIONS_PER_CALL),
+        )?;
+        for (id, liquidation) in liquidations.iter() {
+            state.notified_liquidations = id + 1;
+            if !state.notifying.contains(&liquidation.collateral) {
+                state.notifying.push(liquidation.collateral.clone());
+            }
+        }
+        pending = liquidations.len();
+    }
+
+    let mut messages = vec![];
+    let mut owners = vec![];
+    if !state.notifying.is_empty() {
+        owners = read_hooks(
+            &deps.storage,
+            state.notified_hooks_owner.as_ref(),
+            Some(HOOK_OWNERS_PER_CALL),
+        )?;
+        for (owner_raw, hooks) in owners.iter() {
+            let owner = deps.api.human_address(owner_raw)?;
+            for collateral in state.notifying.iter() {
+                if get_position(&deps.storage, collateral, owner_raw)?
+                    .collateral_shares
+                    .is_zero()
+                {
+                    continue;
+                }
+                let msg = to_binary(&PositionHookMsg::PositionChanged {
+                    collateral: collateral.clone(),
+                    owner: owner.clone(),
+                    change: PositionChange::Liquidation,
+                })?;
+                for hook in hooks.iter() {
+                    messages.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                        contract_addr: deps.api.human_address(hook)?,
+                        send: vec![],
+                        msg: msg.clone(),
+                    }));
+                }
+            }
+        }
+        if owners.len() < HOOK_OWNERS_PER_CALL as usize {
+            state.notifying = vec![];
+            state.notified_hooks_owner = None;
+        } else {
+            state.notified_hooks_owner = owners.last().map(|(owner, _)| owner.clone());
+        }
+    }
+    set_state(&mut deps.storage, &state)?;
+
+    Ok(HandleResponse {
+        messages,
+        log: vec![
+            log("action", "reconcile"),
+            log("notified", pending),
+            log("hook_owners", owners.len()),
+        ],
+        data: None,
+    })
+}
+
+fn add_hook<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    contract: HumanAddr,
+    owner: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let owner = acting_owner(deps, &env, owner, OperatorScope::SetTriggers)?;
+    let owner_raw = deps.api.canonical_address(&owner)?;
+    // only depositors get notified, which keeps the owners to page through few
+    if !has_position(deps, &owner_raw)? {
+        return Err(StdError::generic_err(
+            "Only owners of a position can add hooks",
+        ));
+    }
+    let contract_raw = deps.api.canonical_address(&contract)?;
+    let mut hooks = get_hooks(&deps.storage, &owner_raw)?;
+    if hooks.contains(&contract_raw) {
+        return Err(StdError::generic_err(format!(
+            "{} is already a hook",
+            contract
+        )));
+    }
+    if hooks.len() >= MAX_HOOKS {
+        return Err(StdError::generic_err(format!(
+            "At most {} hooks per owner",
+            MAX_HOOKS
+        )));
+    }
+    hooks.push(contract_raw);
+    set_hooks(&mut deps.storage, &owner_raw, &hooks)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "add_hook"), log("contract", contract)],
+        data: None,
+    })
+}
+
+fn remove_hook<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    contract: HumanAddr,
+    owner: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let owner = acting_owner(deps, &env, owner, OperatorScope::SetTriggers)?;
+    let owner_raw = deps.api.canonical_address(&owner)?;
+    let contract_raw = deps.api.canonical_address(&contract)?;
+    let mut hooks = get_hooks(&deps.storage, &owner_raw)?;
+    if !hooks.contains(&contract_raw) {
+        return Err(StdError::generic_err(format!("{} is not a hook", contract)));
+    }
+    hooks.retain(|hook| hook != &contract_raw);
+    set_hooks(&mut deps.storage, &owner_raw, &hooks)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "remove_hook"), log("contract", contract)],
+        data: None,
+    })
+}
+
+fn set_earn_share<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    collateral: String,
+    earn_share: Decimal256,
+    owner: Option<HumanAddr>,
+) -> StdResult<HandleResponse> {
+    let owner = acting_owner(deps, &env, owner, OperatorScope::Rebalance)?;
+    let owner_raw = deps.api.canonical_address(&owner)?;
+    let mut position = get_position(&deps.storage, &collateral, &owner_raw)?;
+    if position.collateral_shares.is_zero() {
+        return Err(StdError::generic_err("No position to rebalance"));
+    }
+    if earn_share >= Decimal256::one() {
+        return Err(StdError::generic_err("Earn share must be lower than 1"));
+    }
+    position.earn_share = earn_share;
+    set_position(&mut deps.storage, &collateral, &owner_raw, &position)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "set_earn_share"),
+            log("owner", owner),
+            log("earn_share", earn_share),
+        ],
+        data: None,
+    })
+}
+
+fn grant_operator<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    operator: HumanAddr,
+    scopes: Vec<OperatorScope>,
+    expires: Option<u64>,
+) -> StdResult<HandleResponse> {
+    if operator == env.message.sender {
+        return Err(StdError::generic_err("An owner cannot be its own operator"));
+    }
+    if scopes.is_empty() {
+        return Err(StdError::generic_err(
+            "An operator needs at least one scope",
+        ));
+    }
+    if expires.map_or(false, |expires| expires <= env.block.time) {
+        return Err(StdError::generic_err("Expiry must be in the future"));
+    }
+    set_operator(
+        &mut deps.storage,
+        &deps.api.canonical_address(&env.message.sender)?,
+        &deps.api.canonical_address(&operator)?,
+        &Operator { scopes, expires },
+    )?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "grant_operator"), log("operator", operator)],
+        data: None,
+    })
+}
+
+fn revoke_operator<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    operator: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let owner_raw = deps.api.canonical_address(&env.message.sender)?;
+    let operator_raw = deps.api.canonical_address(&operator)?;
+    if may_get_operator(&deps.storage, &owner_raw, &operator_raw)?.is_none() {
+        return Err(StdError::generic_err(format!(
+            "{} is not an operator",
+            operator
+        )));
+    }
+    remove_operator(&mut deps.storage, &owner_raw, &operator_raw);
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "revoke_operator"), log("operator", operator)],
+        data: None,
+    })
+}
+
+fn update_config<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    owner: Option<HumanAddr>,
+    max_total_deposit: Option<Uint256>,
+    max_user_deposit: Option<Uint256>,
+    min_deposit: Option<Uint256>,
+    max_slippage: Option<Decimal256>,
+) -> StdResult<HandleResponse> {
+    let mut config = get_config(&deps.storage)?;
+    if deps.api.canonical_address(&env.message.sender)? != config.owner {
+        return Err(StdError::unauthorized());
+    }
+
+    if let Some(owner) = owner {
+        config.owner = deps.api.canonical_address(&owner)?;
+    }
+    if let Some(max_total_deposit) = max_total_deposit {
+        config.max_total_deposit = max_total_deposit;
+    }
+    if let Some(max_user_deposit) = max_user_deposit {
+        config.max_user_deposit = max_user_deposit;
+    }
+    if let Some(min_deposit) = min_deposit {
+        config.min_deposit = min_deposit;
+    }
+    validate_deposit_limits(&config)?;
+    if let Some(max_slippage) = max_slippage {
+        validate_slippage(max_slippage)?;
+        config.max_slippage = max_slippage;
+    }
+    set_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "update_config")],
+        data: None,
+    })
+}
+
+/// Changes the fee settings, which are kept from the owner so that the fees
+/// the protocol runs an instance for cannot be turned off by its client
+fn update_fees<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    fee_admin: Option<HumanAddr>,
+    fee_collector: Option<HumanAddr>,
+    performance_fee: Option<Decimal256>,
+    management_fee: Option<Decimal256>,
+) -> StdResult<HandleResponse> {
+    let mut config = get_config(&deps.storage)?;
+    if deps.api.canonical_address(&env.message.sender)? != config.fee_admin {
+        return Err(StdError::unauthorized());
+    }
+
+    if let Some(fee_admin) = fee_admin {
+        config.fee_admin = deps.api.canonical_address(&fee_admin)?;
+    }
+    if let Some(fee_collector) = fee_collector {
+        config.fee_collector = deps.api.canonical_address(&fee_collector)?;
+    }
+    if let Some(performance_fee) = performance_fee {
+        validate_fee(performance_fee)?;
+        config.performance_fee = performance_fee;
+    }
+    if let Some(management_fee) = management_fee {
+        validate_fee(management_fee)?;
+        config.management_fee = management_fee;
+    }
+    set_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![log("action", "update_fees")],
+        data: None,
+    })
+}
+
+fn register_collateral<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    collateral: CollateralMsg,
+) -> StdResult<HandleResponse> {
+    let config = get_config(&deps.storage)?;
+    if deps.api.canonical_address(&env.message.sender)? != config.owner {
+        return Err(StdError::unauthorized());
+    }
+
+    store_collateral(deps, &collateral)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "register_collateral"),
+            log("collateral", collateral.name),
+        ],
+        data: None,
+    })
+}
+
+/// Validates and stores a registry entry; an existing collateral keeps
+/// its bAsset token so the positions pooled on it stay valid
+fn store_collateral<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    collateral: &CollateralMsg,
+) -> StdResult<()> {
+    if collateral.name.is_empty() {
+        return Err(StdError::generic_err("Collateral name is required"));
+    }
+    validate_collateral_contracts(deps, collateral)?;
+
+    let token = deps.api.canonical_address(&collateral.token)?;
+    if let Some(registered) = may_get_collateral(&deps.storage, &collateral.name)? {
+        if registered.token != token {
+            return Err(StdError::generic_err(format!(
+                "Collateral '{}' is already registered with another token",
+                collateral.name
+            )));
+        }
+    }
+
+    let mint_route = match (&collateral.mint_route, &collateral.deposit_asset) {
+        (
+            MintRouteMsg::Hub {
+                hub_contract,
+                validators,
+            },
+            AssetInfo::NativeToken { .. },
+        ) => {
+            validate_validators(deps, hub_contract, validators)?;
+            set_validators(&mut deps.storage, &collateral.name, validators)?;
+            MintRoute::Hub {
+                hub_contract: deps.api.canonical_address(hub_contract)?,
+            }
+        }
+        (MintRouteMsg::Converter { contract_addr, msg }, AssetInfo::Token { .. }) => {
+            MintRoute::Converter {
+                contract_addr: deps.api.canonical_address(contract_addr)?,
+                msg: msg.clone(),
+            }
+        }
+        (MintRouteMsg::Hub { .. }, AssetInfo::Token { .. }) => {
+            return Err(StdError::generic_err(
+                "A hub can only bond a native deposit asset",
+            ))
+        }
+        (MintRouteMsg::Converter { .. }, AssetInfo::NativeToken { .. }) => {
+            return Err(StdError::generic_err(
+                "A converter can only take a cw20 deposit asset",
+            ))
+        }
+    };
+
+    let swap_route = match &collateral.swap_route {
+        SwapRouteMsg::Pair { pair_contract } => SwapRoute::Pair {
+            pair_contract: deps.api.canonical_address(pair_contract)?,
+        },
+        SwapRouteMsg::Router {
+            router_contract,
+            paths,
+        } => {
+            validate_paths(&collateral.deposit_asset, paths)?;
+            SwapRoute::Router {
+                router_contract: deps.api.canonical_address(router_contract)?,
+                paths: paths.clone(),
+            }
+        }
+    };
+
+    let registered = Collateral {
+        deposit_asset: collateral.deposit_asset.to_raw(deps)?,
+        mint_route,
+        token,
+        custody_contract: deps.api.canonical_address(&collateral.custody_contract)?,
+        swap_route,
+        exit_pair: deps.api.canonical_address(&collateral.exit_pair)?,
+        staking_apr: collateral.staking_apr,
+    };
+    set_collateral(&mut deps.storage, &collateral.name, &registered)
+}
+
+fn update_validators<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    collateral: String,
+    validators: Vec<Validator>,
+) -> StdResult<HandleResponse> {
+    let config = get_config(&deps.storage)?;
+    if deps.api.canonical_address(&env.message.sender)? != config.owner {
+        return Err(StdError::unauthorized());
+    }
+
+    let hub_contract = match get_collateral(&deps.storage, &collateral)?.mint_route {
+        MintRoute::Hub { hub_contract } => deps.api.human_address(&hub_contract)?,
+        MintRoute::Converter { .. } => {
+            return Err(StdError::generic_err(format!(
+                "Collateral '{}' is not bonded to validators",
+                collateral
+            )))
+        }
+    };
+    validate_validators(deps, &hub_contract, &validators)?;
+    set_validators(&mut deps.storage, &collateral, &validators)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![
+            log("action", "update_validators"),
+            log("collateral", collateral),
+            log("validators", validators.len()),
+        ],
+        data: None,
+    })
+}
+
+/// Probes every contract of the config with a query of its interface, so a
+/// wrong address fails here instead of in the middle of the first deposit
+fn validate_contracts<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+) -> StdResult<()> {
+    let market = deps.api.human_address(&config.anchor_market_contract)?;
+    let overseer = deps.api.human_address(&config.anchor_overseer_contract)?;
+    let oracle = deps.api.human_address(&config.anchor_oracle_contract)?;
+    let anc_token = deps.api.human_address(&config.anc_token)?;
+    let anc_pair = deps.api.human_address(&config.terraswap_anc_ust)?;
+
+    probe(
+        "Market",
+        &market,
+        querier::query_market_config(deps, config),
+    )?;
+    probe(
+        "Oracle",
+        &oracle,
+        querier::query_oracle_config(deps, config),
+    )?;
+    let overseer_config = probe(
+        "Overseer",
+        &overseer,
+        querier::query_overseer_config(deps, config),
+    )?;
+    if overseer_config.market_contract != market || overseer_config.oracle_contract != oracle {
+        return Err(StdError::generic_err(format!(
+            "Overseer {} belongs to market {} and oracle {}",
+            overseer, overseer_config.market_contract, overseer_config.oracle_contract
+        )));
+    }
+    probe(
+        "ANC token",
+        &anc_token,
+        querier::query_token_info(deps, &anc_token),
+    )?;
+    validate_pair(
+        deps,
+        &anc_pair,
+        &[
+            AssetInfo::Token {
+                contract_addr: anc_token,
+            },
+            stable_asset(),
+        ],
+    )
+}
+
+/// Probes the contracts of a collateral like validate_contracts, its custody
+/// must hold its token for the configured overseer and market
+fn validate_collateral_contracts<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    collateral: &CollateralMsg,
+) -> StdResult<()> {
+    let config = get_config(&deps.storage)?;
+    let basset_asset = AssetInfo::Token {
+        contract_addr: collateral.token.clone(),
+    };
+
+    probe(
+        "Token",
+        &collateral.token,
+        querier::query_token_info(deps, &collateral.token),
+    )?;
+    probe(
+        "Overseer",
+        &deps.api.human_address(&config.anchor_overseer_contract)?,
+        querier::query_max_ltv(deps, &config, &collateral.token),
+    )?;
+    let custody_config = probe(
+        "Custody",
+        &collateral.custody_contract,
+        querier::query_custody_config(deps, &collateral.custody_contract),
+    )?;
+    if custody_config.collateral_token != collateral.token
+        || deps
+            .api
+            .canonical_address(&custody_config.overseer_contract)?
+            != config.anchor_overseer_contract
+        || deps
+            .api
+            .canonical_address(&custody_config.market_contract)?
+            != config.anchor_market_contract
+    {
+        return Err(StdError::generic_err(format!(
+            "Custody {} holds {} for overseer {} and market {}",
+            collateral.custody_contract,
+            custody_config.collateral_token,
+            custody_config.overseer_contract,
+            custody_config.market_contract
+        )));
+    }
+
+    if let MintRouteMsg::Hub { hub_contract, .. } = &collateral.mint_route {
+        probe(
+            "Hub",
+            hub_contract,
+            hub_querier::query_state(deps, hub_contract),
+        )?;
+    }
+    match &collateral.swap_route {
+        SwapRouteMsg::Pair { pair_contract } => validate_pair(
+            deps,
+            pair_contract,
+            &[collateral.deposit_asset.clone(), stable_asset()],
+        )?,
+        SwapRouteMsg::Router {
+            router_contract, ..
+        } => {
+            probe(
+                "Router",
+                router_contract,
+                querier::query_router_config(deps, router_contract),
+            )?;
+        }
+    }
+    validate_pair(
+        deps,
+        &collateral.exit_pair,
+        &[basset_asset, collateral.deposit_asset.clone()],
+    )
+}
+
+/// Pair must answer a pool query and trade both `assets`
+fn validate_pair<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    pair: &HumanAddr,
+    assets: &[AssetInfo],
+) -> StdResult<()> {
+    let pool = probe("Pair", pair, querier::query_pool(deps, pair))?;
+    for asset in assets.iter() {
+        if !pool.assets.iter().any(|pooled| pooled.info == *asset) {
+            return Err(StdError::generic_err(format!(
+                "Pair {} does not trade {}",
+                pair, asset
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Names the contract a failed probe was sent to
+fn probe<T>(name: &str, contract: &HumanAddr, response: StdResult<T>) -> StdResult<T> {
+    response.map_err(|err| {
+        StdError::generic_err(format!(
+            "{} {} does not answer as expected: {}",
+            name, contract, err
+        ))
+    })
+}
+
+/// Rejects an empty or duplicated validator set, zero weights and
+/// validators the hub would refuse to bond to
+fn validate_validators<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    basset_hub_contract: &HumanAddr,
+    validators: &[Validator],
+) -> StdResult<()> {
+    if validators.is_empty() {
+        return Err(StdError::generic_err("At least one validator is required"));
+    }
+
+    let whitelisted = querier::query_whitelisted_validators(deps, basset_hub_contract)?;
+    for (i, validator) in validators.iter().enumerate() {
+        if validator.weight == 0 {
+            return Err(StdError::generic_err(format!(
+                "Validator '{}' has zero weight",
+                validator.address
+            )));
+        }
+        if validators[..i]
+            .iter()
+            .any(|v| v.address == validator.address)
+        {
+            return Err(StdError::generic_err(format!(
+                "Validator '{}' is listed more than once",
+                validator.address
+            )));
+        }
+        if !whitelisted.contains(&validator.address) {
+            return Err(StdError::generic_err(format!(
+                "Validator '{}' is not whitelisted by the hub",
+                validator.address
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Every router path has to lead from uusd to the deposit asset hop by hop
+fn validate_paths(deposit_asset: &AssetInfo, paths: &[Vec<SwapOperation>]) -> StdResult<()> {
+    if paths.is_empty() {
+        return Err(StdError::generic_err("At least one swap path is required"));
+    }
+
+    for path in paths.iter() {
+        let mut asset = stable_asset();
+        for operation in path.iter() {
+            if operation.offer_asset_info() != asset {
+                return Err(StdError::generic_err(format!(
+                    "Swap path offers '{}' where it holds '{}'",
+                    operation.offer_asset_info(),
+                    asset
+                )));
+            }
+            asset = operation.ask_asset_info();
+        }
+        if asset != *deposit_asset {
+            return Err(StdError::generic_err(format!(
+                "Swap path ends in '{}' instead of '{}'",
+                asset, deposit_asset
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Rejects deposits below the minimum or over the total or the owner's cap,
+/// the owner's deposits are summed over all collaterals
+fn validate_deposit<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    owner: &CanonicalAddr,
+    value: Uint256,
+) -> StdResult<()> {
+    if value < config.min_deposit {
+        return Err(StdError::generic_err(format!(
+            "Deposit must be worth at least {} uusd",
+            config.min_deposit
+        )));
+    }
+
+    let state = get_state(&deps.storage)?;
+    if state.total_deposit + value > config.max_total_deposit {
+        return Err(StdError::generic_err(format!(
+            "Deposits are capped at {} uusd in total",
+            config.max_total_deposit
+        )));
+    }
+
+    let mut deposited = Uint256::zero();
+    for (collateral, _) in get_collaterals(&deps.storage)? {
+        deposited += get_position(&deps.storage, &collateral, owner)?.cost_basis_uusd;
+    }
+    if deposited + value > config.max_user_deposit {
+        return Err(StdError::generic_err(format!(
+            "Deposits are capped at {} uusd per user",
+            config.max_user_deposit
+        )));
+    }
+
+    Ok(())
+}
+
+fn validate_fee(fee: Decimal256) -> StdResult<()> {
+    if fee >= Decimal256::one() {
+        return Err(StdError::generic_err("Fee must be lower than 1"));
+    }
+    Ok(())
+}
+
+fn validate_deposit_limits(config: &Config) -> StdResult<()> {
+    if config.min_deposit > config.max_user_deposit
+        || config.max_user_deposit > config.max_total_deposit
+    {
+        return Err(StdError::generic_err(
+            "Deposit limits must be ordered min_deposit <= max_user_deposit <= max_total_deposit",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_slippage(slippage: Decimal256) -> StdResult<()> {
+    if slippage >= Decimal256::one() {
+        return Err(StdError::generic_err("Slippage must be lower than 1"));
+    }
+    Ok(())
+}
+
+/// Unlocks `amount` of bAsset at the overseer and withdraws it from custody
+fn release_collateral_msgs<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    registered: &Collateral,
+    amount: Uint256,
+) -> StdResult<Vec<CosmosMsg>> {
+    Ok(vec![
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: deps.api.human_address(&config.anchor_overseer_contract)?,
+            send: vec![],
+            msg: querier::overseer_unlock_collateral(
+                &deps.api.human_address(&registered.token)?,
+                amount,
+            )?,
+        }),
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: deps.api.human_address(&registered.custody_contract)?,
+            send: vec![],
+            msg: querier::withdraw_basset_collateral(amount)?,
+        }),
+    ])
+}
+
+/// Swaps `amount` of `offer_asset` on `pair` and returns the simulated return
+/// with it, the pair refuses to return more than the configured slippage below
+/// the simulation. Native assets are sent along with the swap, cw20 assets are
+/// sent to the pair with a swap hook
+fn swap_msg<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    offer_asset: &AssetInfo,
+    pair: &HumanAddr,
+    amount: Uint128,
+    to: Option<HumanAddr>,
+) -> StdResult<(CosmosMsg, Uint128)> {
+    let max_spread = get_config(&deps.storage)?.max_slippage.into();
+    let (simulated, belief_price) = querier::query_swap_price(deps, pair, offer_asset, amount)?;
+    let msg = match offer_asset {
+        AssetInfo::NativeToken { denom } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: pair.clone(),
+            send: vec![coin(amount.u128(), denom)],
+            msg: querier::swap_native(denom, amount, belief_price, max_spread, to)?,
+        }),
+        AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract_addr.clone(),
+            send: vec![],
+            msg: querier::sell_token(pair, amount, belief_price, max_spread, to)?,
+        }),
+    };
+    Ok((msg, simulated))
+}
+
+/// Swaps `amount` of `offer_asset` between uusd and the deposit asset along
+/// the collateral's swap route and returns the simulated return with it, a
+/// router swap takes the path simulating the best return and insists on it,
+/// after tax and less the configured slippage
+fn route_swap_msg<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    registered: &Collateral,
+    offer_asset: &AssetInfo,
+    amount: Uint128,
+    to: Option<HumanAddr>,
+) -> StdResult<(CosmosMsg, Uint128)> {
+    let (router_contract, paths) = match &registered.swap_route {
+        SwapRoute::Pair { pair_contract } => {
+            let pair_contract = deps.api.human_address(pair_contract)?;
+            return swap_msg(deps, offer_asset, &pair_contract, amount, to);
+        }
+        SwapRoute::Router {
+            router_contract,
+            paths,
+        } => (deps.api.human_address(router_contract)?, paths),
+    };
+
+    let mut best: Option<(Vec<SwapOperation>, Uint128)> = None;
+    for path in paths.iter() {
+        let operations: Vec<SwapOperation> = if *offer_asset == stable_asset() {
+            path.clone()
+        } else {
+            path.iter()
+                .rev()
+                .map(|operation| operation.reverse())
+                .collect()
+        };
+        // a path whose pools cannot take the amount is skipped
+        if let Ok(simulated) =
+            querier::simulate_swap_operations(deps, &router_contract, amount, &operations)
+        {
+            if best
+                .as_ref()
+                .map_or(true, |(_, amount)| simulated > *amount)
+            {
+                best = Some((operations, simulated));
+            }
+        }
+    }
+    let (operations, simulated) = best.ok_or_else(|| {
+        StdError::generic_err(format!("No swap path can take {} {}", amount, offer_asset))
+    })?;
+    // the router holds what arrives against the minimum, so uusd is counted after tax
+    let received = if *offer_asset == stable_asset() {
+        Uint256::from(simulated)
+    } else {
+        querier::deduct_tax(deps, simulated.into())?
+    };
+    let minimum_receive: Uint128 =
+        (received * (Decimal256::one() - get_config(&deps.storage)?.max_slippage)).into();
+
+    let msg = match offer_asset {
+        AssetInfo::NativeToken { denom } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: router_contract,
+            send: vec![coin(amount.u128(), denom)],
+            msg: querier::execute_swap_operations(operations, minimum_receive, to)?,
+        }),
+        AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract_addr.clone(),
+            send: vec![],
+            msg: querier::sell_token_on_router(
+                &router_contract,
+                amount,
+                operations,
+                minimum_receive,
+                to,
+            )?,
+        }),
+    };
+    Ok((msg, simulated))
+}
+
+/// uusd paid or received per unit of the deposit asset in a swap
+fn swap_price(uusd: Uint128, deposit_asset: Uint128) -> Decimal256 {
+    if deposit_asset.is_zero() {
+        Decimal256::zero()
+    } else {
+        Decimal256::from_ratio(Uint256::from(uusd).0, Uint256::from(deposit_asset).0)
+    }
+}
+
+/// Oracle price and hub exchange rate a step has queried already, which
+/// step_log takes instead of querying them again
+#[derive(Default)]
+struct Queried {
+    basset_price: Option<Decimal256>,
+    exchange_rate: Option<Decimal256>,
+}
+
+/// Attributes every loop step logs so a position's history can be rebuilt
+/// from the logs alone, `loan` is the pooled loan once the step has run
+fn step_log<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    collateral: &str,
+    owner: &HumanAddr,
+    iteration: u32,
+    loan: Uint256,
+    queried: Queried,
+) -> StdResult<Vec<LogAttribute>> {
+    let config = get_config(&deps.storage)?;
+    let registered = get_collateral(&deps.storage, collateral)?;
+    let state = get_state(&deps.storage)?;
+    let collateral_state = get_collateral_state(&deps.storage, collateral)?;
+    let position = get_position(
+        &deps.storage,
+        collateral,
+        &deps.api.canonical_address(owner)?,
+    )?;
+
+    let balance = from_shares(
+        position.collateral_shares,
+        collateral_state.total_collateral_shares,
+        collateral_state.total_collateral,
+    );
+    let debt = from_shares(position.debt_shares, state.total_debt_shares, loan);
+    // an emptied position has no value to price
+    let value = if balance.is_zero() {
+        Uint256::zero()
+    } else {
+        balance
+            * match queried.basset_price {
+                Some(price) => price,
+                None => querier::query_basset_price(
+                    deps,
+                    &config,
+                    &deps.api.human_address(&registered.token)?,
+                )?,
+            }
+    };
+    let ltv = if value.is_zero() {
+        Decimal256::zero()
+    } else {
+        Decimal256::from_ratio(debt.0, value.0)
+    };
+    let exchange_rate = match queried.exchange_rate {
+        Some(exchange_rate) => exchange_rate,
+        None => query_mint_rate(deps, &registered)?,
+    };
+
+    Ok(vec![
+        log("collateral", collateral),
+        log("owner", owner),
+        log("iteration", iteration),
+        log("collateral_after", balance),
+        log("debt_after", debt),
+        log("ltv_after", ltv),
+        log("exchange_rate", exchange_rate),
+    ])
+}
+
+/// Owner a user-facing handler acts for, the sender unless it is an operator
+/// the `owner` granted `scope` that has not expired
+fn acting_owner<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    owner: Option<HumanAddr>,
+    scope: OperatorScope,
+) -> StdResult<HumanAddr> {
+    let owner = match owner {
+        Some(owner) if owner != env.message.sender => owner,
+        _ => return Ok(env.message.sender.clone()),
+    };
+    let granted = may_get_operator(
+        &deps.storage,
+        &deps.api.canonical_address(&owner)?,
+        &deps.api.canonical_address(&env.message.sender)?,
+    )?;
+    match granted {
+        Some(operator)
+            if operator.scopes.contains(&scope)
+                && operator
+                    .expires
+                    .map_or(true, |expires| env.block.time < expires) =>
+        {
+            Ok(owner)
+        }
+        _ => Err(StdError::unauthorized()),
+    }
+}
+
+/// Both or neither of a deposit's callback and its contract must be given
+fn deposit_callback(
+    callback: Option<Binary>,
+    callback_contract: Option<HumanAddr>,
+) -> StdResult<Option<(HumanAddr, Binary)>> {
+    match (callback_contract, callback) {
+        (Some(contract), Some(msg)) => Ok(Some((contract, msg))),
+        (None, None) => Ok(None),
+        _ => Err(StdError::generic_err(
+            "callback and callback_contract must be given together",
+        )),
+    }
+}
+
+/// Adds what a loop step did to the running deposit's callback, if it has one
+fn track_deposit_callback<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    collateral: &str,
+    owner_raw: &CanonicalAddr,
+    bonded: Uint256,
+    borrowed: Uint256,
+) -> StdResult<()> {
+    if let Some(mut callback) = may_get_deposit_callback(&deps.storage, collateral, owner_raw)? {
+        callback.bonded += bonded;
+        callback.borrowed += borrowed;
+        set_deposit_callback(&mut deps.storage, collateral, owner_raw, &callback)?;
+    }
+    Ok(())
+}
+
+/// Releases the loop lock from the last step of a deposit and sends its
+/// callback, if it has one
+fn finish_deposit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    collateral: &str,
+    owner: &HumanAddr,
+    iteration: u32,
+) -> StdResult<Vec<CosmosMsg>> {
+    let mut state = get_state(&deps.storage)?;
+    state.loop_in_progress = false;
+    set_state(&mut deps.storage, &state)?;
+
+    let owner_raw = deps.api.canonical_address(owner)?;
+    let callback = match may_get_deposit_callback(&deps.storage, collateral, &owner_raw)? {
+        Some(callback) => callback,
+        None => return Ok(vec![]),
+    };
+    remove_deposit_callback(&mut deps.storage, collateral, &owner_raw);
+
+    Ok(vec![CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: deps.api.human_address(&callback.contract)?,
+        send: vec![],
+        msg: to_binary(&DepositCallbackMsg::DepositCompleted {
+            collateral: collateral.to_string(),
+            owner: owner.clone(),
+            bonded: callback.bonded,
+            borrowed: callback.borrowed,
+            iterations: iteration + 1,
+            msg: callback.msg,
+        })?,
+    })])
+}
+
+/// Callbacks to the hooks of `owner` about a change of their position
+/// Whether the owner holds collateral in any collateral
+fn has_position<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    owner: &CanonicalAddr,
+) -> StdResult<bool> {
+    for (collateral, _) in get_collaterals(&deps.storage)? {
+        if !get_position(&deps.storage, &collateral, owner)?
+            .collateral_shares
+            .is_zero()
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn hook_msgs<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    owner: &HumanAddr,
+    collateral: &str,
+    change: PositionChange,
+) -> StdResult<Vec<CosmosMsg>> {
+    let hooks = get_hooks(&deps.storage, &deps.api.canonical_address(owner)?)?;
+    if hooks.is_empty() {
+        return Ok(vec![]);
+    }
+    let msg = to_binary(&PositionHookMsg::PositionChanged {
+        collateral: collateral.to_string(),
+        owner: owner.clone(),
+        change,
+    })?;
+    hooks
+        .iter()
+        .map(|hook| {
+            Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: deps.api.human_address(hook)?,
+                send: vec![],
+                msg: msg.clone(),
+            }))
+        })
+        .collect()
+}
+
+fn stable_asset() -> AssetInfo {
+    AssetInfo::NativeToken {
+        denom: TERRASWAP_PAIR.to_string(),
+    }
+}
+
+/// Hub minting the collateral's bAsset, the only one that can unbond it
+fn hub_contract<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    collateral: &str,
+    registered: &Collateral,
+) -> StdResult<HumanAddr> {
+    match &registered.mint_route {
+        MintRoute::Hub { hub_contract } => deps.api.human_address(hub_contract),
+        MintRoute::Converter { .. } => Err(StdError::generic_err(format!(
+            "Collateral '{}' is not minted by a hub",
+            collateral
+        ))),
+    }
+}
+
+/// Deposit asset one bAsset was minted for, the hub exchange rate or 1 for a converter
+fn query_mint_rate<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    registered: &Collateral,
+) -> StdResult<Decimal256> {
+    match &registered.mint_route {
+        MintRoute::Hub { hub_contract } => Ok(querier::query_bonded_exchange_rate(
+            deps,
+            &deps.api.human_address(hub_contract)?,
+        )?
+        .into()),
+        MintRoute::Converter { .. } => Ok(Decimal256::one()),
+    }
+}
+
+/// bAsset minting `amount` of the deposit asset returns, a hub keeps its peg
+/// recovery fee while its exchange rate is under the threshold
+fn query_mint_return<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    registered: &Collateral,
+    amount: Uint128,
+) -> StdResult<Uint256> {
+    let exchange_rate = query_mint_rate(deps, registered)?;
+    let minted = Uint256::from(amount) / exchange_rate;
+    if let MintRoute::Hub { hub_contract } = &registered.mint_route {
+        let parameters =
+            hub_querier::query_parameters(deps, &deps.api.human_address(hub_contract)?)?;
+        if exchange_rate < Decimal256::from(parameters.er_threshold) {
+            return Ok(minted - minted * Decimal256::from(parameters.peg_recovery_fee));
+        }
+    }
+
+    Ok(minted)
+}
+
+/// uusd per deposit asset, derived from the oracle price of the bAsset
+fn query_deposit_price<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    registered: &Collateral,
+) -> StdResult<Decimal256> {
+    let basset_price =
+        querier::query_basset_price(deps, config, &deps.api.human_address(&registered.token)?)?;
+    Ok(basset_price / query_mint_rate(deps, registered)?)
+}
+
+/// Shares `amount` is worth in a pool of `total` split into `total_shares`,
+/// the first deposit into an empty pool mints shares 1:1, while a pool emptied
+/// with shares left would hand part of the new amount to their holders
+fn to_shares(amount: Uint256, total_shares: Uint256, total: Uint256) -> StdResult<Uint256> {
+    if total_shares.is_zero() {
+        Ok(amount)
+    } else if total.is_zero() {
+        Err(StdError::generic_err(
+            "Pool is empty while shares of it are outstanding",
+        ))
+    } else {
+        Ok(amount.multiply_ratio(total_shares.0, total.0))
+    }
+}
+
+/// Part of a pool of `total` split into `total_shares` that `shares` are worth
+fn from_shares(shares: Uint256, total_shares: Uint256, total: Uint256) -> Uint256 {
+    if total_shares.is_zero() {
+        Uint256::zero()
+    } else {
+        total.multiply_ratio(shares.0, total_shares.0)
+    }
+}
+
+/// Splits `amount` proportionally to validator weights,
+/// the rounding remainder goes to the last validator
+fn split_by_weight(amount: Uint128, validators: &[Validator]) -> Vec<(HumanAddr, Uint128)> {
+    let total_weight: u64 = validators.iter().map(|v| v.weight).sum();
+    let mut remaining = amount.u128();
+    let mut split = vec![];
+    for (i, validator) in validators.iter().enumerate() {
+        let share = if i == validators.len() - 1 {
+            remaining
+        } else {
+            amount.multiply_ratio(validator.weight, total_weight).u128()
+        };
+        remaining -= share;
+        if share > 0 {
+            split.push((validator.address.clone(), Uint128::from(share)));
+        }
+    }
+    split
+}
+
+pub fn query_possible_borrow<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    contract_addr: &HumanAddr,
+    block_time: Option<u64>,
+    block_height: Option<u64>,
+    collateral: Option<String>,
+    owner: Option<HumanAddr>,
+) -> StdResult<PossibleBorrowResponse> {
+    let config = get_config(&deps.storage)?;
+    let position = match (collateral, owner) {
+        (Some(collateral), Some(owner)) => Some((collateral, deps.api.canonical_address(&owner)?)),
+        _ => None,
+    };
+    get_possible_borrow(
+        deps,
+        &config,
+        contract_addr,
+        block_time,
+        block_height,
+        position
+            .as_ref()
+            .map(|(collateral, owner)| (collateral.as_str(), owner)),
+    )
+}
+
+pub fn get_possible_borrow<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    contract_addr: &HumanAddr,
+    block_time: Option<u64>,
+    block_height: Option<u64>,
+    position: Option<(&str, &CanonicalAddr)>,
+) -> StdResult<PossibleBorrowResponse> {
+    let borrow_limit = querier::query_borrow_limit(deps, config, contract_addr, block_time)?;
+    let already_borrowed = querier::query_loan_amount(deps, config, contract_addr, block_height)?;
+    let target_loan = borrow_limit.mul(Decimal256::percent(BORROW_LTV_PERCENTAGE));
+    let mut borrow_amount = if target_loan > already_borrowed {
+        target_loan - already_borrowed
+    } else {
+        Uint256::zero()
+    };
+
+    // the loan is shared, so each owner only borrows against their own collateral
+    let (position_limit, slashing_haircut, position_debt) = match position {
+        Some((collateral, owner)) => {
+            let (position_limit, slashing_haircut) =
+                query_position_limit(deps, config, collateral, owner)?;
+            let position_debt = from_shares(
+                get_position(&deps.storage, collateral, owner)?.debt_shares,
+                get_state(&deps.storage)?.total_debt_shares,
+                already_borrowed,
+            );
+            let target_debt =
+                (position_limit - slashing_haircut).mul(Decimal256::percent(BORROW_LTV_PERCENTAGE));
+            borrow_amount = if target_debt > position_debt {
+                min(borrow_amount, target_debt - position_debt)
+            } else {
+                Uint256::zero()
+            };
+            (position_limit, slashing_haircut, position_debt)
+        }
+        None => (Uint256::zero(), Uint256::zero(), Uint256::zero()),
+    };
+
+    Ok(PossibleBorrowResponse {
+        borrow_limit,
+        position_limit,
+        slashing_haircut,
+        already_borrowed,
+        position_debt,
+        borrow_amount,
+    })
+}
+
+/// Borrow limit the owner's collateral brings in at the oracle price, and the
+/// part of it backed by what a drop of the hub exchange rate since their
+/// deposits took off that collateral, which Anchor, pricing a bAsset as its
+/// underlying, still lends against
+fn query_position_limit<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    collateral: &str,
+    owner: &CanonicalAddr,
+) -> StdResult<(Uint256, Uint256)> {
+    let registered = get_collateral(&deps.storage, collateral)?;
+    let position = get_position(&deps.storage, collateral, owner)?;
+    let collateral_state = get_collateral_state(&deps.storage, collateral)?;
+    let balance = from_shares(
+        position.collateral_shares,
+        collateral_state.total_collateral_shares,
+        collateral_state.total_collateral,
+    );
+    if balance.is_zero() {
+        return Ok((Uint256::zero(), Uint256::zero()));
+    }
+
+    let basset_token = deps.api.human_address(&registered.token)?;
+    let position_limit = balance
+        * querier::query_basset_price(deps, config, &basset_token)?
+        * querier::query_max_ltv(deps, config, &basset_token)?;
+    let exchange_rate = query_mint_rate(deps, &registered)?;
+    if position.exchange_rate.is_zero() || exchange_rate >= position.exchange_rate {
+        return Ok((position_limit, Uint256::zero()));
+    }
+    Ok((
+        position_limit,
+        position_limit * (Decimal256::one() - exchange_rate / position.exchange_rate),
+    ))
+}
+
+pub fn query_collateral<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    collateral: &str,
+    contract_addr: &HumanAddr,
+) -> StdResult<moneymarket::custody::BorrowerResponse> {
+    let registered = get_collateral(&deps.storage, collateral)?;
+    querier::query_collateral(
+        deps,
+        &deps.api.human_address(&registered.custody_contract)?,
+        contract_addr,
+    )
+}
+
+pub fn query_collaterals<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<CollateralsResponse> {
+    let collaterals = get_collaterals(&deps.storage)?
+        .into_iter()
+        .map(|(name, registered)| {
+            Ok(CollateralMsg {
+                deposit_asset: registered.deposit_asset.to_normal(deps)?,
+                mint_route: match registered.mint_route {
+                    MintRoute::Hub { hub_contract } => MintRouteMsg::Hub {
+                        hub_contract: deps.api.human_address(&hub_contract)?,
+                        validators: get_validators(&deps.storage, &name)?,
+                    },
+                    MintRoute::Converter { contract_addr, msg } => MintRouteMsg::Converter {
+                        contract_addr: deps.api.human_address(&contract_addr)?,
+                        msg,
+                    },
+                },
+                token: deps.api.human_address(&registered.token)?,
+                custody_contract: deps.api.human_address(&registered.custody_contract)?,
+                swap_route: match registered.swap_route {
+                    SwapRoute::Pair { pair_contract } => SwapRouteMsg::Pair {
+                        pair_contract: deps.api.human_address(&pair_contract)?,
+                    },
+                    SwapRoute::Router {
+                        router_contract,
+                        paths,
+                    } => SwapRouteMsg::Router {
+                        router_contract: deps.api.human_address(&router_contract)?,
+                        paths,
+                    },
+                },
+                exit_pair: deps.api.human_address(&registered.exit_pair)?,
+                staking_apr: registered.staking_apr,
+                name,
+            })
+        })
+        .collect::<StdResult<Vec<CollateralMsg>>>()?;
+
+    Ok(CollateralsResponse { collaterals })
+}
+
+pub fn query_state<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<StateResponse> {
+    let state = get_state(&deps.storage)?;
+    let collaterals = get_collaterals(&deps.storage)?
+        .into_iter()
+        .map(|(collateral, _)| {
+            let collateral_state = get_collateral_state(&deps.storage, &collateral)?;
+            Ok(CollateralStateResponse {
+                collateral,
+                total_collateral: collateral_state.total_collateral,
+                total_collateral_shares: collateral_state.total_collateral_shares,
+                fee_basset: collateral_state.fee_basset,
+                unbonded: collateral_state.unbonded,
+                exchange_rate: collateral_state.exchange_rate,
+            })
+        })
+        .collect::<StdResult<Vec<CollateralStateResponse>>>()?;
+
+    Ok(StateResponse {
+        total_debt_shares: state.total_debt_shares,
+        total_aust: state.total_aust,
+        total_deposit: state.total_deposit,
+        fee_stable: state.fee_stable,
+        collaterals,
+    })
+}
+
+pub fn query_position<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    collateral: String,
+    owner: HumanAddr,
+) -> StdResult<PositionResponse> {
+    let config = get_config(&deps.storage)?;
+    let state = get_state(&deps.storage)?;
+    let collateral_state = get_collateral_state(&deps.storage, &collateral)?;
+    let position = get_position(
+        &deps.storage,
+        &collateral,
+        &deps.api.canonical_address(&owner)?,
+    )?;
+    let loan = querier::query_loan_amount(
+        deps,
+        &config,
+        &deps.api.human_address(&config.contract_addr)?,
+        None,
+    )?;
+
+    Ok(PositionResponse {
+        collateral,
+        owner,
+        collateral_shares: position.collateral_shares,
+        debt_shares: position.debt_shares,
+        balance: from_shares(
+            position.collateral_shares,
+            collateral_state.total_collateral_shares,
+            collateral_state.total_collateral,
+        ),
+        debt: from_shares(position.debt_shares, state.total_debt_shares, loan),
+        earn_share: position.earn_share,
+        aust: position.aust,
+        exchange_rate: position.exchange_rate,
+    })
+}
+
+/// Pages a collateral's positions by owner or riskiest (highest debt to collateral) first
+pub fn query_positions<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    collateral: String,
+    start_after: Option<HumanAddr>,
+    limit: Option<u32>,
+    order_by: Option<PositionsOrder>,
+) -> StdResult<PositionsResponse> {
+    let start_after = match start_after {
+        Some(owner) => Some(deps.api.canonical_address(&owner)?),
+        None => None,
+    };
+    let positions = match order_by.unwrap_or(PositionsOrder::Owner) {
+        PositionsOrder::Owner => {
+            read_positions(&deps.storage, &collateral, start_after.as_ref(), limit)?
+        }
+        PositionsOrder::Risk => {
+            read_positions_by_risk(&deps.storage, &collateral, start_after.as_ref(), limit)?
+        }
+    };
+
+    let config = get_config(&deps.storage)?;
+    let registered = get_collateral(&deps.storage, &collateral)?;
+    let state = get_state(&deps.storage)?;
+    let collateral_state = get_collateral_state(&deps.storage, &collateral)?;
+    let loan = querier::query_loan_amount(
+        deps,
+        &config,
+        &deps.api.human_address(&config.contract_addr)?,
+        None,
+    )?;
+    let price =
+        querier::query_basset_price(deps, &config, &deps.api.human_address(&registered.token)?)?;
+
+    let positions = positions
+        .into_iter()
+        .map(|(owner, position)| {
+            let balance = from_shares(
+                position.collateral_shares,
+                collateral_state.total_collateral_shares,
+                collateral_state.total_collateral,
+            );
+            let debt = from_shares(position.debt_shares, state.total_debt_shares, loan);
+            let value = balance * price;
+            Ok(PositionSummary {
+                owner: deps.api.human_address(&owner)?,
+                collateral_shares: position.collateral_shares,
+                debt_shares: position.debt_shares,
+                balance,
+                debt,
+                ltv: if value.is_zero() {
+                    Decimal256::zero()
+                } else {
+                    Decimal256::from_ratio(debt.0, value.0)
+                },
+            })
+        })
+        .collect::<StdResult<Vec<PositionSummary>>>()?;
+
+    Ok(PositionsResponse {
+        collateral,
+        positions,
+    })
+}
+
+/// Values the owner's position at the mint rate and the oracle price
+/// and compares it with what was deposited
+pub fn query_pnl<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    collateral: String,
+    owner: HumanAddr,
+) -> StdResult<PnlResponse> {
+    let config = get_config(&deps.storage)?;
+    let registered = get_collateral(&deps.storage, &collateral)?;
+    let state = get_state(&deps.storage)?;
+    let collateral_state = get_collateral_state(&deps.storage, &collateral)?;
+    let owner_raw = deps.api.canonical_address(&owner)?;
+    let position = get_position(&deps.storage, &collateral, &owner_raw)?;
+    let realized_pnl = get_realized_pnl(&deps.storage, &collateral, &owner_raw)?;
+
+    let price = query_deposit_price(deps, &config, &registered)?;
+    let mint_rate = query_mint_rate(deps, &registered)?;
+    let loan = querier::query_loan_amount(
+        deps,
+        &config,
+        &deps.api.human_address(&config.contract_addr)?,
+        None,
+    )?;
+
+    let collateral_value = from_shares(
+        position.collateral_shares,
+        collateral_state.total_collateral_shares,
+        collateral_state.total_collateral,
+    ) * mint_rate;
+    let aust_uusd = position.aust * querier::query_aust_exchange_rate(deps, &config, None)?;
+    let assets = collateral_value + aust_uusd / price;
+    let assets_uusd = collateral_value * price + aust_uusd;
+    let debt_uusd = from_shares(position.debt_shares, state.total_debt_shares, loan);
+    let debt = debt_uusd / price;
+
+    Ok(PnlResponse {
+        collateral,
+        owner,
+        cost_basis: position.cost_basis,
+        cost_basis_uusd: position.cost_basis_uusd,
+        net_value: signed_difference(assets, debt),
+        net_value_uusd: signed_difference(assets_uusd, debt_uusd),
+        unrealized: signed_difference(assets, debt + position.cost_basis),
+        unrealized_uusd: signed_difference(assets_uusd, debt_uusd + position.cost_basis_uusd),
+        realized: signed_difference(realized_pnl.proceeds, realized_pnl.cost),
+        realized_uusd: signed_difference(realized_pnl.proceeds_uusd, realized_pnl.cost_uusd),
+    })
+}
+
+pub fn query_liquidations<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<LiquidationsResponse> {
+    let liquidations = read_liquidations(&deps.storage, start_after, limit)?
+        .into_iter()
+        .map(|(id, liquidation)| LiquidationResponse {
+            id,
+            collateral: liquidation.collateral,
+            block_height: liquidation.block_height,
+            seized: liquidation.seized,
+            loan: liquidation.loan,
+        })
+        .collect();
+
+    Ok(LiquidationsResponse { liquidations })
+}
+
+pub fn query_slashings<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<SlashingsResponse> {
+    let slashings = read_slashings(&deps.storage, start_after, limit)?
+        .into_iter()
+        .map(|(id, slashing)| SlashingResponse {
+            id,
+            collateral: slashing.collateral,
+            block_height: slashing.block_height,
+            previous_rate: slashing.previous_rate,
+            exchange_rate: slashing.exchange_rate,
+        })
+        .collect();
+
+    Ok(SlashingsResponse { slashings })
+}
+
+pub fn query_unbond_requests<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    collateral: String,
+    owner: HumanAddr,
+) -> StdResult<UnbondRequestsResponse> {
+    let requests = get_unbond_requests(
+        &deps.storage,
+        &collateral,
+        &deps.api.canonical_address(&owner)?,
+    )?;
+
+    Ok(UnbondRequestsResponse {
+        collateral,
+        owner,
+        requests,
+    })
+}
+
+pub fn query_operators<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    owner: HumanAddr,
+) -> StdResult<OperatorsResponse> {
+    let operators = get_operators(&deps.storage, &deps.api.canonical_address(&owner)?)?
+        .into_iter()
+        .map(|(operator, grant)| {
+            Ok(OperatorResponse {
+                operator: deps.api.human_address(&operator)?,
+                scopes: grant.scopes,
+                expires: grant.expires,
+            })
+        })
+        .collect::<StdResult<Vec<OperatorResponse>>>()?;
+
+    Ok(OperatorsResponse { owner, operators })
+}
+
+pub fn query_hooks<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    owner: HumanAddr,
+) -> StdResult<HooksResponse> {
+    let hooks = get_hooks(&deps.storage, &deps.api.canonical_address(&owner)?)?
+        .iter()
+        .map(|hook| deps.api.human_address(hook))
+        .collect::<StdResult<Vec<HumanAddr>>>()?;
+
+    Ok(HooksResponse { owner, hooks })
+}
+
+/// Estimates the yearly return on a deposit levered `leverage` times: the whole
+/// position earns staking yield, the loans pay the borrow rate and earn ANC
+/// distribution, and the part of them parked in Earn the deposit rate
+pub fn query_apy<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    collateral: String,
+    leverage: Option<Decimal256>,
+    earn_share: Option<Decimal256>,
+) -> StdResult<ApyResponse> {
+    let config = get_config(&deps.storage)?;
+    let registered = get_collateral(&deps.storage, &collateral)?;
+    let earn_share = earn_share.unwrap_or_else(Decimal256::zero);
+    if earn_share >= Decimal256::one() {
+        return Err(StdError::generic_err("Earn share must be lower than 1"));
+    }
+    let levered_share = Decimal256::one() - earn_share;
+
+    let max_ltv =
+        querier::query_max_ltv(deps, &config, &deps.api.human_address(&registered.token)?)?;
+    let target_ltv = max_ltv * Decimal256::percent(BORROW_LTV_PERCENTAGE);
+    // depositing 1 and re-depositing the levered part of every loan sums up
+    // to 1 / (1 - ltv * levered share)
+    let max_leverage = Decimal256::one() / (Decimal256::one() - target_ltv * levered_share);
+    let leverage = leverage.unwrap_or(max_leverage);
+    if leverage < Decimal256::one() || leverage > max_leverage {
+        return Err(StdError::generic_err(format!(
+            "Leverage must be between 1 and {}",
+            max_leverage
+        )));
+    }
+
+    let market_config = querier::query_market_config(deps, &config)?;
+    let market_state = querier::query_market_state(deps, &config, None)?;
+    let market_balance = deps.querier.query_balance(
+        deps.api.human_address(&config.anchor_market_contract)?,
+        TERRASWAP_PAIR,
+    )?;
+    let blocks_per_year = Decimal256::from_uint256(BLOCKS_PER_YEAR);
+    let borrow_apr = querier::query_borrow_rate(
+        deps,
+        &market_config.interest_model,
+        Uint256::from(market_balance.amount),
+        market_state.total_liabilities,
+        market_state.total_reserves,
+    )? * blocks_per_year;
+    let distribution_apr = if market_state.total_liabilities.is_zero() {
+        Decimal256::zero()
+    } else {
+        market_state.anc_emission_rate * blocks_per_year * querier::query_anc_price(deps, &config)?
+            / market_state.total_liabilities
+    };
+
+    let deposit_apr = querier::query_deposit_rate(deps, &config)? * blocks_per_year;
+
+    let borrowed = (leverage - Decimal256::one()) / levered_share;
+    let parked = borrowed * earn_share;
+    let earned =
+        leverage * registered.staking_apr + borrowed * distribution_apr + parked * deposit_apr;
+    let paid = borrowed * borrow_apr;
+    let (net_apy, negative) = if earned >= paid {
+        (earned - paid, false)
+    } else {
+        (paid - earned, true)
+    };
+
+    Ok(ApyResponse {
+        collateral,
+        leverage,
+        earn_share,
+        staking_apr: registered.staking_apr,
+        borrow_apr,
+        distribution_apr,
+        deposit_apr,
+        net_apy,
+        negative,
+    })
+}
+
+fn signed_difference(a: Uint256, b: Uint256) -> PnlAmount {
+    if a >= b {
+        PnlAmount {
+            amount: a - b,
+            negative: false,
+        }
+    } else {
+        PnlAmount {
+            amount: b - a,
+            negative: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_querier::{mock_dependencies, WasmMockQuerier};
+    use crate::state::{CollateralState, ConfigMsg, Position};
+    use cosmwasm_std::testing::{mock_env, MockApi, MockStorage, MOCK_CONTRACT_ADDR};
+    use cosmwasm_std::Decimal;
+
+    fn validator(address: &str, weight: u64) -> Validator {
+        Validator {
+            address: HumanAddr::from(address),
+            weight,
+        }
+    }
+
+    fn native(denom: &str) -> AssetInfo {
+        AssetInfo::NativeToken {
+            denom: denom.to_string(),
+        }
+    }
+
+    fn token(contract_addr: &str) -> AssetInfo {
+        AssetInfo::Token {
+            contract_addr: HumanAddr::from(contract_addr),
+        }
+    }
+
+    fn borrower_info(loan_amount: u64) -> moneymarket::market::BorrowerInfoResponse {
+        moneymarket::market::BorrowerInfoResponse {
+            borrower: HumanAddr::from(MOCK_CONTRACT_ADDR),
+            interest_index: Decimal256::one(),
+            reward_index: Decimal256::zero(),
+            loan_amount: Uint256::from(loan_amount),
+            pending_rewards: Decimal256::zero(),
+        }
+    }
+
+    fn hub_state(exchange_rate: Decimal) -> hub_querier::StateResponse {
+        hub_querier::StateResponse {
+            exchange_rate,
+            total_bond_amount: Uint128::zero(),
+            last_index_modification: 0,
+            prev_hub_balance: Uint128::zero(),
+            actual_unbonded_amount: Uint128::zero(),
+            last_unbonded_time: 0,
+            last_processed_batch: 0,
+        }
+    }
+
+    fn custody_balance(balance: u64) -> moneymarket::custody::BorrowerResponse {
+        moneymarket::custody::BorrowerResponse {
+            borrower: HumanAddr::from(MOCK_CONTRACT_ADDR),
+            balance: Uint256::from(balance),
+            spendable: Uint256::zero(),
+        }
+    }
+
+    fn epoch_state(exchange_rate: Decimal256) -> moneymarket::market::EpochStateResponse {
+        moneymarket::market::EpochStateResponse {
+            exchange_rate,
+            aterra_supply: Uint256::zero(),
+        }
+    }
+
+    fn price(rate: Decimal256) -> moneymarket::oracle::PriceResponse {
+        moneymarket::oracle::PriceResponse {
+            rate,
+            last_updated_base: 0,
+            last_updated_quote: 0,
+        }
+    }
+
+    fn simulation(return_amount: u128) -> terraswap::pair::SimulationResponse {
+        terraswap::pair::SimulationResponse {
+            return_amount: Uint128(return_amount),
+            spread_amount: Uint128::zero(),
+            commission_amount: Uint128::zero(),
+        }
+    }
+
+    /// bLuna bonded at a hub from uluna and bought on a uluna pair, bETH
+    /// converted from wETH and bought through a router
+    fn pool(assets: [AssetInfo; 2]) -> terraswap::pair::PoolResponse {
+        let [first, second] = assets;
+        terraswap::pair::PoolResponse {
+            assets: [
+                terraswap::asset::Asset {
+                    info: first,
+                    amount: Uint128(1_000_000_000_000),
+                },
+                terraswap::asset::Asset {
+                    info: second,
+                    amount: Uint128(1_000_000_000_000),
+                },
+            ],
+            total_share: Uint128(1_000_000_000_000),
+        }
+    }
+
+    fn token_info(symbol: &str) -> cw20::TokenInfoResponse {
+        cw20::TokenInfoResponse {
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            decimals: 6,
+            total_supply: Uint128(1_000_000_000_000),
+        }
+    }
+
+    fn custody_config(collateral_token: &str) -> moneymarket::custody::ConfigResponse {
+        moneymarket::custody::ConfigResponse {
+            owner: HumanAddr::from("gov"),
+            collateral_token: HumanAddr::from(collateral_token),
+            overseer_contract: HumanAddr::from("overseer"),
+            market_contract: HumanAddr::from("market"),
+            reward_contract: HumanAddr::from("reward"),
+            liquidation_contract: HumanAddr::from("liquidation"),
+            stable_denom: TERRASWAP_PAIR.to_string(),
+            basset_info: moneymarket::custody::BAssetInfo {
+                name: collateral_token.to_string(),
+                symbol: collateral_token.to_string(),
+                decimals: 6,
+            },
+        }
+    }
+
+    fn collaterals(validators: Vec<Validator>) -> Vec<CollateralMsg> {
+        vec![
+            CollateralMsg {
+                name: "bluna".to_string(),
+                deposit_asset: native("uluna"),
+                mint_route: MintRouteMsg::Hub {
+                    hub_contract: HumanAddr::from("hub"),
+                    validators,
+                },
+                token: HumanAddr::from("bluna"),
+                custody_contract: HumanAddr::from("custody"),
+                swap_route: SwapRouteMsg::Pair {
+                    pair_contract: HumanAddr::from("lunapair"),
+                },
+                exit_pair: HumanAddr::from("blunapair"),
+                staking_apr: Decimal256::percent(7),
+            },
+            CollateralMsg {
+                name: "beth".to_string(),
+                deposit_asset: token("weth"),
+                mint_route: MintRouteMsg::Converter {
+                    contract_addr: HumanAddr::from("converter"),
+                    msg: to_binary("convert").unwrap(),
+                },
+                token: HumanAddr::from("beth"),
+                custody_contract: HumanAddr::from("bethcustody"),
+                swap_route: SwapRouteMsg::Router {
+                    router_contract: HumanAddr::from("router"),
+                    paths: vec![vec![SwapOperation::TerraSwap {
+                        offer_asset_info: stable_asset(),
+                        ask_asset_info: token("weth"),
+                    }]],
+                },
+                exit_pair: HumanAddr::from("bethpair"),
+                staking_apr: Decimal256::percent(5),
+            },
+        ]
+    }
+
+    fn init_msg(validators: Vec<Validator>) -> InitMsg {
+        InitMsg {
+            config: ConfigMsg {
+                anchor_overseer_contract: HumanAddr::from("overseer"),
+                anchor_market_contract: HumanAddr::from("market"),
+                anchor_oracle_contract: HumanAddr::from("oracle"),
+                anc_token: HumanAddr::from("anc"),
+                terraswap_anc_ust: HumanAddr::from("ancpair"),
+                fee_collector: HumanAddr::from("collector"),
+                performance_fee: Decimal256::percent(10),
+                management_fee: Decimal256::percent(1),
+                max_total_deposit: Uint256::from(1_000_000_000_000u64),
+                max_user_deposit: Uint256::from(100_000_000_000u64),
+                min_deposit: Uint256::from(1_000_000u64),
+                max_slippage: Decimal256::percent(1),
+                collaterals: collaterals(validators),
+            },
+            owner: None,
+            fee_admin: Some(HumanAddr::from("feeadmin")),
+            init_hook: None,
+        }
+    }
+
+    /// Answers every query init and register_collateral send to check the
+    /// configured contracts
+    fn answer_probes(querier: &mut WasmMockQuerier) {
+        querier.with_response(
+            "market",
+            "config",
+            &moneymarket::market::ConfigResponse {
+                owner_addr: HumanAddr::from("gov"),
+                aterra_contract: HumanAddr::from("aust"),
+                interest_model: HumanAddr::from("interest"),
+                distribution_model: HumanAddr::from("distribution"),
+                overseer_contract: HumanAddr::from("overseer"),
+                collector_contract: HumanAddr::from("anccollector"),
+                distributor_contract: HumanAddr::from("distributor"),
+                stable_denom: TERRASWAP_PAIR.to_string(),
+                reserve_factor: Decimal256::zero(),
+                max_borrow_factor: Decimal256::one(),
+            },
+        );
+        querier.with_response(
+            "oracle",
+            "config",
+            &moneymarket::oracle::ConfigResponse {
+                owner: HumanAddr::from("gov"),
+                base_asset: TERRASWAP_PAIR.to_string(),
+            },
+        );
+        querier.with_response(
+            "overseer",
+            "config",
+            &moneymarket::overseer::ConfigResponse {
+                owner_addr: HumanAddr::from("gov"),
+                oracle_contract: HumanAddr::from("oracle"),
+                market_contract: HumanAddr::from("market"),
+                liquidation_contract: HumanAddr::from("liquidation"),
+                collector_contract: HumanAddr::from("anccollector"),
+                threshold_deposit_rate: Decimal256::zero(),
+                target_deposit_rate: Decimal256::zero(),
+                buffer_distribution_factor: Decimal256::zero(),
+                anc_purchase_factor: Decimal256::zero(),
+                stable_denom: TERRASWAP_PAIR.to_string(),
+                epoch_period: 0,
+                price_timeframe: 0,
+            },
+        );
+        querier.with_response(
+            "overseer",
+            "whitelist",
+            &moneymarket::overseer::WhitelistResponse {
+                elems: vec![moneymarket::overseer::WhitelistResponseElem {
+                    name: "bAsset".to_string(),
+                    symbol: "bAsset".to_string(),
+                    max_ltv: Decimal256::percent(50),
+                    custody_contract: HumanAddr::from("custody"),
+                    collateral_token: HumanAddr::from("bluna"),
+                }],
+            },
+        );
+        querier.with_response("anc", "token_info", &token_info("ANC"));
+        querier.with_response("ancpair", "pool", &pool([token("anc"), stable_asset()]));
+
+        querier.with_response("bluna", "token_info", &token_info("BLUNA"));
+        querier.with_response("custody", "config", &custody_config("bluna"));
+        querier.with_response("hub", "state", &hub_state(Decimal::one()));
+        querier.with_response("lunapair", "pool", &pool([native("uluna"), stable_asset()]));
+        querier.with_response(
+            "blunapair",
+            "pool",
+            &pool([token("bluna"), native("uluna")]),
+        );
+
+        querier.with_response("beth", "token_info", &token_info("BETH"));
+        querier.with_response("bethcustody", "config", &custody_config("beth"));
+        querier.with_response(
+            "router",
+            "config",
+            &router_querier::ConfigResponse {
+                terraswap_factory: HumanAddr::from("factory"),
+            },
+        );
+        querier.with_response("bethpair", "pool", &pool([token("beth"), token("weth")]));
+    }
+
+    /// Dependencies whose hub whitelists "val1" and "val2"
+    fn dependencies() -> Extern<MockStorage, MockApi, WasmMockQuerier> {
+        let mut deps = mock_dependencies(20, &[]);
+        deps.querier.with_response(
+            "hub",
+            "whitelisted_validators",
+            &hub_querier::WhitelistedValidatorsResponse {
+                validators: vec![HumanAddr::from("val1"), HumanAddr::from("val2")],
+            },
+        );
+        answer_probes(&mut deps.querier);
+        deps
+    }
+
+    /// Contract instantiated by "owner" with an empty loan at the market, 1:1
+    /// minting and aUST, both bAssets worth 50 uusd and bLuna sales returning
+    /// 980_000
+    fn instance() -> Extern<MockStorage, MockApi, WasmMockQuerier> {
+        let mut deps = dependencies();
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(0));
+        deps.querier
+            .with_response("market", "epoch_state", &epoch_state(Decimal256::one()));
+        deps.querier
+            .with_response("oracle", "price", &price(Decimal256::percent(5_000)));
+        deps.querier
+            .with_response("hub", "state", &hub_state(Decimal::one()));
+        deps.querier.with_response(
+            "hub",
+            "parameters",
+            &hub_querier::Parameters {
+                epoch_period: 0,
+                underlying_coin_denom: "uluna".to_string(),
+                unbonding_period: 0,
+                peg_recovery_fee: Decimal::permille(5),
+                er_threshold: Decimal::one(),
+                reward_denom: TERRASWAP_PAIR.to_string(),
+            },
+        );
+        deps.querier
+            .with_response("blunapair", "simulation", &simulation(980_000));
+        deps.querier
+            .with_response("bethpair", "simulation", &simulation(900_000));
+        let msg = init_msg(vec![validator("val1", 1)]);
+        init(&mut deps, mock_env("owner", &[]), msg).unwrap();
+        deps
+    }
+
+    fn lock<S: Storage, A: Api, Q: Querier>(deps: &mut Extern<S, A, Q>) {
+        let state = State {
+            loop_in_progress: true,
+            ..State::default()
+        };
+        set_state(&mut deps.storage, &state).unwrap();
+    }
+
+    fn is_lock_error(result: StdResult<HandleResponse>) -> bool {
+        match result {
+            Err(StdError::GenericErr { msg, .. }) => msg.contains("in progress"),
+            _ => false,
+        }
+    }
+
+    /// Stands in for the last step of the loop the test started
+    fn unlock<S: Storage, A: Api, Q: Querier>(deps: &mut Extern<S, A, Q>) {
+        let mut state = get_state(&deps.storage).unwrap();
+        state.loop_in_progress = false;
+        set_state(&mut deps.storage, &state).unwrap();
+    }
+
+    #[test]
+    fn validators_must_be_whitelisted_by_the_hub() {
+        let mut deps = dependencies();
+        let msg = init_msg(vec![validator("val1", 1), validator("val3", 1)]);
+        assert_eq!(
+            init(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err(
+                "Validator 'val3' is not whitelisted by the hub"
+            ))
+        );
+
+        let msg = init_msg(vec![validator("val1", 1)]);
+        init(&mut deps, mock_env("owner", &[]), msg).unwrap();
+
+        let msg = HandleMsg::UpdateValidators {
+            collateral: "bluna".to_string(),
+            validators: vec![validator("val2", 1), validator("val2", 2)],
+        };
+        assert_eq!(
+            handle(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err(
+                "Validator 'val2' is listed more than once"
+            ))
+        );
+
+        let msg = HandleMsg::UpdateValidators {
+            collateral: "beth".to_string(),
+            validators: vec![validator("val2", 1)],
+        };
+        assert_eq!(
+            handle(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err(
+                "Collateral 'beth' is not bonded to validators"
+            ))
+        );
+
+        let msg = HandleMsg::UpdateValidators {
+            collateral: "bluna".to_string(),
+            validators: vec![validator("val2", 1)],
+        };
+        assert_eq!(
+            handle(&mut deps, mock_env("stranger", &[]), msg.clone()),
+            Err(StdError::unauthorized())
+        );
+        handle(&mut deps, mock_env("owner", &[]), msg).unwrap();
+        assert_eq!(
+            get_validators(&deps.storage, "bluna").unwrap(),
+            vec![validator("val2", 1)]
+        );
+    }
+
+    #[test]
+    fn init_probes_the_anchor_contracts() {
+        let mut deps = dependencies();
+        deps.querier
+            .with_error("oracle", "config", "unknown variant `config`");
+        let msg = init_msg(vec![validator("val1", 1)]);
+        assert_eq!(
+            init(&mut deps, mock_env("owner", &[]), msg.clone()),
+            Err(StdError::generic_err(
+                "Oracle oracle does not answer as expected: Generic error: \
+                 unknown variant `config`"
+            ))
+        );
+
+        let mut deps = dependencies();
+        let mut msg = init_msg(vec![validator("val1", 1)]);
+        msg.config.anchor_oracle_contract = HumanAddr::from("oracle2");
+        deps.querier.with_response(
+            "oracle2",
+            "config",
+            &moneymarket::oracle::ConfigResponse {
+                owner: HumanAddr::from("gov"),
+                base_asset: TERRASWAP_PAIR.to_string(),
+            },
+        );
+        assert_eq!(
+            init(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err(
+                "Overseer overseer belongs to market market and oracle oracle"
+            ))
+        );
+
+        let mut deps = dependencies();
+        deps.querier
+            .with_response("ancpair", "pool", &pool([token("anc"), native("uluna")]));
+        let msg = init_msg(vec![validator("val1", 1)]);
+        assert_eq!(
+            init(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err("Pair ancpair does not trade uusd"))
+        );
+
+        let mut deps = dependencies();
+        let mut msg = init_msg(vec![validator("val1", 1)]);
+        msg.config.min_deposit = msg.config.max_user_deposit + Uint256::one();
+        assert_eq!(
+            init(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err(
+                "Deposit limits must be ordered min_deposit <= max_user_deposit <= max_total_deposit"
+            ))
+        );
+    }
+
+    #[test]
+    fn deposit_bonds_across_validators_by_weight() {
+        let mut deps = instance();
+        let msg = HandleMsg::UpdateValidators {
+            collateral: "bluna".to_string(),
+            validators: vec![validator("val1", 1), validator("val2", 3)],
+        };
+        handle(&mut deps, mock_env("owner", &[]), msg).unwrap();
+
+        let env = mock_env("depositor", &[coin(1_000_001, "uluna")]);
+        let msg = HandleMsg::Deposit {
+            collateral: "bluna".to_string(),
+            earn_share: None,
+            callback: None,
+            callback_contract: None,
+        };
+        let res = handle(&mut deps, env, msg).unwrap();
+        let bond = |validator: &str, amount: u128| {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from("hub"),
+                send: vec![coin(amount, "uluna")],
+                msg: querier::bond_luna(&HumanAddr::from(validator)).unwrap(),
+            })
+        };
+        assert_eq!(
+            res.messages,
+            vec![
+                bond("val1", 250_000),
+                // the rounding remainder goes to the last validator
+                bond("val2", 750_001),
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    send: vec![],
+                    msg: to_binary(&HandleMsg::DepositCollateral {
+                        collateral: "bluna".to_string(),
+                        owner: HumanAddr::from("depositor"),
+                        iteration: 0,
+                        balance_before: Uint128::zero(),
+                    })
+                    .unwrap(),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn deposits_buy_the_basset_when_the_exit_pair_beats_minting() {
+        let mut deps = instance();
+        let env = mock_env("depositor", &[coin(1_000_000, "uluna")]);
+        let msg = HandleMsg::Deposit {
+            collateral: "bluna".to_string(),
+            earn_share: None,
+            callback: None,
+            callback_contract: None,
+        };
+        let bond = CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: HumanAddr::from("hub"),
+            send: vec![coin(1_000_000, "uluna")],
+            msg: querier::bond_luna(&HumanAddr::from("val1")).unwrap(),
+        });
+        let buy = CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: HumanAddr::from("blunapair"),
+            send: vec![coin(1_000_000, "uluna")],
+            msg: querier::swap_native(
+                "uluna",
+                Uint128(1_000_000),
+                Decimal::from_ratio(1_000_000u128, 1_010_000u128),
+                Decimal::percent(1),
+                None,
+            )
+            .unwrap(),
+        });
+
+        let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
+        unlock(&mut deps);
+        assert_eq!(res.messages[0], bond);
+        assert!(res.log.contains(&log("mint_route", "mint")));
+        assert!(res.log.contains(&log("basset_expected", 1_000_000)));
+
+        deps.querier
+            .with_response("blunapair", "simulation", &simulation(1_010_000));
+        let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
+        unlock(&mut deps);
+        assert_eq!(res.messages[0], buy);
+        assert!(res.log.contains(&log("mint_route", "pair")));
+        assert!(res.log.contains(&log("basset_expected", 1_010_000)));
+
+        // below the threshold the hub mints more but keeps its peg recovery fee
+        deps.querier
+            .with_response("hub", "state", &hub_state(Decimal::percent(98)));
+        let res = handle(&mut deps, env.clone(), msg.clone()).unwrap();
+        unlock(&mut deps);
+        assert_eq!(res.messages[0], bond);
+        assert!(res.log.contains(&log("basset_expected", 1_015_306)));
+
+        // a pair that cannot be simulated leaves minting, and says why
+        deps.querier
+            .with_response("hub", "state", &hub_state(Decimal::one()));
+        deps.querier
+            .with_error("blunapair", "simulation", "Pool is empty");
+        let res = handle(&mut deps, env, msg).unwrap();
+        assert_eq!(res.messages[0], bond);
+        assert!(res.log.contains(&log("mint_route", "mint")));
+        assert!(res
+            .log
+            .iter()
+            .any(|attr| attr.key == "exit_pair_error" && attr.value.contains("Pool is empty")));
+    }
+
+    #[test]
+    fn deposit_loops_call_back_once_done() {
+        let mut deps = instance();
+        let deposit =
+            |callback: Option<&str>, callback_contract: Option<&str>| HandleMsg::Deposit {
+                collateral: "bluna".to_string(),
+                earn_share: None,
+                callback: callback.map(|msg| to_binary(msg).unwrap()),
+                callback_contract: callback_contract.map(HumanAddr::from),
+            };
+        let env = mock_env("depositor", &[coin(1_000_000, "uluna")]);
+        assert_eq!(
+            handle(&mut deps, env.clone(), deposit(Some("done"), None)),
+            Err(StdError::generic_err(
+                "callback and callback_contract must be given together"
+            ))
+        );
+        handle(&mut deps, env, deposit(Some("done"), Some("vault"))).unwrap();
+
+        deps.querier
+            .with_token_balance("bluna", MOCK_CONTRACT_ADDR, 1_000_000);
+        let msg = HandleMsg::DepositCollateral {
+            collateral: "bluna".to_string(),
+            owner: HumanAddr::from("depositor"),
+            iteration: 0,
+            balance_before: Uint128::zero(),
+        };
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg).unwrap();
+        assert_eq!(res.messages.len(), 3);
+
+        // nothing left to borrow ends the loop
+        deps.querier.with_response(
+            "overseer",
+            "borrow_limit",
+            &moneymarket::overseer::BorrowLimitResponse {
+                borrower: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                borrow_limit: Uint256::zero(),
+            },
+        );
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(1_000_000));
+        let msg = HandleMsg::Borrow {
+            collateral: "bluna".to_string(),
+            owner: HumanAddr::from("depositor"),
+            iteration: 0,
+        };
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg.clone()).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from("vault"),
+                send: vec![],
+                msg: to_binary(&DepositCallbackMsg::DepositCompleted {
+                    collateral: "bluna".to_string(),
+                    owner: HumanAddr::from("depositor"),
+                    bonded: Uint256::from(1_000_000u64),
+                    borrowed: Uint256::zero(),
+                    iterations: 1,
+                    msg: to_binary("done").unwrap(),
+                })
+                .unwrap(),
+            })]
+        );
+
+        // the callback is sent once
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg).unwrap();
+        assert!(res.messages.is_empty());
+    }
+
+    #[test]
+    fn loop_steps_only_move_what_the_previous_step_produced() {
+        let mut deps = instance();
+        deps.querier.with_balance(
+            MOCK_CONTRACT_ADDR,
+            vec![coin(3_000_000, "uluna"), coin(10_000_000, "uusd")],
+        );
+        deps.querier
+            .with_token_balance("bluna", MOCK_CONTRACT_ADDR, 500_000);
+
+        // the swap left 1 Luna on top of what the contract held already
+        let msg = HandleMsg::Redeposit {
+            collateral: "bluna".to_string(),
+            owner: HumanAddr::from("depositor"),
+            iteration: 1,
+            balance_before: Uint128(2_000_000),
+        };
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from("hub"),
+                    send: vec![coin(1_000_000, "uluna")],
+                    msg: querier::bond_luna(&HumanAddr::from("val1")).unwrap(),
+                }),
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    send: vec![],
+                    msg: to_binary(&HandleMsg::DepositCollateral {
+                        collateral: "bluna".to_string(),
+                        owner: HumanAddr::from("depositor"),
+                        iteration: 2,
+                        balance_before: Uint128(500_000),
+                    })
+                    .unwrap(),
+                }),
+            ]
+        );
+
+        // only the bLuna minted since is locked
+        deps.querier
+            .with_token_balance("bluna", MOCK_CONTRACT_ADDR, 1_500_000);
+        let msg = HandleMsg::DepositCollateral {
+            collateral: "bluna".to_string(),
+            owner: HumanAddr::from("depositor"),
+            iteration: 2,
+            balance_before: Uint128(500_000),
+        };
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg).unwrap();
+        assert_eq!(res.log[1], log("bonded", 1_000_000));
+        assert_eq!(
+            res.messages[0],
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from("bluna"),
+                send: vec![],
+                msg: querier::deposit_basset_collateral(
+                    &HumanAddr::from("custody"),
+                    Uint128(1_000_000),
+                )
+                .unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn cw20_deposits_go_through_the_converter() {
+        let mut deps = instance();
+        let receive = |collateral: &str| {
+            HandleMsg::Receive(Cw20ReceiveMsg {
+                sender: HumanAddr::from("depositor"),
+                amount: Uint128(1_000_000),
+                msg: Some(
+                    to_binary(&Cw20HookMsg::Deposit {
+                        collateral: collateral.to_string(),
+                        earn_share: None,
+                        callback: None,
+                        callback_contract: None,
+                    })
+                    .unwrap(),
+                ),
+            })
+        };
+
+        let res = handle(&mut deps, mock_env("weth", &[]), receive("beth")).unwrap();
+        unlock(&mut deps);
+        assert_eq!(
+            res.messages,
+            vec![
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from("weth"),
+                    send: vec![],
+                    msg: querier::convert_to_basset(
+                        &HumanAddr::from("converter"),
+                        Uint128(1_000_000),
+                        &to_binary("convert").unwrap(),
+                    )
+                    .unwrap(),
+                }),
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    send: vec![],
+                    msg: to_binary(&HandleMsg::DepositCollateral {
+                        collateral: "beth".to_string(),
+                        owner: HumanAddr::from("depositor"),
+                        iteration: 0,
+                        balance_before: Uint128::zero(),
+                    })
+                    .unwrap(),
+                }),
+            ]
+        );
+
+        // only the deposit asset of the collateral is accepted
+        let res = handle(&mut deps, mock_env("weth", &[]), receive("bluna"));
+        assert_eq!(res, Err(StdError::unauthorized()));
+        let res = handle(&mut deps, mock_env("mallory", &[]), receive("beth"));
+        assert_eq!(res, Err(StdError::unauthorized()));
+
+        let env = mock_env("depositor", &[coin(1_000_000, "uluna")]);
+        let msg = HandleMsg::Deposit {
+            collateral: "beth".to_string(),
+            earn_share: None,
+            callback: None,
+            callback_contract: None,
+        };
+        assert_eq!(
+            handle(&mut deps, env, msg),
+            Err(StdError::generic_err(
+                "'beth' is deposited by sending 'weth'"
+            ))
+        );
+    }
+
+    #[test]
+    fn register_collateral_checks_the_mint_route() {
+        let mut deps = instance();
+        let mut collateral = collaterals(vec![validator("val1", 1)]).remove(1);
+        collateral.name = "bsol".to_string();
+        collateral.token = HumanAddr::from("bsol");
+        collateral.custody_contract = HumanAddr::from("bsolcustody");
+        collateral.exit_pair = HumanAddr::from("bsolpair");
+        deps.querier
+            .with_response("bsol", "token_info", &token_info("BSOL"));
+        deps.querier
+            .with_response("bsolcustody", "config", &custody_config("bsol"));
+        deps.querier
+            .with_response("bsolpair", "pool", &pool([token("bsol"), token("weth")]));
+        collateral.mint_route = MintRouteMsg::Hub {
+            hub_contract: HumanAddr::from("hub"),
+            validators: vec![validator("val1", 1)],
+        };
+        let msg = HandleMsg::RegisterCollateral {
+            collateral: collateral.clone(),
+        };
+        assert_eq!(
+            handle(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err(
+                "A hub can only bond a native deposit asset"
+            ))
+        );
+
+        // a registered collateral keeps its token
+        let mut collateral = collaterals(vec![validator("val1", 1)]).remove(1);
+        collateral.token = HumanAddr::from("beth2");
+        collateral.custody_contract = HumanAddr::from("beth2custody");
+        collateral.exit_pair = HumanAddr::from("beth2pair");
+        deps.querier
+            .with_response("beth2", "token_info", &token_info("BETH"));
+        deps.querier
+            .with_response("beth2custody", "config", &custody_config("beth2"));
+        deps.querier
+            .with_response("beth2pair", "pool", &pool([token("beth2"), token("weth")]));
+        let msg = HandleMsg::RegisterCollateral { collateral };
+        assert_eq!(
+            handle(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err(
+                "Collateral 'beth' is already registered with another token"
+            ))
+        );
+
+        // the contracts of a collateral are probed before it is stored
+        let mut collateral = collaterals(vec![validator("val1", 1)]).remove(1);
+        collateral.custody_contract = HumanAddr::from("custody");
+        let msg = HandleMsg::RegisterCollateral { collateral };
+        assert_eq!(
+            handle(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err(
+                "Custody custody holds bluna for overseer overseer and market market"
+            ))
+        );
+
+        let mut collateral = collaterals(vec![validator("val1", 1)]).remove(1);
+        collateral.exit_pair = HumanAddr::from("blunapair");
+        let msg = HandleMsg::RegisterCollateral { collateral };
+        assert_eq!(
+            handle(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err("Pair blunapair does not trade beth"))
+        );
+
+        deps.querier.with_error(
+            "overseer",
+            "whitelist",
+            "Token is not registered as collateral",
+        );
+        let collateral = collaterals(vec![validator("val1", 1)]).remove(1);
+        let msg = HandleMsg::RegisterCollateral { collateral };
+        assert_eq!(
+            handle(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err(
+                "Overseer overseer does not answer as expected: Generic error: \
+                 Token is not registered as collateral"
+            ))
+        );
+        answer_probes(&mut deps.querier);
+
+        let mut collateral = collaterals(vec![validator("val1", 1)]).remove(1);
+        collateral.exit_pair = HumanAddr::from("bethpair2");
+        let msg = HandleMsg::RegisterCollateral { collateral };
+        assert_eq!(
+            handle(&mut deps, mock_env("owner", &[]), msg.clone()),
+            Err(StdError::generic_err(
+                "Pair bethpair2 does not answer as expected: Generic error: \
+                 Querier system error: Unsupported query type: pool on bethpair2"
+            ))
+        );
+        deps.querier
+            .with_response("bethpair2", "pool", &pool([token("beth"), token("weth")]));
+        assert_eq!(
+            handle(&mut deps, mock_env("stranger", &[]), msg.clone()),
+            Err(StdError::unauthorized())
+        );
+        handle(&mut deps, mock_env("owner", &[]), msg).unwrap();
+        let collaterals = query_collaterals(&deps).unwrap().collaterals;
+        assert_eq!(collaterals.len(), 2);
+        assert_eq!(collaterals[0].exit_pair, HumanAddr::from("bethpair2"));
+    }
+
+    #[test]
+    fn withdraw_keeps_the_management_fee() {
+        let mut deps = instance();
+        let depositor = deps
+            .api
+            .canonical_address(&HumanAddr::from("depositor"))
+            .unwrap();
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(3_000_000u64),
+            total_collateral_shares: Uint256::from(3_000_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(3_000_000));
+        let position = Position {
+            collateral_shares: Uint256::from(1_000_000u64),
+            ..Position::default()
+        };
+        set_position(&mut deps.storage, "bluna", &depositor, &position).unwrap();
+
+        let msg = HandleMsg::Withdraw {
+            collateral: "bluna".to_string(),
+            unbond: None,
+            owner: None,
+        };
+        let res = handle(&mut deps, mock_env("depositor", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages.last(),
+            Some(&CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from("bluna"),
+                send: vec![],
+                msg: querier::sell_token(
+                    &HumanAddr::from("blunapair"),
+                    Uint128(990_000),
+                    Decimal::from_ratio(990_000u128, 980_000u128),
+                    Decimal::percent(1),
+                    Some(HumanAddr::from("depositor")),
+                )
+                .unwrap(),
+            }))
+        );
+
+        let collateral_state = get_collateral_state(&deps.storage, "bluna").unwrap();
+        assert_eq!(collateral_state.fee_basset, Uint256::from(10_000u64));
+        assert_eq!(
+            collateral_state.total_collateral,
+            Uint256::from(2_000_000u64)
+        );
+        assert_eq!(
+            collateral_state.total_collateral_shares,
+            Uint256::from(2_000_000u64)
+        );
+        assert_eq!(
+            get_position(&deps.storage, "bluna", &depositor).unwrap(),
+            Position::default()
+        );
+    }
+
+    #[test]
+    fn withdraws_at_the_borrow_limit_repay_sent_uusd_first() {
+        let mut deps = instance();
+        let depositor = deps
+            .api
+            .canonical_address(&HumanAddr::from("depositor"))
+            .unwrap();
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(1_000_000u64),
+            total_collateral_shares: Uint256::from(1_000_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        let state = State {
+            total_debt_shares: Uint256::from(10_000_000u64),
+            ..get_state(&deps.storage).unwrap()
+        };
+        set_state(&mut deps.storage, &state).unwrap();
+        let position = Position {
+            collateral_shares: Uint256::from(1_000_000u64),
+            debt_shares: Uint256::from(10_000_000u64),
+            ..Position::default()
+        };
+        set_position(&mut deps.storage, "bluna", &depositor, &position).unwrap();
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(1_000_000));
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(10_000_000));
+        deps.querier.with_response(
+            "overseer",
+            "borrow_limit",
+            &moneymarket::overseer::BorrowLimitResponse {
+                borrower: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                borrow_limit: Uint256::from(10_000_000u64),
+            },
+        );
+
+        // at the borrow limit no collateral can be unlocked to sell
+        let msg = HandleMsg::Withdraw {
+            collateral: "bluna".to_string(),
+            unbond: None,
+            owner: None,
+        };
+        let res = handle(&mut deps, mock_env("depositor", &[]), msg.clone());
+        assert_eq!(
+            res,
+            Err(StdError::generic_err(
+                "Position cannot be deleveraged at the current LTV, send uusd to repay first"
+            ))
+        );
+        unlock(&mut deps);
+
+        // uusd sent along goes to the debt before anything is unlocked
+        deps.querier
+            .with_balance(MOCK_CONTRACT_ADDR, vec![coin(3_000_000, TERRASWAP_PAIR)]);
+        let env = mock_env("depositor", &[coin(2_000_000, TERRASWAP_PAIR)]);
+        let res = handle(&mut deps, env, msg).unwrap();
+        assert!(res.log.contains(&log("action", "repay_sent")));
+        assert_eq!(
+            res.messages,
+            vec![CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                send: vec![],
+                msg: to_binary(&HandleMsg::Repay {
+                    collateral: "bluna".to_string(),
+                    owner: HumanAddr::from("depositor"),
+                    iteration: 0,
+                    unbond: false,
+                    balance_before: Uint128(1_000_000),
+                })
+                .unwrap(),
+            })]
+        );
+    }
+
+    #[test]
+    fn compound_rewards_keeps_the_performance_fee() {
+        let mut deps = instance();
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(5_000_000));
+        let state = State {
+            total_debt_shares: Uint256::from(5_000_000u64),
+            fee_stable: Uint256::from(100_000u64),
+            ..State::default()
+        };
+        set_state(&mut deps.storage, &state).unwrap();
+
+        // the harvest remembers what was held before the claim
+        deps.querier
+            .with_balance(MOCK_CONTRACT_ADDR, vec![coin(1_100_000, TERRASWAP_PAIR)]);
+        let res = handle(&mut deps, mock_env("anyone", &[]), HandleMsg::Harvest {}).unwrap();
+        assert_eq!(
+            res.messages[1],
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                send: vec![],
+                msg: to_binary(&HandleMsg::SellRewards {
+                    balance_before: Uint128(1_100_000),
+                })
+                .unwrap(),
+            })
+        );
+
+        // only what the sale added is rewards, the fees already held are not
+        deps.querier
+            .with_balance(MOCK_CONTRACT_ADDR, vec![coin(2_100_000, TERRASWAP_PAIR)]);
+        let msg = HandleMsg::CompoundRewards {
+            balance_before: Uint128(1_100_000),
+        };
+        let env = mock_env(MOCK_CONTRACT_ADDR, &[]);
+        let res = handle(&mut deps, env, msg.clone()).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from("market"),
+                send: vec![coin(900_000, TERRASWAP_PAIR)],
+                msg: querier::anchor_repay().unwrap(),
+            })]
+        );
+        assert_eq!(
+            get_state(&deps.storage).unwrap().fee_stable,
+            Uint256::from(200_000u64)
+        );
+
+        let res = handle(&mut deps, mock_env("stranger", &[]), msg);
+        assert_eq!(res, Err(StdError::unauthorized()));
+    }
+
+    #[test]
+    fn shares_of_an_emptied_pool_are_not_diluted() {
+        assert_eq!(
+            to_shares(Uint256::from(5u64), Uint256::zero(), Uint256::zero()),
+            Ok(Uint256::from(5u64))
+        );
+        assert_eq!(
+            to_shares(
+                Uint256::from(5u64),
+                Uint256::from(20u64),
+                Uint256::from(10u64)
+            ),
+            Ok(Uint256::from(10u64))
+        );
+        assert_eq!(
+            to_shares(Uint256::from(5u64), Uint256::from(20u64), Uint256::zero()),
+            Err(StdError::generic_err(
+                "Pool is empty while shares of it are outstanding"
+            ))
+        );
+    }
+
+    #[test]
+    fn claim_fees_pays_the_fee_collector() {
+        let mut deps = instance();
+        deps.querier.with_tax(Decimal::percent(1), 1_000_000);
+        let collateral_state = CollateralState {
+            fee_basset: Uint256::from(10_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        let state = State {
+            fee_stable: Uint256::from(1_010_000u64),
+            ..State::default()
+        };
+        set_state(&mut deps.storage, &state).unwrap();
+
+        let res = handle(
+            &mut deps,
+            mock_env("stranger", &[]),
+            HandleMsg::ClaimFees {},
+        );
+        assert_eq!(res, Err(StdError::unauthorized()));
+
+        let res = handle(
+            &mut deps,
+            mock_env("collector", &[]),
+            HandleMsg::ClaimFees {},
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from("bluna"),
+                    send: vec![],
+                    msg: querier::transfer_basset(&HumanAddr::from("collector"), Uint128(10_000))
+                        .unwrap(),
+                }),
+                CosmosMsg::Bank(BankMsg::Send {
+                    from_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    to_address: HumanAddr::from("collector"),
+                    amount: vec![coin(1_000_000, TERRASWAP_PAIR)],
+                }),
+            ]
+        );
+
+        assert!(get_collateral_state(&deps.storage, "bluna")
+            .unwrap()
+            .fee_basset
+            .is_zero());
+        assert!(get_state(&deps.storage).unwrap().fee_stable.is_zero());
+    }
+
+    #[test]
+    fn fees_are_updated_by_the_fee_admin_only() {
+        let mut deps = instance();
+        let update = |fee: Decimal256| HandleMsg::UpdateFees {
+            fee_admin: None,
+            fee_collector: None,
+            performance_fee: Some(fee),
+            management_fee: None,
+        };
+
+        // the instance owner cannot touch the fees
+        for sender in &["stranger", "owner"] {
+            let res = handle(
+                &mut deps,
+                mock_env(*sender, &[]),
+                update(Decimal256::percent(20)),
+            );
+            assert_eq!(res, Err(StdError::unauthorized()));
+        }
+
+        let res = handle(
+            &mut deps,
+            mock_env("feeadmin", &[]),
+            update(Decimal256::one()),
+        );
+        assert_eq!(res, Err(StdError::generic_err("Fee must be lower than 1")));
+
+        handle(
+            &mut deps,
+            mock_env("feeadmin", &[]),
+            update(Decimal256::percent(20)),
+        )
+        .unwrap();
+        assert_eq!(
+            get_config(&deps.storage).unwrap().performance_fee,
+            Decimal256::percent(20)
+        );
+
+        let msg = HandleMsg::UpdateFees {
+            fee_admin: Some(HumanAddr::from("newadmin")),
+            fee_collector: Some(HumanAddr::from("newcollector")),
+            performance_fee: None,
+            management_fee: None,
+        };
+        handle(&mut deps, mock_env("feeadmin", &[]), msg).unwrap();
+        let config = get_config(&deps.storage).unwrap();
+        assert_eq!(
+            config.fee_admin,
+            deps.api
+                .canonical_address(&HumanAddr::from("newadmin"))
+                .unwrap()
+        );
+        assert_eq!(
+            config.fee_collector,
+            deps.api
+                .canonical_address(&HumanAddr::from("newcollector"))
+                .unwrap()
+        );
+        let res = handle(
+            &mut deps,
+            mock_env("feeadmin", &[]),
+            update(Decimal256::percent(30)),
+        );
+        assert_eq!(res, Err(StdError::unauthorized()));
+    }
+
+    #[test]
+    fn pnl_follows_deposits_and_exits() {
+        let mut deps = instance();
+        // bLuna at 55 uusd minted at 1.1 uluna values uluna at 50 uusd
+        deps.querier
+            .with_response("oracle", "price", &price(Decimal256::percent(5_500)));
+        deps.querier
+            .with_response("hub", "state", &hub_state(Decimal::percent(110)));
+
+        let env = mock_env("depositor", &[coin(1_000_000, "uluna")]);
+        let msg = HandleMsg::Deposit {
+            collateral: "bluna".to_string(),
+            earn_share: None,
+            callback: None,
+            callback_contract: None,
+        };
+        handle(&mut deps, env, msg).unwrap();
+        unlock(&mut deps);
+        // the bonded bLuna the deposit loop locks
+        let depositor = deps
+            .api
+            .canonical_address(&HumanAddr::from("depositor"))
+            .unwrap();
+        let mut position = get_position(&deps.storage, "bluna", &depositor).unwrap();
+        position.collateral_shares = Uint256::from(1_000_000u64);
+        set_position(&mut deps.storage, "bluna", &depositor, &position).unwrap();
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(1_000_000u64),
+            total_collateral_shares: Uint256::from(1_000_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(1_000_000));
+
+        let gain = |amount: u64| PnlAmount {
+            amount: Uint256::from(amount),
+            negative: false,
+        };
+        let loss = |amount: u64| PnlAmount {
+            amount: Uint256::from(amount),
+            negative: true,
+        };
+        let pnl = query_pnl(&deps, "bluna".to_string(), HumanAddr::from("depositor")).unwrap();
+        assert_eq!(pnl.cost_basis, Uint256::from(1_000_000u64));
+        assert_eq!(pnl.cost_basis_uusd, Uint256::from(50_000_000u64));
+        assert_eq!(pnl.net_value, gain(1_100_000));
+        assert_eq!(pnl.unrealized, gain(100_000));
+        assert_eq!(pnl.unrealized_uusd, gain(5_000_000));
+        assert_eq!(pnl.realized, gain(0));
+
+        let msg = HandleMsg::Withdraw {
+            collateral: "bluna".to_string(),
+            unbond: None,
+            owner: None,
+        };
+        handle(&mut deps, mock_env("depositor", &[]), msg).unwrap();
+        let pnl = query_pnl(&deps, "bluna".to_string(), HumanAddr::from("depositor")).unwrap();
+        assert_eq!(pnl.cost_basis, Uint256::zero());
+        assert_eq!(pnl.unrealized, gain(0));
+        assert_eq!(pnl.realized, loss(20_000));
+        assert_eq!(pnl.realized_uusd, loss(1_000_000));
+    }
+
+    #[test]
+    fn router_swaps_take_the_best_simulated_path() {
+        let mut deps = instance();
+        let direct = vec![SwapOperation::TerraSwap {
+            offer_asset_info: stable_asset(),
+            ask_asset_info: token("weth"),
+        }];
+        let through_luna = vec![
+            SwapOperation::NativeSwap {
+                offer_denom: TERRASWAP_PAIR.to_string(),
+                ask_denom: "uluna".to_string(),
+            },
+            SwapOperation::TerraSwap {
+                offer_asset_info: native("uluna"),
+                ask_asset_info: token("weth"),
+            },
+        ];
+        let mut collateral = collaterals(vec![validator("val1", 1)]).remove(1);
+        collateral.swap_route = SwapRouteMsg::Router {
+            router_contract: HumanAddr::from("router"),
+            paths: vec![direct.clone(), through_luna.clone()],
+        };
+        let msg = HandleMsg::RegisterCollateral { collateral };
+        handle(&mut deps, mock_env("owner", &[]), msg).unwrap();
+        let registered = get_collateral(&deps.storage, "beth").unwrap();
+
+        let simulate = |amount: u128, operations: &[SwapOperation]| {
+            router_querier::QueryMsg::SimulateSwapOperations {
+                offer_amount: Uint128(amount),
+                operations: operations.to_vec(),
+            }
+        };
+        let simulated = |amount: u128| router_querier::SimulateSwapOperationsResponse {
+            amount: Uint128(amount),
+        };
+        deps.querier
+            .with_query_response("router", &simulate(1_000, &direct), &simulated(900));
+        deps.querier.with_query_response(
+            "router",
+            &simulate(1_000, &through_luna),
+            &simulated(950),
+        );
+
+        let msg = route_swap_msg(&deps, &registered, &stable_asset(), Uint128(1_000), None);
+        assert_eq!(
+            msg.unwrap(),
+            (
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from("router"),
+                    send: vec![coin(1_000, TERRASWAP_PAIR)],
+                    // the best return less the 1% slippage
+                    msg: querier::execute_swap_operations(through_luna.clone(), Uint128(940), None)
+                        .unwrap(),
+                }),
+                Uint128(950)
+            )
+        );
+
+        // selling walks the paths backwards, a path failing to simulate is skipped,
+        // the uusd arriving is taxed so 1_000 is bound to 991 less the slippage
+        deps.querier.with_tax(Decimal::percent(1), 1_000_000);
+        let back: Vec<SwapOperation> = through_luna
+            .iter()
+            .rev()
+            .map(|operation| operation.reverse())
+            .collect();
+        deps.querier
+            .with_query_response("router", &simulate(20, &back), &simulated(1_000));
+        let to = Some(HumanAddr::from("depositor"));
+        let msg = route_swap_msg(&deps, &registered, &token("weth"), Uint128(20), to.clone());
+        assert_eq!(
+            msg.unwrap(),
+            (
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from("weth"),
+                    send: vec![],
+                    msg: querier::sell_token_on_router(
+                        &HumanAddr::from("router"),
+                        Uint128(20),
+                        back,
+                        Uint128(981),
+                        to,
+                    )
+                    .unwrap(),
+                }),
+                Uint128(1_000)
+            )
+        );
+
+        let msg = route_swap_msg(&deps, &registered, &token("weth"), Uint128(30), None);
+        assert_eq!(
+            msg,
+            Err(StdError::generic_err("No swap path can take 30 weth"))
+        );
+
+        let msg = HandleMsg::UpdateConfig {
+            owner: None,
+            max_total_deposit: None,
+            max_user_deposit: None,
+            min_deposit: None,
+            max_slippage: Some(Decimal256::one()),
+        };
+        assert_eq!(
+            handle(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err("Slippage must be lower than 1"))
+        );
+    }
+
+    #[test]
+    fn swap_paths_must_lead_from_uusd_to_the_deposit_asset() {
+        let mut deps = instance();
+        let register = |paths: Vec<Vec<SwapOperation>>| {
+            let mut collateral = collaterals(vec![validator("val1", 1)]).remove(1);
+            collateral.swap_route = SwapRouteMsg::Router {
+                router_contract: HumanAddr::from("router"),
+                paths,
+            };
+            HandleMsg::RegisterCollateral { collateral }
+        };
+
+        let res = handle(&mut deps, mock_env("owner", &[]), register(vec![]));
+        assert_eq!(
+            res,
+            Err(StdError::generic_err("At least one swap path is required"))
+        );
+
+        let path = vec![SwapOperation::TerraSwap {
+            offer_asset_info: native("uluna"),
+            ask_asset_info: token("weth"),
+        }];
+        let res = handle(&mut deps, mock_env("owner", &[]), register(vec![path]));
+        assert_eq!(
+            res,
+            Err(StdError::generic_err(
+                "Swap path offers 'uluna' where it holds 'uusd'"
+            ))
+        );
+
+        let path = vec![SwapOperation::NativeSwap {
+            offer_denom: TERRASWAP_PAIR.to_string(),
+            ask_denom: "uluna".to_string(),
+        }];
+        let res = handle(&mut deps, mock_env("owner", &[]), register(vec![path]));
+        assert_eq!(
+            res,
+            Err(StdError::generic_err(
+                "Swap path ends in 'uluna' instead of 'weth'"
+            ))
+        );
+    }
+
+    #[test]
+    fn uusd_tax_is_truncated_and_capped() {
+        let mut deps = instance();
+        deps.querier.with_tax(Decimal::permille(3), 1_400);
+        let tax = |amount: u64| querier::compute_tax(&deps, Uint256::from(amount)).unwrap();
+        assert_eq!(tax(1_000), Uint256::from(3u64));
+        assert_eq!(tax(999), Uint256::from(2u64));
+        assert_eq!(tax(1_000_000), Uint256::from(1_400u64));
+
+        let sendable = |balance: u64| querier::deduct_tax(&deps, Uint256::from(balance)).unwrap();
+        assert_eq!(sendable(0), Uint256::zero());
+        assert_eq!(sendable(1_003), Uint256::from(1_000u64));
+        assert_eq!(sendable(1_002), Uint256::from(999u64));
+        assert_eq!(sendable(1_000_000), Uint256::from(998_600u64));
+    }
+
+    #[test]
+    fn apy_levers_staking_distribution_and_earn_against_borrowing() {
+        let mut deps = instance();
+        deps.querier.with_response(
+            "overseer",
+            "whitelist",
+            &moneymarket::overseer::WhitelistResponse {
+                elems: vec![moneymarket::overseer::WhitelistResponseElem {
+                    name: "bLuna".to_string(),
+                    symbol: "bLuna".to_string(),
+                    max_ltv: Decimal256::percent(50),
+                    custody_contract: HumanAddr::from("custody"),
+                    collateral_token: HumanAddr::from("bluna"),
+                }],
+            },
+        );
+        deps.querier.with_response(
+            "market",
+            "state",
+            &moneymarket::market::StateResponse {
+                total_liabilities: Decimal256::from_uint256(931_362_000u64),
+                total_reserves: Decimal256::zero(),
+                last_interest_updated: 0,
+                last_reward_updated: 0,
+                global_interest_index: Decimal256::one(),
+                global_reward_index: Decimal256::zero(),
+                anc_emission_rate: Decimal256::from_uint256(10u64),
+            },
+        );
+        deps.querier.with_response(
+            "interest",
+            "borrow_rate",
+            &moneymarket::interest_model::BorrowRateResponse {
+                rate: Decimal256::from_ratio(2, 100_000_000),
+            },
+        );
+        deps.querier.with_response(
+            "ancpair",
+            "pool",
+            &terraswap::pair::PoolResponse {
+                assets: [
+                    terraswap::asset::Asset {
+                        info: token("anc"),
+                        amount: Uint128(500_000),
+                    },
+                    terraswap::asset::Asset {
+                        info: stable_asset(),
+                        amount: Uint128(1_000_000),
+                    },
+                ],
+                total_share: Uint128(700_000),
+            },
+        );
+
+        deps.querier.with_response(
+            "overseer",
+            "distribution_params",
+            &moneymarket::overseer::DistributionParamsResponse {
+                deposit_rate: Decimal256::from_ratio(3, 100_000_000),
+                target_deposit_rate: Decimal256::zero(),
+                threshold_deposit_rate: Decimal256::zero(),
+            },
+        );
+
+        let apy = query_apy(
+            &deps,
+            "bluna".to_string(),
+            Some(Decimal256::percent(150)),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            apy,
+            ApyResponse {
+                collateral: "bluna".to_string(),
+                leverage: Decimal256::percent(150),
+                earn_share: Decimal256::zero(),
+                staking_apr: Decimal256::percent(7),
+                borrow_apr: Decimal256::from_ratio(931_362, 10_000_000),
+                distribution_apr: Decimal256::percent(10),
+                deposit_apr: Decimal256::from_ratio(1_397_043, 10_000_000),
+                net_apy: Decimal256::from_ratio(1_084_319, 10_000_000),
+                negative: false,
+            }
+        );
+
+        // the loop at 70% of the 50% max LTV reaches 1 / (1 - 0.35)
+        let max_leverage = Decimal256::one() / (Decimal256::one() - Decimal256::percent(35));
+        let apy = query_apy(&deps, "bluna".to_string(), None, None).unwrap();
+        assert_eq!(apy.leverage, max_leverage);
+
+        let res = query_apy(
+            &deps,
+            "bluna".to_string(),
+            Some(Decimal256::percent(160)),
+            None,
+        );
+        assert_eq!(
+            res,
+            Err(StdError::generic_err(format!(
+                "Leverage must be between 1 and {}",
+                max_leverage
+            )))
+        );
+
+        // parking a fifth of every loan takes 0.3125 of loans to lever 1.25
+        // times, 0.0625 of which earn the deposit rate
+        let apy = query_apy(
+            &deps,
+            "bluna".to_string(),
+            Some(Decimal256::percent(125)),
+            Some(Decimal256::percent(20)),
+        )
+        .unwrap();
+        assert_eq!(
+            apy.net_apy,
+            Decimal256::from_ratio(9_837_645_625u64, 100_000_000_000u64)
+        );
+        let max_leverage = Decimal256::one() / (Decimal256::one() - Decimal256::percent(28));
+        let res = query_apy(
+            &deps,
+            "bluna".to_string(),
+            Some(Decimal256::percent(150)),
+            Some(Decimal256::percent(20)),
+        );
+        assert_eq!(
+            res,
+            Err(StdError::generic_err(format!(
+                "Leverage must be between 1 and {}",
+                max_leverage
+            )))
+        );
+        let res = query_apy(&deps, "bluna".to_string(), None, Some(Decimal256::one()));
+        assert_eq!(
+            res,
+            Err(StdError::generic_err("Earn share must be lower than 1"))
+        );
+    }
+
+    #[test]
+    fn earn_share_of_every_loan_is_parked_as_aust() {
+        let mut deps = instance();
+        deps.querier.with_response(
+            "overseer",
+            "borrow_limit",
+            &moneymarket::overseer::BorrowLimitResponse {
+                borrower: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                borrow_limit: Uint256::from(100_000_000u64),
+            },
+        );
+        let depositor = deps
+            .api
+            .canonical_address(&HumanAddr::from("depositor"))
+            .unwrap();
+
+        let deposit = |earn_share: u64| HandleMsg::Deposit {
+            collateral: "bluna".to_string(),
+            earn_share: Some(Decimal256::percent(earn_share)),
+            callback: None,
+            callback_contract: None,
+        };
+        let env = mock_env("depositor", &[coin(1_000_000, "uluna")]);
+        let res = handle(&mut deps, env.clone(), deposit(100));
+        assert_eq!(
+            res,
+            Err(StdError::generic_err("Earn share must be lower than 1"))
+        );
+        handle(&mut deps, env, deposit(20)).unwrap();
+        unlock(&mut deps);
+        let mut position = get_position(&deps.storage, "bluna", &depositor).unwrap();
+        assert_eq!(position.earn_share, Decimal256::percent(20));
+
+        // 4 bLuna worth 50 uusd each at a 50% max LTV back the whole limit,
+        // 70% of which is borrowed and a fifth of that goes to Earn
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(4_000_000u64),
+            total_collateral_shares: Uint256::from(4_000_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(4_000_000));
+        position.collateral_shares = Uint256::from(4_000_000u64);
+        set_position(&mut deps.storage, "bluna", &depositor, &position).unwrap();
+        let msg = HandleMsg::Borrow {
+            collateral: "bluna".to_string(),
+            owner: HumanAddr::from("depositor"),
+            iteration: 0,
+        };
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from("market"),
+                    send: vec![],
+                    msg: querier::anchor_borrow(Uint256::from(70_000_000u64)).unwrap(),
+                }),
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from("market"),
+                    send: vec![coin(14_000_000, TERRASWAP_PAIR)],
+                    msg: querier::anchor_deposit_stable().unwrap(),
+                }),
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    send: vec![],
+                    msg: to_binary(&HandleMsg::CreditEarn {
+                        collateral: "bluna".to_string(),
+                        owner: HumanAddr::from("depositor"),
+                        iteration: 0,
+                    })
+                    .unwrap(),
+                }),
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    send: vec![],
+                    msg: to_binary(&HandleMsg::Swap {
+                        collateral: "bluna".to_string(),
+                        amount: Uint128(56_000_000),
+                        owner: HumanAddr::from("depositor"),
+                        iteration: 0,
+                    })
+                    .unwrap(),
+                }),
+            ]
+        );
+
+        let msg = HandleMsg::CreditEarn {
+            collateral: "bluna".to_string(),
+            owner: HumanAddr::from("depositor"),
+            iteration: 0,
+        };
+        let res = handle(&mut deps, mock_env("depositor", &[]), msg.clone());
+        assert_eq!(res, Err(StdError::unauthorized()));
+        deps.querier
+            .with_token_balance("aust", MOCK_CONTRACT_ADDR, 130_000);
+        handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg).unwrap();
+        let mut position = get_position(&deps.storage, "bluna", &depositor).unwrap();
+        assert_eq!(position.aust, Uint256::from(130_000u64));
+        assert_eq!(
+            get_state(&deps.storage).unwrap().total_aust,
+            Uint256::from(130_000u64)
+        );
+
+        // exiting redeems the aUST and repays with it before touching collateral
+        position.collateral_shares = Uint256::from(1_000_000u64);
+        set_position(&mut deps.storage, "bluna", &depositor, &position).unwrap();
+        let msg = HandleMsg::Withdraw {
+            collateral: "bluna".to_string(),
+            unbond: None,
+            owner: None,
+        };
+        let res = handle(&mut deps, mock_env("depositor", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from("aust"),
+                    send: vec![],
+                    msg: querier::redeem_aust(&HumanAddr::from("market"), Uint128(130_000))
+                        .unwrap(),
+                }),
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    send: vec![],
+                    msg: to_binary(&HandleMsg::Repay {
+                        collateral: "bluna".to_string(),
+                        owner: HumanAddr::from("depositor"),
+                        iteration: 0,
+                        unbond: false,
+                        balance_before: Uint128::zero(),
+                    })
+                    .unwrap(),
+                }),
+            ]
+        );
+        let position = get_position(&deps.storage, "bluna", &depositor).unwrap();
+        assert_eq!(position.aust, Uint256::zero());
+        assert_eq!(
+            get_state(&deps.storage).unwrap().total_aust,
+            Uint256::zero()
+        );
+    }
+
+    #[test]
+    fn deposits_are_capped_in_total_and_per_user() {
+        let mut deps = instance();
+        let msg = HandleMsg::UpdateConfig {
+            owner: None,
+            max_total_deposit: Some(Uint256::from(200_000_000u64)),
+            max_user_deposit: Some(Uint256::from(120_000_000u64)),
+            min_deposit: Some(Uint256::from(10_000_000u64)),
+            max_slippage: None,
+        };
+        handle(&mut deps, mock_env("owner", &[]), msg).unwrap();
+        let msg = HandleMsg::UpdateConfig {
+            owner: None,
+            max_total_deposit: Some(Uint256::from(100_000_000u64)),
+            max_user_deposit: None,
+            min_deposit: None,
+            max_slippage: None,
+        };
+        assert_eq!(
+            handle(&mut deps, mock_env("owner", &[]), msg),
+            Err(StdError::generic_err(
+                "Deposit limits must be ordered min_deposit <= max_user_deposit <= max_total_deposit"
+            ))
+        );
+
+        // uluna is worth 50 uusd at the oracle
+        let mut deposit = |depositor: &str, amount: u128| {
+            let env = mock_env(depositor, &[coin(amount, "uluna")]);
+            let msg = HandleMsg::Deposit {
+                collateral: "bluna".to_string(),
+                earn_share: None,
+                callback: None,
+                callback_contract: None,
+            };
+            let res = handle(&mut deps, env, msg).map(|_| ());
+            unlock(&mut deps);
+            res
+        };
+        assert_eq!(
+            deposit("depositor", 100_000),
+            Err(StdError::generic_err(
+                "Deposit must be worth at least 10000000 uusd"
+            ))
+        );
+        deposit("depositor", 2_000_000).unwrap();
+        assert_eq!(
+            deposit("depositor", 1_000_000),
+            Err(StdError::generic_err(
+                "Deposits are capped at 120000000 uusd per user"
+            ))
+        );
+        deposit("other", 2_000_000).unwrap();
+        assert_eq!(
+            deposit("third", 1_000_000),
+            Err(StdError::generic_err(
+                "Deposits are capped at 200000000 uusd in total"
+            ))
+        );
+        assert_eq!(
+            get_state(&deps.storage).unwrap().total_deposit,
+            Uint256::from(200_000_000u64)
+        );
+
+        // a position exiting frees up its deposits
+        let depositor = deps
+            .api
+            .canonical_address(&HumanAddr::from("depositor"))
+            .unwrap();
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(2_000_000u64),
+            total_collateral_shares: Uint256::from(2_000_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(2_000_000));
+        let mut position = get_position(&deps.storage, "bluna", &depositor).unwrap();
+        position.collateral_shares = Uint256::from(2_000_000u64);
+        set_position(&mut deps.storage, "bluna", &depositor, &position).unwrap();
+        let msg = HandleMsg::Withdraw {
+            collateral: "bluna".to_string(),
+            unbond: None,
+            owner: None,
+        };
+        handle(&mut deps, mock_env("depositor", &[]), msg).unwrap();
+        assert_eq!(
+            get_state(&deps.storage).unwrap().total_deposit,
+            Uint256::from(100_000_000u64)
+        );
+
+        let env = mock_env("third", &[coin(1_000_000, "uluna")]);
+        let msg = HandleMsg::Deposit {
+            collateral: "bluna".to_string(),
+            earn_share: None,
+            callback: None,
+            callback_contract: None,
+        };
+        handle(&mut deps, env, msg).unwrap();
+    }
+
+    #[test]
+    fn liquidations_are_shared_by_every_position() {
+        let mut deps = instance();
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(2_000_000));
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(4_000_000u64),
+            total_collateral_shares: Uint256::from(4_000_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        for (owner, shares) in &[("alice", 1_000_000u64), ("bob", 3_000_000u64)] {
+            let owner = deps
+                .api
+                .canonical_address(&HumanAddr::from(*owner))
+                .unwrap();
+            let position = Position {
+                collateral_shares: Uint256::from(*shares),
+                ..Position::default()
+            };
+            set_position(&mut deps.storage, "bluna", &owner, &position).unwrap();
+        }
+
+        // Anchor seized a quarter of the pool
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(3_000_000));
+        let res = handle(&mut deps, mock_env("keeper", &[]), HandleMsg::Reconcile {}).unwrap();
+        assert_eq!(
+            res.log,
+            vec![
+                log("action", "reconcile"),
+                log("notified", 1),
+                log("hook_owners", 0),
+                log("liquidation", "1000000 bluna"),
+            ]
+        );
+        assert_eq!(
+            get_collateral_state(&deps.storage, "bluna")
+                .unwrap()
+                .total_collateral,
+            Uint256::from(3_000_000u64)
+        );
+        let balance = |owner: &str| {
+            query_position(&deps, "bluna".to_string(), HumanAddr::from(owner))
+                .unwrap()
+                .balance
+        };
+        assert_eq!(balance("alice"), Uint256::from(750_000u64));
+        assert_eq!(balance("bob"), Uint256::from(2_250_000u64));
+
+        // nothing more to catch up with
+        let res = handle(&mut deps, mock_env("keeper", &[]), HandleMsg::Reconcile {}).unwrap();
+        assert_eq!(
+            res.log,
+            vec![
+                log("action", "reconcile"),
+                log("notified", 0),
+                log("hook_owners", 0)
+            ]
+        );
+
+        // any handler catches up first
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(2_400_000));
+        let msg = HandleMsg::Deposit {
+            collateral: "bluna".to_string(),
+            earn_share: None,
+            callback: None,
+            callback_contract: None,
+        };
+        let env = mock_env("alice", &[coin(1_000_000, "uluna")]);
+        let res = handle(&mut deps, env, msg).unwrap();
+        assert_eq!(res.log.last(), Some(&log("liquidation", "600000 bluna")));
+
+        let liquidations = query_liquidations(&deps, None, None).unwrap();
+        assert_eq!(
+            liquidations,
+            LiquidationsResponse {
+                liquidations: vec![
+                    LiquidationResponse {
+                        id: 0,
+                        collateral: "bluna".to_string(),
+                        block_height: 12_345,
+                        seized: Uint256::from(1_000_000u64),
+                        loan: Uint256::from(2_000_000u64),
+                    },
+                    LiquidationResponse {
+                        id: 1,
+                        collateral: "bluna".to_string(),
+                        block_height: 12_345,
+                        seized: Uint256::from(600_000u64),
+                        loan: Uint256::from(2_000_000u64),
+                    },
+                ],
+            }
+        );
+        let liquidations = query_liquidations(&deps, Some(0), None).unwrap();
+        assert_eq!(liquidations.liquidations.len(), 1);
+        assert_eq!(liquidations.liquidations[0].id, 1);
+    }
+
+    #[test]
+    fn hooks_are_told_of_position_changes() {
+        let mut deps = instance();
+        let add_hook = |contract: &str| HandleMsg::AddHook {
+            contract: HumanAddr::from(contract),
+            owner: None,
+        };
+        let env = mock_env("depositor", &[]);
+        assert_eq!(
+            handle(&mut deps, env.clone(), add_hook("hook1")),
+            Err(StdError::generic_err(
+                "Only owners of a position can add hooks"
+            ))
+        );
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(1_000_000u64),
+            total_collateral_shares: Uint256::from(1_000_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(1_000_000));
+        let position = Position {
+            collateral_shares: Uint256::from(1_000_000u64),
+            ..Position::default()
+        };
+        let depositor = deps
+            .api
+            .canonical_address(&HumanAddr::from("depositor"))
+            .unwrap();
+        set_position(&mut deps.storage, "bluna", &depositor, &position).unwrap();
+
+        handle(&mut deps, env.clone(), add_hook("hook1")).unwrap();
+        assert_eq!(
+            handle(&mut deps, env.clone(), add_hook("hook1")),
+            Err(StdError::generic_err("hook1 is already a hook"))
+        );
+        for hook in &["hook2", "hook3", "hook4", "hook5"] {
+            handle(&mut deps, env.clone(), add_hook(hook)).unwrap();
+        }
+        assert_eq!(
+            handle(&mut deps, env.clone(), add_hook("hook6")),
+            Err(StdError::generic_err(format!(
+                "At most {} hooks per owner",
+                MAX_HOOKS
+            )))
+        );
+        for hook in &["hook2", "hook3", "hook4", "hook5"] {
+            let msg = HandleMsg::RemoveHook {
+                contract: HumanAddr::from(*hook),
+                owner: None,
+            };
+            handle(&mut deps, env.clone(), msg).unwrap();
+        }
+        let msg = HandleMsg::RemoveHook {
+            contract: HumanAddr::from("hook2"),
+            owner: None,
+        };
+        assert_eq!(
+            handle(&mut deps, env, msg),
+            Err(StdError::generic_err("hook2 is not a hook"))
+        );
+        assert_eq!(
+            query_hooks(&deps, HumanAddr::from("depositor")).unwrap(),
+            HooksResponse {
+                owner: HumanAddr::from("depositor"),
+                hooks: vec![HumanAddr::from("hook1")],
+            }
+        );
+
+        let callback = |change: PositionChange| {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from("hook1"),
+                send: vec![],
+                msg: to_binary(&PositionHookMsg::PositionChanged {
+                    collateral: "bluna".to_string(),
+                    owner: HumanAddr::from("depositor"),
+                    change,
+                })
+                .unwrap(),
+            })
+        };
+        deps.querier
+            .with_token_balance("bluna", MOCK_CONTRACT_ADDR, 1_000_000);
+        let msg = HandleMsg::DepositCollateral {
+            collateral: "bluna".to_string(),
+            owner: HumanAddr::from("depositor"),
+            iteration: 0,
+            balance_before: Uint128::zero(),
+        };
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg).unwrap();
+        let messages = res.messages.len();
+        assert_eq!(
+            res.messages[messages - 2],
+            callback(PositionChange::Deposit)
+        );
+
+        // a liquidation is only told once Reconcile is called for it
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(750_000));
+        let res = handle(&mut deps, mock_env("keeper", &[]), HandleMsg::Reconcile {}).unwrap();
+        assert_eq!(res.messages, vec![callback(PositionChange::Liquidation)]);
+        let res = handle(&mut deps, mock_env("keeper", &[]), HandleMsg::Reconcile {}).unwrap();
+        assert!(res.messages.is_empty());
+
+        // withdrawing the last position tells the hooks and drops them
+        let msg = HandleMsg::Withdraw {
+            collateral: "bluna".to_string(),
+            unbond: None,
+            owner: None,
+        };
+        let res = handle(&mut deps, mock_env("depositor", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages.last(),
+            Some(&callback(PositionChange::Withdraw))
+        );
+        assert_eq!(
+            query_hooks(&deps, HumanAddr::from("depositor"))
+                .unwrap()
+                .hooks,
+            Vec::<HumanAddr>::new()
+        );
+    }
+
+    #[test]
+    fn emptied_pools_void_their_shares() {
+        let mut deps = instance();
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(2_000_000));
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(4_000_000));
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(4_000_000u64),
+            total_collateral_shares: Uint256::from(4_000_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        let state = State {
+            total_debt_shares: Uint256::from(2_000_000u64),
+            ..get_state(&deps.storage).unwrap()
+        };
+        set_state(&mut deps.storage, &state).unwrap();
+        let alice = deps
+            .api
+            .canonical_address(&HumanAddr::from("alice"))
+            .unwrap();
+        let position = Position {
+            collateral_shares: Uint256::from(4_000_000u64),
+            debt_shares: Uint256::from(2_000_000u64),
+            ..Position::default()
+        };
+        set_position(&mut deps.storage, "bluna", &alice, &position).unwrap();
+
+        // a loan repaid in full leaves its debt shares worth nothing
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(0));
+        let res = handle(&mut deps, mock_env("keeper", &[]), HandleMsg::Reconcile {}).unwrap();
+        assert!(res.log.contains(&log("wiped_debt", 1)));
+        assert_eq!(
+            get_state(&deps.storage).unwrap().total_debt_shares,
+            Uint256::zero()
+        );
+        let position = get_position(&deps.storage, "bluna", &alice).unwrap();
+        assert_eq!(position.debt_shares, Uint256::zero());
+        assert_eq!(position.collateral_shares, Uint256::from(4_000_000u64));
+
+        // so does a pool Anchor seized all of
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(0));
+        let res = handle(&mut deps, mock_env("keeper", &[]), HandleMsg::Reconcile {}).unwrap();
+        assert!(res.log.contains(&log("liquidation", "4000000 bluna")));
+        assert!(res.log.contains(&log("wiped_collateral", "bluna")));
+        let collateral_state = get_collateral_state(&deps.storage, "bluna").unwrap();
+        assert_eq!(collateral_state.total_collateral_shares, Uint256::zero());
+        assert_eq!(collateral_state.wipes, 1);
+        assert_eq!(
+            get_position(&deps.storage, "bluna", &alice)
+                .unwrap()
+                .collateral_shares,
+            Uint256::zero()
+        );
+
+        // the next deposit mints into the pool afresh, the old shares claim none of it
+        deps.querier
+            .with_token_balance("bluna", MOCK_CONTRACT_ADDR, 1_000_000);
+        let msg = HandleMsg::DepositCollateral {
+            collateral: "bluna".to_string(),
+            owner: HumanAddr::from("bob"),
+            iteration: 0,
+            balance_before: Uint128::zero(),
+        };
+        handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg).unwrap();
+        let balance = |owner: &str| {
+            query_position(&deps, "bluna".to_string(), HumanAddr::from(owner))
+                .unwrap()
+                .balance
+        };
+        assert_eq!(balance("bob"), Uint256::from(1_000_000u64));
+        assert_eq!(balance("alice"), Uint256::zero());
+    }
+
+    #[test]
+    fn liquidation_hooks_are_paged_across_reconcile_calls() {
+        let mut deps = instance();
+        let owners = HOOK_OWNERS_PER_CALL as u64 + 1;
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(owners * 1_000_000),
+            total_collateral_shares: Uint256::from(owners * 1_000_000),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(owners * 1_000_000));
+        for i in 0..owners {
+            let owner = HumanAddr::from(format!("owner{:02}", i));
+            let position = Position {
+                collateral_shares: Uint256::from(1_000_000u64),
+                ..Position::default()
+            };
+            let owner_raw = deps.api.canonical_address(&owner).unwrap();
+            set_position(&mut deps.storage, "bluna", &owner_raw, &position).unwrap();
+            let msg = HandleMsg::AddHook {
+                contract: HumanAddr::from("hook"),
+                owner: None,
+            };
+            handle(&mut deps, mock_env(owner, &[]), msg).unwrap();
+        }
+
+        // the first call takes the liquidation to a page of owners, the next
+        // one to the rest
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(owners * 500_000));
+        let res = handle(&mut deps, mock_env("keeper", &[]), HandleMsg::Reconcile {}).unwrap();
+        assert_eq!(res.messages.len(), HOOK_OWNERS_PER_CALL as usize);
+        assert!(res.log.contains(&log("notified", 1)));
+        assert!(res.log.contains(&log("hook_owners", HOOK_OWNERS_PER_CALL)));
+
+        let res = handle(&mut deps, mock_env("keeper", &[]), HandleMsg::Reconcile {}).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from("hook"),
+                send: vec![],
+                msg: to_binary(&PositionHookMsg::PositionChanged {
+                    collateral: "bluna".to_string(),
+                    owner: HumanAddr::from(format!("owner{:02}", owners - 1)),
+                    change: PositionChange::Liquidation,
+                })
+                .unwrap(),
+            })]
+        );
+        assert!(res.log.contains(&log("notified", 0)));
+        assert!(get_state(&deps.storage).unwrap().notifying.is_empty());
+
+        let res = handle(&mut deps, mock_env("keeper", &[]), HandleMsg::Reconcile {}).unwrap();
+        assert!(res.messages.is_empty());
+        assert!(res.log.contains(&log("hook_owners", 0)));
+    }
+
+    #[test]
+    fn slashings_haircut_the_borrow_limit() {
+        let mut deps = instance();
+        deps.querier.with_response(
+            "overseer",
+            "borrow_limit",
+            &moneymarket::overseer::BorrowLimitResponse {
+                borrower: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                borrow_limit: Uint256::from(50_000_000u64),
+            },
+        );
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(2_000_000));
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(2_000_000u64),
+            total_collateral_shares: Uint256::from(2_000_000u64),
+            exchange_rate: Decimal256::one(),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+
+        // the hub is only asked to look for a slashing once its delegations
+        // fall short of what it has bonded
+        let check_slashing = CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: HumanAddr::from("hub"),
+            send: vec![],
+            msg: to_binary(&hub_querier::HandleMsg::CheckSlashing {}).unwrap(),
+        });
+        deps.querier
+            .with_token_balance("bluna", MOCK_CONTRACT_ADDR, 1_000_000);
+        let msg = HandleMsg::DepositCollateral {
+            collateral: "bluna".to_string(),
+            owner: HumanAddr::from("depositor"),
+            iteration: 0,
+            balance_before: Uint128::zero(),
+        };
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg.clone()).unwrap();
+        assert!(!res.messages.contains(&check_slashing));
+
+        deps.querier.with_response(
+            "hub",
+            "state",
+            &hub_querier::StateResponse {
+                total_bond_amount: Uint128(1_000_000),
+                ..hub_state(Decimal::one())
+            },
+        );
+        deps.querier.with_delegation("hub", 900_000);
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg).unwrap();
+        assert_eq!(res.messages[2], check_slashing);
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        let depositor = deps
+            .api
+            .canonical_address(&HumanAddr::from("depositor"))
+            .unwrap();
+        let position = Position {
+            collateral_shares: Uint256::from(2_000_000u64),
+            exchange_rate: Decimal256::one(),
+            ..Position::default()
+        };
+        set_position(&mut deps.storage, "bluna", &depositor, &position).unwrap();
+
+        // 10% slashed off the depositor's 2 bLuna worth 50 uusd each at a
+        // 50% max LTV, while the limit as a whole is left alone
+        deps.querier
+            .with_response("hub", "state", &hub_state(Decimal::percent(90)));
+        let possible_borrow = query_possible_borrow(
+            &deps,
+            &HumanAddr::from(MOCK_CONTRACT_ADDR),
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(possible_borrow.slashing_haircut, Uint256::zero());
+        let possible_borrow = query_possible_borrow(
+            &deps,
+            &HumanAddr::from(MOCK_CONTRACT_ADDR),
+            None,
+            None,
+            Some("bluna".to_string()),
+            Some(HumanAddr::from("depositor")),
+        )
+        .unwrap();
+        assert_eq!(
+            possible_borrow.slashing_haircut,
+            Uint256::from(5_000_000u64)
+        );
+        assert_eq!(possible_borrow.position_limit, Uint256::from(50_000_000u64));
+        assert_eq!(possible_borrow.borrow_amount, Uint256::from(31_500_000u64));
+
+        let msg = HandleMsg::Borrow {
+            collateral: "bluna".to_string(),
+            owner: HumanAddr::from("depositor"),
+            iteration: 0,
+        };
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg.clone()).unwrap();
+        assert!(res.log.contains(&log("slashing", "bluna")));
+        assert!(res.log.contains(&log("previous_rate", 1)));
+        let slashings = query_slashings(&deps, None, None).unwrap().slashings;
+        assert_eq!(
+            slashings,
+            vec![SlashingResponse {
+                id: 0,
+                collateral: "bluna".to_string(),
+                block_height: 12_345,
+                previous_rate: Decimal256::one(),
+                exchange_rate: Decimal256::percent(90),
+            }]
+        );
+
+        // the rate of the last deposit is what the next one is held to
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(31_500_000));
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg).unwrap();
+        assert!(!res.log.contains(&log("slashing", "bluna")));
+        assert_eq!(
+            query_slashings(&deps, None, None).unwrap().slashings.len(),
+            1
+        );
+
+        // a slashing can never take more than the position's own limit
+        deps.querier
+            .with_response("hub", "state", &hub_state(Decimal::zero()));
+        let possible_borrow = query_possible_borrow(
+            &deps,
+            &HumanAddr::from(MOCK_CONTRACT_ADDR),
+            None,
+            None,
+            Some("bluna".to_string()),
+            Some(HumanAddr::from("depositor")),
+        )
+        .unwrap();
+        assert_eq!(
+            possible_borrow.slashing_haircut,
+            Uint256::from(50_000_000u64)
+        );
+        assert_eq!(possible_borrow.borrow_amount, Uint256::zero());
+    }
+
+    #[test]
+    fn owners_borrow_against_their_own_collateral() {
+        let mut deps = instance();
+        deps.querier.with_response(
+            "overseer",
+            "borrow_limit",
+            &moneymarket::overseer::BorrowLimitResponse {
+                borrower: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                borrow_limit: Uint256::from(100_000_000u64),
+            },
+        );
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(20_000_000));
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(4_000_000));
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(4_000_000u64),
+            total_collateral_shares: Uint256::from(4_000_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        let state = State {
+            total_debt_shares: Uint256::from(20_000_000u64),
+            ..State::default()
+        };
+        set_state(&mut deps.storage, &state).unwrap();
+
+        // both hold 2 bLuna worth 50 uusd each at a 50% max LTV, only the
+        // first one has borrowed so far
+        for (owner, debt_shares) in &[("levered", 20_000_000u64), ("fresh", 0)] {
+            let owner_raw = deps
+                .api
+                .canonical_address(&HumanAddr::from(*owner))
+                .unwrap();
+            let position = Position {
+                collateral_shares: Uint256::from(2_000_000u64),
+                debt_shares: Uint256::from(*debt_shares),
+                ..Position::default()
+            };
+            set_position(&mut deps.storage, "bluna", &owner_raw, &position).unwrap();
+        }
+        let possible_borrow = |owner: &str| {
+            query_possible_borrow(
+                &deps,
+                &HumanAddr::from(MOCK_CONTRACT_ADDR),
+                None,
+                None,
+                Some("bluna".to_string()),
+                Some(HumanAddr::from(owner)),
+            )
+            .unwrap()
+        };
+
+        // the pool has 50M of room, but the first owner's debt uses up most of theirs
+        let levered = possible_borrow("levered");
+        assert_eq!(levered.position_limit, Uint256::from(50_000_000u64));
+        assert_eq!(levered.position_debt, Uint256::from(20_000_000u64));
+        assert_eq!(levered.borrow_amount, Uint256::from(15_000_000u64));
+        let fresh = possible_borrow("fresh");
+        assert_eq!(fresh.position_debt, Uint256::zero());
+        assert_eq!(fresh.borrow_amount, Uint256::from(35_000_000u64));
+
+        // an owner already past their own target borrows nothing
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(40_000_000));
+        let levered = query_possible_borrow(
+            &deps,
+            &HumanAddr::from(MOCK_CONTRACT_ADDR),
+            None,
+            None,
+            Some("bluna".to_string()),
+            Some(HumanAddr::from("levered")),
+        )
+        .unwrap();
+        assert_eq!(levered.position_debt, Uint256::from(40_000_000u64));
+        assert_eq!(levered.borrow_amount, Uint256::zero());
+    }
+
+    #[test]
+    fn loop_steps_log_a_position_snapshot() {
+        let mut deps = instance();
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(40_000_000));
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(2_000_000));
+        deps.querier
+            .with_response("lunapair", "simulation", &simulation(400_000));
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(2_000_000u64),
+            total_collateral_shares: Uint256::from(2_000_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        let state = State {
+            total_debt_shares: Uint256::from(20_000_000u64),
+            ..State::default()
+        };
+        set_state(&mut deps.storage, &state).unwrap();
+        let depositor = deps
+            .api
+            .canonical_address(&HumanAddr::from("depositor"))
+            .unwrap();
+        let position = Position {
+            collateral_shares: Uint256::from(1_000_000u64),
+            debt_shares: Uint256::from(10_000_000u64),
+            ..Position::default()
+        };
+        set_position(&mut deps.storage, "bluna", &depositor, &position).unwrap();
+
+        let msg = HandleMsg::Swap {
+            collateral: "bluna".to_string(),
+            amount: Uint128(20_000_000),
+            owner: HumanAddr::from("depositor"),
+            iteration: 2,
+        };
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg).unwrap();
+        // half the pooled collateral and half the loan at 50 uusd per bLuna
+        assert_eq!(
+            res.log,
+            vec![
+                log("action", "swap"),
+                log("swap_amount", 20_000_000),
+                log("swap_price", 50),
+                log("collateral", "bluna"),
+                log("owner", "depositor"),
+                log("iteration", 2),
+                log("collateral_after", 1_000_000),
+                log("debt_after", 20_000_000),
+                log("ltv_after", "0.4"),
+                log("exchange_rate", 1),
+            ]
+        );
+        assert_eq!(
+            res.messages.last(),
+            Some(&CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                send: vec![],
+                msg: to_binary(&HandleMsg::Redeposit {
+                    collateral: "bluna".to_string(),
+                    owner: HumanAddr::from("depositor"),
+                    iteration: 2,
+                    balance_before: Uint128::zero(),
+                })
+                .unwrap(),
+            }))
+        );
+    }
+
+    #[test]
+    fn hub_querier_parses_what_the_hub_answers() {
+        let mut deps = instance();
+        let hub = HumanAddr::from("hub");
+        let depositor = HumanAddr::from("depositor");
+
+        // the hub's own JSON, tuples and decimals included
+        let requests: hub_querier::UnbondRequestsResponse = from_binary(&Binary::from(
+            &br#"{"address":"depositor","requests":[[3,"1000"],[4,"250"]]}"#[..],
+        ))
+        .unwrap();
+        assert_eq!(
+            requests.requests,
+            vec![(3, Uint128(1_000)), (4, Uint128(250))]
+        );
+        let parameters: hub_querier::Parameters = from_binary(&Binary::from(
+            &br#"{"epoch_period":21600,"underlying_coin_denom":"uluna","unbonding_period":1814400,"peg_recovery_fee":"0.001","er_threshold":"1","reward_denom":"uusd"}"#[..],
+        ))
+        .unwrap();
+        assert_eq!(parameters.peg_recovery_fee, Decimal::permille(1));
+        assert_eq!(parameters.unbonding_period, 1_814_400);
+
+        deps.querier.with_query_response(
+            "hub",
+            &hub_querier::QueryMsg::UnbondRequests {
+                address: depositor.clone(),
+            },
+            &requests,
+        );
+        assert_eq!(
+            hub_querier::query_unbond_requests(&deps, &hub, &depositor).unwrap(),
+            requests
+        );
+        deps.querier.with_response("hub", "parameters", &parameters);
+        assert_eq!(
+            hub_querier::query_parameters(&deps, &hub).unwrap(),
+            parameters
+        );
+
+        let batch = hub_querier::CurrentBatchResponse {
+            id: 5,
+            requested_with_fee: Uint128(300),
+        };
+        deps.querier.with_response("hub", "current_batch", &batch);
+        assert_eq!(
+            hub_querier::query_current_batch(&deps, &hub).unwrap(),
+            batch
+        );
+
+        let withdrawable = hub_querier::WithdrawableUnbondedResponse {
+            withdrawable: Uint128(1_000),
+        };
+        deps.querier.with_query_response(
+            "hub",
+            &hub_querier::QueryMsg::WithdrawableUnbonded {
+                address: depositor.clone(),
+                block_time: 100,
+            },
+            &withdrawable,
+        );
+        assert_eq!(
+            hub_querier::query_withdrawable_unbonded(&deps, &hub, &depositor, 100).unwrap(),
+            withdrawable
+        );
+
+        let history = hub_querier::AllHistoryResponse {
+            history: vec![hub_querier::UnbondHistory {
+                batch_id: 3,
+                time: 50,
+                amount: Uint128(1_000),
+                applied_exchange_rate: Decimal::one(),
+                withdraw_rate: Decimal::percent(99),
+                released: true,
+            }],
+        };
+        deps.querier.with_query_response(
+            "hub",
+            &hub_querier::QueryMsg::AllHistory {
+                start_from: Some(3),
+                limit: None,
+            },
+            &history,
+        );
+        assert_eq!(
+            hub_querier::query_all_history(&deps, &hub, Some(3), None).unwrap(),
+            history
+        );
+
+        assert_eq!(
+            hub_querier::query_state(&deps, &hub).unwrap(),
+            hub_state(Decimal::one())
+        );
+        assert_eq!(
+            hub_querier::query_whitelisted_validators(&deps, &hub)
+                .unwrap()
+                .validators,
+            vec![HumanAddr::from("val1"), HumanAddr::from("val2")]
+        );
+    }
+
+    #[test]
+    fn unbonding_exit_is_claimed_once_the_batch_is_released() {
+        let mut deps = instance();
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(1_000_000));
+        deps.querier.with_response(
+            "hub",
+            "current_batch",
+            &hub_querier::CurrentBatchResponse {
+                id: 5,
+                requested_with_fee: Uint128::zero(),
+            },
+        );
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(1_000_000u64),
+            total_collateral_shares: Uint256::from(1_000_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        let depositor = deps
+            .api
+            .canonical_address(&HumanAddr::from("depositor"))
+            .unwrap();
+        let position = Position {
+            collateral_shares: Uint256::from(1_000_000u64),
+            ..Position::default()
+        };
+        set_position(&mut deps.storage, "bluna", &depositor, &position).unwrap();
+
+        let res = handle(
+            &mut deps,
+            mock_env("depositor", &[]),
+            HandleMsg::ClaimUnbonded {
+                collateral: "bluna".to_string(),
+                owner: None,
+            },
+        );
+        assert_eq!(
+            res,
+            Err(StdError::generic_err("No unbond requests to claim"))
+        );
+        let res = handle(
+            &mut deps,
+            mock_env("depositor", &[]),
+            HandleMsg::ClaimUnbonded {
+                collateral: "beth".to_string(),
+                owner: None,
+            },
+        );
+        assert_eq!(
+            res,
+            Err(StdError::generic_err(
+                "Collateral 'beth' is not minted by a hub"
+            ))
+        );
+
+        // the rest after the management fee is unbonded in the current batch
+        let msg = HandleMsg::Withdraw {
+            collateral: "bluna".to_string(),
+            unbond: Some(true),
+            owner: None,
+        };
+        let res = handle(&mut deps, mock_env("depositor", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages.last(),
+            Some(&CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from("bluna"),
+                send: vec![],
+                msg: querier::unbond_basset(&HumanAddr::from("hub"), Uint128(990_000)).unwrap(),
+            }))
+        );
+        let requests = vec![UnbondRequest {
+            batch_id: 5,
+            amount: Uint256::from(990_000u64),
+        }];
+        assert_eq!(
+            query_unbond_requests(&deps, "bluna".to_string(), HumanAddr::from("depositor"))
+                .unwrap()
+                .requests,
+            requests
+        );
+
+        // the hub has released something, so the claim withdraws it first
+        deps.querier.with_response(
+            "hub",
+            "withdrawable_unbonded",
+            &hub_querier::WithdrawableUnbondedResponse {
+                withdrawable: Uint128(985_000),
+            },
+        );
+        let msg = HandleMsg::ClaimUnbonded {
+            collateral: "bluna".to_string(),
+            owner: None,
+        };
+        let res = handle(&mut deps, mock_env("depositor", &[]), msg).unwrap();
+        let forward = HandleMsg::ForwardUnbonded {
+            collateral: "bluna".to_string(),
+            owner: HumanAddr::from("depositor"),
+            balance_before: Uint128::zero(),
+        };
+        assert_eq!(
+            res.messages,
+            vec![
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from("hub"),
+                    send: vec![],
+                    msg: querier::withdraw_unbonded().unwrap(),
+                }),
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                    send: vec![],
+                    msg: to_binary(&forward).unwrap(),
+                }),
+            ]
+        );
+
+        // batch 5 was released at a 0.99 withdraw rate
+        deps.querier
+            .with_balance(MOCK_CONTRACT_ADDR, vec![coin(985_000, "uluna")]);
+        let history = |released: bool| hub_querier::AllHistoryResponse {
+            history: vec![hub_querier::UnbondHistory {
+                batch_id: 5,
+                time: 0,
+                amount: Uint128(990_000),
+                applied_exchange_rate: Decimal::one(),
+                withdraw_rate: Decimal::percent(99),
+                released,
+            }],
+        };
+        deps.querier
+            .with_response("hub", "all_history", &history(false));
+        let res = handle(
+            &mut deps,
+            mock_env(MOCK_CONTRACT_ADDR, &[]),
+            forward.clone(),
+        );
+        assert_eq!(
+            res,
+            Err(StdError::generic_err("No unbonding is released yet"))
+        );
+        deps.querier
+            .with_response("hub", "all_history", &history(true));
+        let res = handle(&mut deps, mock_env("depositor", &[]), forward.clone());
+        assert_eq!(res, Err(StdError::unauthorized()));
+        let res = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), forward).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                from_address: HumanAddr::from(MOCK_CONTRACT_ADDR),
+                to_address: HumanAddr::from("depositor"),
+                amount: vec![coin(980_100, "uluna")],
+            })]
+        );
+        assert!(get_unbond_requests(&deps.storage, "bluna", &depositor)
+            .unwrap()
+            .is_empty());
+        // the hub's rounding is kept back from the next deposits
+        assert_eq!(
+            get_collateral_state(&deps.storage, "bluna")
+                .unwrap()
+                .unbonded,
+            Uint256::from(4_900u64)
+        );
+    }
+
+    #[test]
+    fn entry_points_are_rejected_while_a_loop_runs() {
+        let mut deps = dependencies();
+        lock(&mut deps);
+
+        let msg = HandleMsg::Deposit {
+            collateral: "bluna".to_string(),
+            earn_share: None,
+            callback: None,
+            callback_contract: None,
+        };
+        let env = mock_env("depositor", &[coin(1_000_000, "uluna")]);
+        assert!(is_lock_error(handle(&mut deps, env, msg)));
+
+        let msg = HandleMsg::Receive(Cw20ReceiveMsg {
+            sender: HumanAddr::from("depositor"),
+            amount: Uint128(1_000_000),
+            msg: Some(
+                to_binary(&Cw20HookMsg::Deposit {
+                    collateral: "beth".to_string(),
+                    earn_share: None,
+                    callback: None,
+                    callback_contract: None,
+                })
+                .unwrap(),
+            ),
+        });
+        assert!(is_lock_error(handle(&mut deps, mock_env("weth", &[]), msg)));
+
+        // hooks called back mid-loop cannot reenter either
+        let msgs = vec![
+            HandleMsg::Withdraw {
+                collateral: "bluna".to_string(),
+                unbond: None,
+                owner: None,
+            },
+            HandleMsg::ClaimUnbonded {
+                collateral: "bluna".to_string(),
+                owner: None,
+            },
+            HandleMsg::Harvest {},
+            HandleMsg::CompoundRewards {
+                balance_before: Uint128::zero(),
+            },
+            HandleMsg::Reconcile {},
+            HandleMsg::AddHook {
+                contract: HumanAddr::from("hook"),
+                owner: None,
+            },
+        ];
+        for msg in msgs {
+            assert!(is_lock_error(handle(&mut deps, mock_env("hook", &[]), msg)));
+        }
+
+        let msg = HandleMsg::Borrow {
+            collateral: "bluna".to_string(),
+            owner: HumanAddr::from("depositor"),
+            iteration: 1,
+        };
+        let result = handle(&mut deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg);
+        // the step fails without a config, but the lock let it through
+        assert!(result.is_err());
+        assert!(!is_lock_error(result));
+    }
+
+    #[test]
+    fn operators_act_within_their_scopes_until_expiry() {
+        let mut deps = instance();
+        let owner = HumanAddr::from("depositor");
+        let env = mock_env("depositor", &[]);
+        let grant = |operator: &str, scopes: Vec<OperatorScope>, expires: Option<u64>| {
+            HandleMsg::GrantOperator {
+                operator: HumanAddr::from(operator),
+                scopes,
+                expires,
+            }
+        };
+        assert_eq!(
+            handle(
+                &mut deps,
+                env.clone(),
+                grant("depositor", vec![OperatorScope::Deleverage], None)
+            ),
+            Err(StdError::generic_err("An owner cannot be its own operator"))
+        );
+        assert_eq!(
+            handle(&mut deps, env.clone(), grant("riskbot", vec![], None)),
+            Err(StdError::generic_err(
+                "An operator needs at least one scope"
+            ))
+        );
+        assert_eq!(
+            handle(
+                &mut deps,
+                env.clone(),
+                grant(
+                    "riskbot",
+                    vec![OperatorScope::Deleverage],
+                    Some(env.block.time)
+                )
+            ),
+            Err(StdError::generic_err("Expiry must be in the future"))
+        );
+        let expires = env.block.time + 100;
+        let scopes = vec![OperatorScope::Deleverage, OperatorScope::SetTriggers];
+        handle(
+            &mut deps,
+            env.clone(),
+            grant("riskbot", scopes.clone(), Some(expires)),
+        )
+        .unwrap();
+        assert_eq!(
+            query_operators(&deps, owner.clone()).unwrap(),
+            OperatorsResponse {
+                owner: owner.clone(),
+                operators: vec![OperatorResponse {
+                    operator: HumanAddr::from("riskbot"),
+                    scopes,
+                    expires: Some(expires),
+                }],
+            }
+        );
+
+        let position = Position {
+            collateral_shares: Uint256::from(10_000_000u64),
+            ..Position::default()
+        };
+        set_position(
+            &mut deps.storage,
+            "bluna",
+            &deps.api.canonical_address(&owner).unwrap(),
+            &position,
+        )
+        .unwrap();
+
+        // a hook added by the operator belongs to the owner
+        let bot = mock_env("riskbot", &[]);
+        let msg = HandleMsg::AddHook {
+            contract: HumanAddr::from("hook1"),
+            owner: Some(owner.clone()),
+        };
+        handle(&mut deps, bot.clone(), msg).unwrap();
+        assert_eq!(
+            query_hooks(&deps, owner.clone()).unwrap().hooks,
+            vec![HumanAddr::from("hook1")]
+        );
+        assert!(query_hooks(&deps, HumanAddr::from("riskbot"))
+            .unwrap()
+            .hooks
+            .is_empty());
+
+        // rebalancing was not granted
+        let msg = HandleMsg::SetEarnShare {
+            collateral: "bluna".to_string(),
+            earn_share: Decimal256::percent(20),
+            owner: Some(owner.clone()),
+        };
+        assert_eq!(
+            handle(&mut deps, bot.clone(), msg.clone()),
+            Err(StdError::unauthorized())
+        );
+        handle(&mut deps, env, msg).unwrap();
+
+        let withdraw = || HandleMsg::Withdraw {
+            collateral: "bluna".to_string(),
+            unbond: None,
+            owner: Some(HumanAddr::from("depositor")),
+        };
+        assert_eq!(
+            handle(&mut deps, mock_env("stranger", &[]), withdraw()),
+            Err(StdError::unauthorized())
+        );
+        let mut expired = bot.clone();
+        expired.block.time = expires;
+        assert_eq!(
+            handle(&mut deps, expired, withdraw()),
+            Err(StdError::unauthorized())
+        );
+
+        let msg = HandleMsg::RevokeOperator {
+            operator: HumanAddr::from("riskbot"),
+        };
+        handle(&mut deps, mock_env("depositor", &[]), msg.clone()).unwrap();
+        assert_eq!(
+            handle(&mut deps, mock_env("depositor", &[]), msg),
+            Err(StdError::generic_err("riskbot is not an operator"))
+        );
+        assert!(query_operators(&deps, owner).unwrap().operators.is_empty());
+        assert_eq!(
+            handle(&mut deps, bot, withdraw()),
+            Err(StdError::unauthorized())
+        );
+    }
+
+    #[test]
+    fn risk_index_follows_position_changes() {
+        let mut deps = mock_dependencies(20, &[]);
+        let position = |collateral_shares: u64, debt_shares: u64| Position {
+            collateral_shares: Uint256::from(collateral_shares),
+            debt_shares: Uint256::from(debt_shares),
+            ..Position::default()
+        };
+        let owners: Vec<CanonicalAddr> = ["alice", "bob", "carol"]
+            .iter()
+            .map(|owner| {
+                deps.api
+                    .canonical_address(&HumanAddr::from(*owner))
+                    .unwrap()
+            })
+            .collect();
+        set_position(&mut deps.storage, "bluna", &owners[0], &position(100, 30)).unwrap();
+        set_position(&mut deps.storage, "bluna", &owners[1], &position(100, 60)).unwrap();
+        set_position(&mut deps.storage, "bluna", &owners[2], &position(100, 45)).unwrap();
+
+        let by_risk = |deps: &Extern<_, _, _>, start_after: Option<&CanonicalAddr>| {
+            read_positions_by_risk(&deps.storage, "bluna", start_after, None)
+                .unwrap()
+                .into_iter()
+                .map(|(owner, _)| owner)
+                .collect::<Vec<CanonicalAddr>>()
+        };
+        assert_eq!(
+            by_risk(&deps, None),
+            vec![owners[1].clone(), owners[2].clone(), owners[0].clone()]
+        );
+        assert_eq!(by_risk(&deps, Some(&owners[2])), vec![owners[0].clone()]);
+
+        // a repay moves bob behind carol, a close drops carol
+        set_position(&mut deps.storage, "bluna", &owners[1], &position(100, 40)).unwrap();
+        remove_position(&mut deps.storage, "bluna", &owners[2]).unwrap();
+        assert_eq!(
+            by_risk(&deps, None),
+            vec![owners[1].clone(), owners[0].clone()]
+        );
+
+        let by_owner = read_positions(&deps.storage, "bluna", Some(&owners[0]), None).unwrap();
+        assert_eq!(by_owner, vec![(owners[1].clone(), position(100, 40))]);
+    }
+
+    #[test]
+    fn positions_are_listed_by_owner_or_riskiest_first() {
+        let mut deps = instance();
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(40_000_000));
+        let collateral_state = CollateralState {
+            total_collateral: Uint256::from(4_000_000u64),
+            total_collateral_shares: Uint256::from(4_000_000u64),
+            ..CollateralState::default()
+        };
+        set_collateral_state(&mut deps.storage, "bluna", &collateral_state).unwrap();
+        let state = State {
+            total_debt_shares: Uint256::from(20_000_000u64),
+            ..State::default()
+        };
+        set_state(&mut deps.storage, &state).unwrap();
+        for (owner, collateral_shares) in &[("alice", 1_000_000u64), ("bob", 3_000_000u64)] {
+            let owner = deps
+                .api
+                .canonical_address(&HumanAddr::from(*owner))
+                .unwrap();
+            let position = Position {
+                collateral_shares: Uint256::from(*collateral_shares),
+                debt_shares: Uint256::from(10_000_000u64),
+                ..Position::default()
+            };
+            set_position(&mut deps.storage, "bluna", &owner, &position).unwrap();
+        }
+
+        // both owe 20M uusd, alice against 1M bLuna worth 50M uusd
+        let alice = PositionSummary {
+            owner: HumanAddr::from("alice"),
+            collateral_shares: Uint256::from(1_000_000u64),
+            debt_shares: Uint256::from(10_000_000u64),
+            balance: Uint256::from(1_000_000u64),
+            debt: Uint256::from(20_000_000u64),
+            ltv: Decimal256::percent(40),
+        };
+        let bob = PositionSummary {
+            owner: HumanAddr::from("bob"),
+            collateral_shares: Uint256::from(3_000_000u64),
+            debt_shares: Uint256::from(10_000_000u64),
+            balance: Uint256::from(3_000_000u64),
+            debt: Uint256::from(20_000_000u64),
+            ltv: Decimal256::from_ratio(2, 15),
+        };
+        let positions = |start_after: Option<&str>, limit, order_by| {
+            query_positions(
+                &deps,
+                "bluna".to_string(),
+                start_after.map(HumanAddr::from),
+                limit,
+                order_by,
+            )
+            .unwrap()
+            .positions
+        };
+        assert_eq!(
+            positions(None, None, None),
+            vec![alice.clone(), bob.clone()]
+        );
+        assert_eq!(
+            positions(Some("alice"), None, Some(PositionsOrder::Owner)),
+            vec![bob.clone()]
+        );
+        assert_eq!(
+            positions(None, Some(1), Some(PositionsOrder::Risk)),
+            vec![alice.clone()]
+        );
+        assert_eq!(
+            positions(Some("alice"), Some(1), Some(PositionsOrder::Risk)),
+            vec![bob]
+        );
+        assert_eq!(
+            query_positions(
+                &deps,
+                "bluna".to_string(),
+                Some(HumanAddr::from("carol")),
+                None,
+                Some(PositionsOrder::Risk),
+            ),
+            Err(StdError::generic_err("start_after has no position"))
+        );
+    }
+
+    fn next_step(res: &HandleResponse) -> HandleMsg {
+        match res.messages.last() {
+            Some(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr, msg, ..
+            })) if contract_addr == &HumanAddr::from(MOCK_CONTRACT_ADDR) => {
+                from_binary(msg).unwrap()
+            }
+            last => panic!("no next step in {:?}", last),
+        }
+    }
+
+    fn run_step(
+        deps: &mut Extern<MockStorage, MockApi, WasmMockQuerier>,
+        msg: HandleMsg,
+    ) -> HandleResponse {
+        handle(deps, mock_env(MOCK_CONTRACT_ADDR, &[]), msg).unwrap()
+    }
+
+    #[test]
+    fn outsiders_wait_for_the_whole_deposit_and_withdraw_chain() {
+        let mut deps = instance();
+        let borrow_limit = |borrow_limit: u64| moneymarket::overseer::BorrowLimitResponse {
+            borrower: HumanAddr::from(MOCK_CONTRACT_ADDR),
+            borrow_limit: Uint256::from(borrow_limit),
+        };
+        let deposit = HandleMsg::Deposit {
+            collateral: "bluna".to_string(),
+            earn_share: None,
+            callback: None,
+            callback_contract: None,
+        };
+        let is_locked = |deps: &Extern<_, _, _>| get_state(&deps.storage).unwrap().loop_in_progress;
+        let outsider = |deps: &mut Extern<_, _, _>| {
+            let env = mock_env("outsider", &[coin(1_000_000, "uluna")]);
+            assert!(is_lock_error(handle(deps, env, deposit.clone())));
+            let msg = HandleMsg::Withdraw {
+                collateral: "bluna".to_string(),
+                unbond: None,
+                owner: None,
+            };
+            assert!(is_lock_error(handle(deps, mock_env("outsider", &[]), msg)));
+        };
+        deps.querier
+            .with_response("custody", "borrower", &custody_balance(13_500_000));
+
+        let env = mock_env("depositor", &[coin(10_000_000, "uluna")]);
+        let res = handle(&mut deps, env, deposit.clone()).unwrap();
+        assert!(is_locked(&deps));
+        outsider(&mut deps);
+
+        // the hub minted 10M bLuna, 70% of their 250M uusd borrow limit is
+        // borrowed and swapped for 3.5M uluna
+        deps.querier
+            .with_token_balance("bluna", MOCK_CONTRACT_ADDR, 10_000_000);
+        let res = run_step(&mut deps, next_step(&res));
+        deps.querier
+            .with_response("overseer", "borrow_limit", &borrow_limit(250_000_000));
+        let res = run_step(&mut deps, next_step(&res));
+        deps.querier
+            .with_response("lunapair", "simulation", &simulation(3_500_000));
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(175_000_000));
+        let res = run_step(&mut deps, next_step(&res));
+        outsider(&mut deps);
+        deps.querier
+            .with_balance(MOCK_CONTRACT_ADDR, vec![coin(3_500_000, "uluna")]);
+        let res = run_step(&mut deps, next_step(&res));
+        assert!(is_locked(&deps));
+
+        // every step logs a snapshot of the position it leaves behind
+        deps.querier
+            .with_token_balance("bluna", MOCK_CONTRACT_ADDR, 13_500_000);
+        let res = run_step(&mut deps, next_step(&res));
+        assert!(res.log.contains(&log("iteration", 1)));
+        assert!(res.log.contains(&log("collateral_after", 13_500_000)));
+        assert!(res.log.contains(&log("debt_after", 175_000_000)));
+        assert!(res.log.contains(&log("ltv_after", "0.259259259259259259")));
+        assert!(res.log.contains(&log("exchange_rate", 1)));
+
+        // the second round's loan is too small to swap, which ends the loop
+        outsider(&mut deps);
+        deps.querier
+            .with_response("overseer", "borrow_limit", &borrow_limit(260_000_000));
+        let res = run_step(&mut deps, next_step(&res));
+        assert!(res.messages.is_empty());
+        assert!(!is_locked(&deps));
+
+        let owner_raw = deps
+            .api
+            .canonical_address(&HumanAddr::from("depositor"))
+            .unwrap();
+        let position = get_position(&deps.storage, "bluna", &owner_raw).unwrap();
+        assert_eq!(position.collateral_shares, Uint256::from(13_500_000u64));
+        assert_eq!(position.debt_shares, Uint256::from(175_000_000u64));
+
+        // unwinding sells what the borrow limit frees up, repays the loan and
+        // releases the rest once the debt is gone
+        deps.querier
+            .with_response("overseer", "borrow_limit", &borrow_limit(337_500_000));
+        deps.querier.with_balance(MOCK_CONTRACT_ADDR, vec![]);
+        let msg = HandleMsg::Withdraw {
+            collateral: "bluna".to_string(),
+            unbond: None,
+            owner: None,
+        };
+        let res = handle(&mut deps, mock_env("depositor", &[]), msg).unwrap();
+        assert!(is_locked(&deps));
+        outsider(&mut deps);
+        deps.querier
+            .with_balance(MOCK_CONTRACT_ADDR, vec![coin(5_800_000, "uluna")]);
+        deps.querier
+            .with_response("lunapair", "simulation", &simulation(290_000_000));
+        let res = run_step(&mut deps, next_step(&res));
+        deps.querier
+            .with_balance(MOCK_CONTRACT_ADDR, vec![coin(290_000_000, TERRASWAP_PAIR)]);
+        let res = run_step(&mut deps, next_step(&res));
+        outsider(&mut deps);
+        assert!(get_position(&deps.storage, "bluna", &owner_raw)
+            .unwrap()
+            .debt_shares
+            .is_zero());
+
+        deps.querier
+            .with_response("market", "borrower_info", &borrower_info(0));
+        deps.querier.with_balance(MOCK_CONTRACT_ADDR, vec![]);
+        let res = run_step(&mut deps, next_step(&res));
+        assert!(!is_locked(&deps));
+        assert!(res.log.contains(&log("collateral_after", 0)));
+        assert!(res.log.contains(&log("debt_after", 0)));
+        assert!(res.log.contains(&log("ltv_after", 0)));
+        assert_eq!(
+            res.messages.last(),
+            Some(&CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: HumanAddr::from("bluna"),
+                send: vec![],
+                msg: querier::sell_token(
+                    &HumanAddr::from("blunapair"),
+                    Uint128(7_573_500),
+                    Decimal::from_ratio(7_573_500u128, 980_000u128),
+                    Decimal::percent(1),
+                    Some(HumanAddr::from("depositor")),
+                )
+                .unwrap(),
+            }))
+        );
+        assert_eq!(
+            get_position(&deps.storage, "bluna", &owner_raw).unwrap(),
+            Position::default()
+        );
+    }
 }
@@ -1,7 +1,9 @@
 pub mod contract;
+#[cfg(test)]
+mod mock_querier;
 pub mod msg;
-pub mod state;
 pub mod querier;
+pub mod state;
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(target_arch = "wasm32", not(feature = "library")))]
 cosmwasm_std::create_entry_points!(contract);
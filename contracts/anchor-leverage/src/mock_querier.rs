@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use cosmwasm_std::testing::{MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR};
+use cosmwasm_std::{
+    from_binary, from_slice, to_binary, Api, Binary, CanonicalAddr, Coin, Decimal, Extern,
+    FullDelegation, HumanAddr, Querier, QuerierResult, QueryRequest, StdError, SystemError,
+    Uint128, WasmQuery,
+};
+use cosmwasm_storage::to_length_prefixed;
+use serde::Serialize;
+use terra_cosmwasm::{TaxCapResponse, TaxRateResponse, TerraQuery, TerraQueryWrapper};
+
+/// Dependencies whose querier answers the wasm, bank and treasury queries the
+/// contract makes with whatever the test set up
+pub fn mock_dependencies(
+    canonical_length: usize,
+    contract_balance: &[Coin],
+) -> Extern<MockStorage, MockApi, WasmMockQuerier> {
+    let contract_addr = HumanAddr::from(MOCK_CONTRACT_ADDR);
+    Extern {
+        storage: MockStorage::default(),
+        api: MockApi::new(canonical_length),
+        querier: WasmMockQuerier::new(
+            MockQuerier::new(&[(&contract_addr, contract_balance)]),
+            MockApi::new(canonical_length),
+        ),
+    }
+}
+
+pub struct WasmMockQuerier {
+    base: MockQuerier<TerraQueryWrapper>,
+    api: MockApi,
+    /// Answers to one exact query of a contract
+    queries: HashMap<(HumanAddr, Vec<u8>), Binary>,
+    /// Answers to every query of a kind keyed by its variant name
+    responses: HashMap<(HumanAddr, String), Result<Binary, String>>,
+    /// cw20 balances by token and holder, for smart and raw queries alike
+    token_balances: HashMap<HumanAddr, HashMap<HumanAddr, Uint128>>,
+    tax_rate: Decimal,
+    tax_cap: Uint128,
+}
+
+impl Querier for WasmMockQuerier {
+    fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+        let request: QueryRequest<TerraQueryWrapper> = match from_slice(bin_request) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(SystemError::InvalidRequest {
+                    error: format!("Parsing query request: {}", e),
+                    request: bin_request.into(),
+                })
+            }
+        };
+        self.handle_query(&request)
+    }
+}
+
+impl WasmMockQuerier {
+    pub fn new(base: MockQuerier<TerraQueryWrapper>, api: MockApi) -> Self {
+        WasmMockQuerier {
+            base,
+            api,
+            queries: HashMap::new(),
+            responses: HashMap::new(),
+            token_balances: HashMap::new(),
+            tax_rate: Decimal::zero(),
+            tax_cap: Uint128::zero(),
+        }
+    }
+
+    pub fn handle_query(&self, request: &QueryRequest<TerraQueryWrapper>) -> QuerierResult {
+        match request {
+            QueryRequest::Custom(TerraQueryWrapper { query_data, .. }) => match query_data {
+                TerraQuery::TaxRate {} => Ok(to_binary(&TaxRateResponse {
+                    rate: self.tax_rate,
+                })),
+                TerraQuery::TaxCap { .. } => Ok(to_binary(&TaxCapResponse { cap: self.tax_cap })),
+                _ => Err(SystemError::UnsupportedRequest {
+                    kind: "terra".to_string(),
+                }),
+            },
+            QueryRequest::Wasm(WasmQuery::Smart { contract_addr, msg }) => {
+                if let Some(response) = self
+                    .queries
+                    .get(&(contract_addr.clone(), msg.as_slice().to_vec()))
+                {
+                    return Ok(Ok(response.clone()));
+                }
+                if let Ok(cw20::Cw20QueryMsg::Balance { address }) = from_binary(msg) {
+                    let balance = self.token_balance(contract_addr, &address);
+                    return Ok(to_binary(&cw20::BalanceResponse { balance }));
+                }
+                let name = query_name(msg);
+                match self.responses.get(&(contract_addr.clone(), name.clone())) {
+                    Some(response) => Ok(response.clone().map_err(StdError::generic_err)),
+                    None => Err(SystemError::UnsupportedRequest {
+                        kind: format!("{} on {}", name, contract_addr),
+                    }),
+                }
+            }
+            QueryRequest::Wasm(WasmQuery::Raw { contract_addr, key }) => {
+                let prefix = to_length_prefixed(b"balance");
+                if !key.as_slice().starts_with(&prefix) {
+                    return Err(SystemError::UnsupportedRequest {
+                        kind: format!("raw query on {}", contract_addr),
+                    });
+                }
+                let holder = self
+                    .api
+                    .human_address(&CanonicalAddr(Binary::from(
+                        &key.as_slice()[prefix.len()..],
+                    )))
+                    .unwrap();
+                let balance = self.token_balance(contract_addr, &holder);
+                // the stored value comes back as binary for the caller to parse
+                Ok(to_binary(&to_binary(&balance).unwrap()))
+            }
+            _ => self.base.handle_query(request),
+        }
+    }
+
+    /// Answers every `query` (the variant name, e.g. "state") sent to `contract`
+    pub fn with_response<T: Serialize>(&mut self, contract: &str, query: &str, response: &T) {
+        self.responses.insert(
+            (HumanAddr::from(contract), query.to_string()),
+            Ok(to_binary(response).unwrap()),
+        );
+    }
+
+    /// Fails every `query` sent to `contract` with a generic `error`
+    pub fn with_error(&mut self, contract: &str, query: &str, error: &str) {
+        self.responses.insert(
+            (HumanAddr::from(contract), query.to_string()),
+            Err(error.to_string()),
+        );
+    }
+
+    /// Answers exactly `query` sent to `contract`, ahead of the answers by name
+    pub fn with_query_response<Q: Serialize, T: Serialize>(
+        &mut self,
+        contract: &str,
+        query: &Q,
+        response: &T,
+    ) {
+        self.queries.insert(
+            (
+                HumanAddr::from(contract),
+                to_binary(query).unwrap().as_slice().to_vec(),
+            ),
+            to_binary(response).unwrap(),
+        );
+    }
+
+    fn token_balance(&self, token: &HumanAddr, holder: &HumanAddr) -> Uint128 {
+        self.token_balances
+            .get(token)
+            .and_then(|balances| balances.get(holder))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn with_token_balance(&mut self, token: &str, holder: &str, balance: u128) {
+        self.token_balances
+            .entry(HumanAddr::from(token))
+            .or_default()
+            .insert(HumanAddr::from(holder), Uint128(balance));
+    }
+
+    pub fn with_balance(&mut self, addr: &str, balance: Vec<Coin>) {
+        self.base.update_balance(addr, balance);
+    }
+
+    pub fn with_delegation(&mut self, delegator: &str, amount: u128) {
+        let coin = Coin::new(amount, "uluna");
+        self.base.update_staking(
+            "uluna",
+            &[],
+            &[FullDelegation {
+                delegator: HumanAddr::from(delegator),
+                validator: HumanAddr::from("validator"),
+                amount: coin.clone(),
+                can_redelegate: coin,
+                accumulated_rewards: Coin::new(0, "uluna"),
+            }],
+        );
+    }
+
+    pub fn with_tax(&mut self, rate: Decimal, cap: u128) {
+        self.tax_rate = rate;
+        self.tax_cap = Uint128(cap);
+    }
+}
+
+/// Every query enum serializes as `{"variant":{...}}`
+fn query_name(msg: &Binary) -> String {
+    String::from_utf8_lossy(msg.as_slice())
+        .trim_start_matches("{\"")
+        .split('"')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
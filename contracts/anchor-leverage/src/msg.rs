@@ -1,36 +1,303 @@
-use cosmwasm_bignumber::Uint256;
-use cosmwasm_std::{HumanAddr, Uint128};
+use cosmwasm_bignumber::{Decimal256, Uint256};
+use cosmwasm_std::{Binary, HumanAddr, Uint128};
+use cw20::Cw20ReceiveMsg;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use terraswap::hook::InitHook;
 
-use crate::state::ConfigMsg;
+use crate::state::{CollateralMsg, ConfigMsg, OperatorScope, UnbondRequest, Validator};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InitMsg {
     pub config: ConfigMsg,
+    /// Defaults to the instantiating address
+    pub owner: Option<HumanAddr>,
+    /// Who may change the fee settings, defaults to the instantiating address
+    pub fee_admin: Option<HumanAddr>,
+    /// Called once the contract is created, e.g. to register it with a factory
+    pub init_hook: Option<InitHook>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum HandleMsg {
-    Deposit {},
-    DepositCollateral {},
-    Borrow {},
-    Swap { amount: Uint128 },
-    Redeposit {},
+    /// Deposits a native deposit asset of the collateral, `earn_share` of
+    /// every loan against the position is parked in Anchor Earn. With a
+    /// `callback` the `callback_contract` gets a DepositCallbackMsg once the loop is done
+    Deposit {
+        collateral: String,
+        earn_share: Option<Decimal256>,
+        callback: Option<Binary>,
+        callback_contract: Option<HumanAddr>,
+    },
+    /// Deposits a cw20 deposit asset, see Cw20HookMsg
+    Receive(Cw20ReceiveMsg),
+    /// Loop steps sent by the contract to itself, `iteration` counts the
+    /// rounds of the loop so the logs of every step can be told apart.
+    /// `balance_before` is the contract's bAsset balance before the mint or
+    /// swap of the round, only the increase over it is locked
+    DepositCollateral {
+        collateral: String,
+        owner: HumanAddr,
+        iteration: u32,
+        balance_before: Uint128,
+    },
+    Borrow {
+        collateral: String,
+        owner: HumanAddr,
+        iteration: u32,
+    },
+    CreditEarn {
+        collateral: String,
+        owner: HumanAddr,
+        iteration: u32,
+    },
+    Swap {
+        collateral: String,
+        amount: Uint128,
+        owner: HumanAddr,
+        iteration: u32,
+    },
+    /// Deposits the increase of the deposit asset balance over `balance_before`
+    /// the swap of the loan left behind
+    Redeposit {
+        collateral: String,
+        owner: HumanAddr,
+        iteration: u32,
+        balance_before: Uint128,
+    },
+    /// Adds a collateral to the registry or updates its contracts
+    RegisterCollateral {
+        collateral: CollateralMsg,
+    },
+    UpdateValidators {
+        collateral: String,
+        validators: Vec<Validator>,
+    },
+    UpdateConfig {
+        owner: Option<HumanAddr>,
+        max_total_deposit: Option<Uint256>,
+        max_user_deposit: Option<Uint256>,
+        min_deposit: Option<Uint256>,
+        max_slippage: Option<Decimal256>,
+    },
+    /// Fee settings, only the fee admin may change them
+    UpdateFees {
+        fee_admin: Option<HumanAddr>,
+        fee_collector: Option<HumanAddr>,
+        performance_fee: Option<Decimal256>,
+        management_fee: Option<Decimal256>,
+    },
+    /// Unwinds the sender's position and pays it out in the deposit asset,
+    /// with `unbond` what is left after the debt is unbonded at the hub
+    /// instead of sold and paid out by ClaimUnbonded once released. An
+    /// operator passes the `owner` it acts for, who is paid all the same.
+    /// uusd sent along repays the debt first, which unwinds a position too
+    /// close to its borrow limit to unlock any collateral
+    Withdraw {
+        collateral: String,
+        unbond: Option<bool>,
+        owner: Option<HumanAddr>,
+    },
+    /// Exit loop steps, counted like the deposit loop
+    Deleverage {
+        collateral: String,
+        owner: HumanAddr,
+        iteration: u32,
+        unbond: bool,
+    },
+    /// Sells the increase of the deposit asset balance over `balance_before`
+    /// the sale of the unlocked bAsset left behind
+    SwapToStable {
+        collateral: String,
+        owner: HumanAddr,
+        iteration: u32,
+        unbond: bool,
+        balance_before: Uint128,
+    },
+    /// Repays with the increase of the uusd balance over `balance_before`
+    Repay {
+        collateral: String,
+        owner: HumanAddr,
+        iteration: u32,
+        unbond: bool,
+        balance_before: Uint128,
+    },
+    /// Withdraws released unbondings from the hub and pays the owner's share
+    ClaimUnbonded {
+        collateral: String,
+        owner: Option<HumanAddr>,
+    },
+    /// Pays the owner for the released batches once the hub has sent what
+    /// raised the contract's balance over `balance_before`
+    ForwardUnbonded {
+        collateral: String,
+        owner: HumanAddr,
+        balance_before: Uint128,
+    },
+    /// Claims ANC borrower rewards and repays the pooled loan with them
+    Harvest {},
+    /// Sells the claimed ANC, `balance_before` is the stable held before the claim
+    SellRewards {
+        balance_before: Uint128,
+    },
+    /// Repays the pooled loan with what the sale raised the stable balance
+    /// over `balance_before` by
+    CompoundRewards {
+        balance_before: Uint128,
+    },
+    /// Sends accrued fees to the fee collector
+    ClaimFees {},
+    /// Catches state up with liquidations, which every handler does first anyway,
+    /// and notifies the hooks of the depositors of every liquidation since the last call,
+    /// a page of owners per call until all of them have been notified
+    Reconcile {},
+    /// Subscribes `contract` to PositionHookMsg callbacks on changes of the sender's positions,
+    /// callbacks sent while a loop runs cannot call back into the contract. Only
+    /// owners of a position can add hooks
+    AddHook {
+        contract: HumanAddr,
+        owner: Option<HumanAddr>,
+    },
+    RemoveHook {
+        contract: HumanAddr,
+        owner: Option<HumanAddr>,
+    },
+    /// Changes the part of future loans against a position parked in Anchor Earn
+    SetEarnShare {
+        collateral: String,
+        earn_share: Decimal256,
+        owner: Option<HumanAddr>,
+    },
+    /// Lets `operator` act for the sender within `scopes` until block time
+    /// `expires`, replacing what it was granted before
+    GrantOperator {
+        operator: HumanAddr,
+        scopes: Vec<OperatorScope>,
+        expires: Option<u64>,
+    },
+    RevokeOperator {
+        operator: HumanAddr,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw20HookMsg {
+    /// Deposits the sent cw20 deposit asset of the collateral
+    Deposit {
+        collateral: String,
+        earn_share: Option<Decimal256>,
+        callback: Option<Binary>,
+        callback_contract: Option<HumanAddr>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
+    /// Borrow headroom of `contract_addr`, with a `collateral` and `owner`
+    /// bounded by what that position can borrow against its own collateral
     PossibleBorrow {
         contract_addr: HumanAddr,
         block_time: Option<u64>,
         block_height: Option<u64>,
+        collateral: Option<String>,
+        owner: Option<HumanAddr>,
     },
     Collateral {
+        collateral: String,
         contract_addr: HumanAddr,
     },
+    Collaterals {},
+    Validators {
+        collateral: String,
+    },
+    State {},
+    Position {
+        collateral: String,
+        owner: HumanAddr,
+    },
+    Pnl {
+        collateral: String,
+        owner: HumanAddr,
+    },
+    Liquidations {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    Slashings {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    UnbondRequests {
+        collateral: String,
+        owner: HumanAddr,
+    },
+    /// Estimated yearly return at `leverage`, defaults to what the loop reaches
+    /// with `earn_share` of every loan parked in Anchor Earn
+    Apy {
+        collateral: String,
+        leverage: Option<Decimal256>,
+        earn_share: Option<Decimal256>,
+    },
+    Hooks {
+        owner: HumanAddr,
+    },
+    Operators {
+        owner: HumanAddr,
+    },
+    /// Positions in a collateral, by owner or with the highest LTV first
+    Positions {
+        collateral: String,
+        start_after: Option<HumanAddr>,
+        limit: Option<u32>,
+        order_by: Option<PositionsOrder>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionsOrder {
+    Owner,
+    Risk,
+}
+
+/// Callback sent to the hooks of an owner, a hook contract handles it as a
+/// variant of its own HandleMsg
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionHookMsg {
+    PositionChanged {
+        collateral: String,
+        owner: HumanAddr,
+        change: PositionChange,
+    },
+}
+
+/// Sent to the `callback_contract` of a Deposit after the last round of its
+/// loop, `msg` is the `callback` the deposit was made with
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DepositCallbackMsg {
+    DepositCompleted {
+        collateral: String,
+        owner: HumanAddr,
+        bonded: Uint256,
+        borrowed: Uint256,
+        iterations: u32,
+        msg: Binary,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionChange {
+    Deposit,
+    Borrow,
+    Deleverage,
+    Withdraw,
+    Liquidation,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -44,6 +311,178 @@ pub enum HandleAnswer {
 #[serde(rename_all = "snake_case")]
 pub struct PossibleBorrowResponse {
     pub borrow_limit: Uint256,
+    /// Part of the borrow limit the position's collateral brings in, zero without a position
+    pub position_limit: Uint256,
+    /// Part of the position limit backed by value slashed off its collateral
+    pub slashing_haircut: Uint256,
     pub already_borrowed: Uint256,
+    /// Position's share of the loan, zero without a position
+    pub position_debt: Uint256,
     pub borrow_amount: Uint256,
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ValidatorsResponse {
+    pub validators: Vec<Validator>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CollateralsResponse {
+    pub collaterals: Vec<CollateralMsg>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct StateResponse {
+    pub total_debt_shares: Uint256,
+    pub total_aust: Uint256,
+    pub total_deposit: Uint256,
+    pub fee_stable: Uint256,
+    pub collaterals: Vec<CollateralStateResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CollateralStateResponse {
+    pub collateral: String,
+    pub total_collateral: Uint256,
+    pub total_collateral_shares: Uint256,
+    pub fee_basset: Uint256,
+    pub unbonded: Uint256,
+    pub exchange_rate: Decimal256,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PositionResponse {
+    pub collateral: String,
+    pub owner: HumanAddr,
+    pub collateral_shares: Uint256,
+    pub debt_shares: Uint256,
+    /// bAsset attributed to the owner
+    pub balance: Uint256,
+    /// Part of the pooled loan attributed to the owner
+    pub debt: Uint256,
+    pub earn_share: Decimal256,
+    pub aust: Uint256,
+    /// Hub exchange rate the collateral was minted at
+    pub exchange_rate: Decimal256,
+}
+
+/// Signed amount, `negative` is set for a loss
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PnlAmount {
+    pub amount: Uint256,
+    pub negative: bool,
+}
+
+/// Amounts without a suffix are in the deposit asset of the collateral
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PnlResponse {
+    pub collateral: String,
+    pub owner: HumanAddr,
+    pub cost_basis: Uint256,
+    pub cost_basis_uusd: Uint256,
+    /// Collateral at the mint rate and oracle price plus aUST minus attributed debt
+    pub net_value: PnlAmount,
+    pub net_value_uusd: PnlAmount,
+    pub unrealized: PnlAmount,
+    pub unrealized_uusd: PnlAmount,
+    /// Proceeds of exited positions minus their cost basis
+    pub realized: PnlAmount,
+    pub realized_uusd: PnlAmount,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ApyResponse {
+    pub collateral: String,
+    pub leverage: Decimal256,
+    pub earn_share: Decimal256,
+    pub staking_apr: Decimal256,
+    pub borrow_apr: Decimal256,
+    pub distribution_apr: Decimal256,
+    /// Anchor Earn deposit rate the parked loans earn as aUST
+    pub deposit_apr: Decimal256,
+    /// Yearly return on the deposit, `negative` is set when borrowing costs more than it earns
+    pub net_apy: Decimal256,
+    pub negative: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LiquidationResponse {
+    pub id: u64,
+    pub collateral: String,
+    pub block_height: u64,
+    pub seized: Uint256,
+    pub loan: Uint256,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct LiquidationsResponse {
+    pub liquidations: Vec<LiquidationResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SlashingResponse {
+    pub id: u64,
+    pub collateral: String,
+    pub block_height: u64,
+    pub previous_rate: Decimal256,
+    pub exchange_rate: Decimal256,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SlashingsResponse {
+    pub slashings: Vec<SlashingResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UnbondRequestsResponse {
+    pub collateral: String,
+    pub owner: HumanAddr,
+    pub requests: Vec<UnbondRequest>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HooksResponse {
+    pub owner: HumanAddr,
+    pub hooks: Vec<HumanAddr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OperatorResponse {
+    pub operator: HumanAddr,
+    pub scopes: Vec<OperatorScope>,
+    pub expires: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OperatorsResponse {
+    pub owner: HumanAddr,
+    pub operators: Vec<OperatorResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PositionSummary {
+    pub owner: HumanAddr,
+    pub collateral_shares: Uint256,
+    pub debt_shares: Uint256,
+    pub balance: Uint256,
+    pub debt: Uint256,
+    pub ltv: Decimal256,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PositionsResponse {
+    pub collateral: String,
+    pub positions: Vec<PositionSummary>,
+}
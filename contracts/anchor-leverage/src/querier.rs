@@ -1,11 +1,14 @@
-use cosmwasm_bignumber::Uint256;
+use std::cmp::min;
+
+use cosmwasm_bignumber::{Decimal256, Uint256};
 use cosmwasm_std::{
-    to_binary, Api, Binary, Decimal, Extern, HumanAddr, Querier, QueryRequest, StdResult, Storage,
-    Uint128, WasmQuery,
+    to_binary, Api, Binary, Decimal, Extern, HumanAddr, Querier, QueryRequest, StdError, StdResult,
+    Storage, Uint128, WasmQuery,
 };
 
 use cw20::Cw20HandleMsg;
-use hub_querier::StateResponse;
+use router_querier::{SimulateSwapOperationsResponse, SwapOperation};
+use terra_cosmwasm::TerraQuerier;
 
 use crate::state::Config;
 
@@ -13,15 +16,350 @@ pub fn query_bonded_exchange_rate<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     basset_hub_contract: &HumanAddr,
 ) -> StdResult<Decimal> {
+    Ok(hub_querier::query_state(deps, basset_hub_contract)?.exchange_rate)
+}
+
+/// Whether the hub's delegations fell short of what it bonded, its exchange
+/// rate only drops for the slashing once CheckSlashing has run
+pub fn query_hub_slashed<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    basset_hub_contract: &HumanAddr,
+) -> StdResult<bool> {
+    let bonded = hub_querier::query_state(deps, basset_hub_contract)?.total_bond_amount;
+    let delegated: u128 = deps
+        .querier
+        .query_all_delegations(basset_hub_contract)?
+        .iter()
+        .map(|delegation| delegation.amount.amount.u128())
+        .sum();
+    Ok(delegated < bonded.u128())
+}
+
+/// Treasury tax rate and the uusd tax cap
+fn query_tax<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<(Decimal256, Uint256)> {
+    let terra_querier = TerraQuerier::new(&deps.querier);
+    let tax_rate = Decimal256::from(terra_querier.query_tax_rate()?.rate);
+    let tax_cap = Uint256::from(
+        terra_querier
+            .query_tax_cap(crate::contract::TERRASWAP_PAIR)?
+            .cap,
+    );
+    Ok((tax_rate, tax_cap))
+}
+
+/// Tax the chain charges on top of sending `amount`, truncated and capped
+fn tax_on(amount: Uint256, tax_rate: Decimal256, tax_cap: Uint256) -> Uint256 {
+    min(amount * tax_rate, tax_cap)
+}
+
+/// uusd tax paid on top of sending `amount` of uusd
+pub fn compute_tax<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    amount: Uint256,
+) -> StdResult<Uint256> {
+    let (tax_rate, tax_cap) = query_tax(deps)?;
+    Ok(tax_on(amount, tax_rate, tax_cap))
+}
+
+/// Largest amount of uusd that can be sent out of `balance` with its tax paid
+pub fn deduct_tax<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    balance: Uint256,
+) -> StdResult<Uint256> {
+    let (tax_rate, tax_cap) = query_tax(deps)?;
+    if balance.is_zero() {
+        return Ok(balance);
+    }
+
+    // estimate from the uncapped rate, then settle the rounding one unit at a time
+    let mut amount = min(balance / (Decimal256::one() + tax_rate), balance);
+    if balance > tax_cap && amount < balance - tax_cap {
+        amount = balance - tax_cap;
+    }
+    while !amount.is_zero() && amount + tax_on(amount, tax_rate, tax_cap) > balance {
+        amount = amount - Uint256::one();
+    }
+    while amount + Uint256::one() + tax_on(amount + Uint256::one(), tax_rate, tax_cap) <= balance {
+        amount += Uint256::one();
+    }
+    Ok(amount)
+}
+
+/// uusd per bAsset from the Anchor oracle
+pub fn query_basset_price<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    basset_token: &HumanAddr,
+) -> StdResult<Decimal256> {
+    Ok(moneymarket::querier::query_price(
+        deps,
+        &deps.api.human_address(&config.anchor_oracle_contract)?,
+        basset_token.to_string(),
+        crate::contract::TERRASWAP_PAIR.to_string(),
+        None,
+    )?
+    .rate)
+}
+
+/// Loan to value ratio the overseer allows for the bAsset
+pub fn query_max_ltv<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    basset_token: &HumanAddr,
+) -> StdResult<Decimal256> {
+    let whitelist = deps
+        .querier
+        .query::<moneymarket::overseer::WhitelistResponse>(&QueryRequest::Wasm(
+            WasmQuery::Smart {
+                contract_addr: deps.api.human_address(&config.anchor_overseer_contract)?,
+                msg: to_binary(&moneymarket::overseer::QueryMsg::Whitelist {
+                    collateral_token: Some(basset_token.clone()),
+                    start_after: None,
+                    limit: None,
+                })?,
+            },
+        ))?;
+    match whitelist.elems.first() {
+        Some(elem) => Ok(elem.max_ltv),
+        None => Err(StdError::generic_err(format!(
+            "'{}' is not whitelisted by the overseer",
+            basset_token
+        ))),
+    }
+}
+
+pub fn query_market_config<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+) -> StdResult<moneymarket::market::ConfigResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: deps.api.human_address(&config.anchor_market_contract)?,
+        msg: to_binary(&moneymarket::market::QueryMsg::Config {})?,
+    }))
+}
+
+pub fn query_market_state<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    block_height: Option<u64>,
+) -> StdResult<moneymarket::market::StateResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: deps.api.human_address(&config.anchor_market_contract)?,
+        msg: to_binary(&moneymarket::market::QueryMsg::State { block_height })?,
+    }))
+}
+
+pub fn query_overseer_config<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+) -> StdResult<moneymarket::overseer::ConfigResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: deps.api.human_address(&config.anchor_overseer_contract)?,
+        msg: to_binary(&moneymarket::overseer::QueryMsg::Config {})?,
+    }))
+}
+
+/// Deposit rate per block Anchor Earn pays on aUST
+pub fn query_deposit_rate<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+) -> StdResult<Decimal256> {
+    Ok(deps
+        .querier
+        .query::<moneymarket::overseer::DistributionParamsResponse>(&QueryRequest::Wasm(
+            WasmQuery::Smart {
+                contract_addr: deps.api.human_address(&config.anchor_overseer_contract)?,
+                msg: to_binary(&moneymarket::overseer::QueryMsg::DistributionParams {})?,
+            },
+        ))?
+        .deposit_rate)
+}
+
+pub fn query_oracle_config<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+) -> StdResult<moneymarket::oracle::ConfigResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: deps.api.human_address(&config.anchor_oracle_contract)?,
+        msg: to_binary(&moneymarket::oracle::QueryMsg::Config {})?,
+    }))
+}
+
+pub fn query_custody_config<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    custody_contract: &HumanAddr,
+) -> StdResult<moneymarket::custody::ConfigResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: custody_contract.clone(),
+        msg: to_binary(&moneymarket::custody::QueryMsg::Config {})?,
+    }))
+}
+
+pub fn query_token_info<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    token: &HumanAddr,
+) -> StdResult<cw20::TokenInfoResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: token.clone(),
+        msg: to_binary(&cw20::Cw20QueryMsg::TokenInfo {})?,
+    }))
+}
+
+pub fn query_pool<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    pair: &HumanAddr,
+) -> StdResult<terraswap::pair::PoolResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: pair.clone(),
+        msg: to_binary(&terraswap::pair::QueryMsg::Pool {})?,
+    }))
+}
+
+pub fn query_router_config<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    router_contract: &HumanAddr,
+) -> StdResult<router_querier::ConfigResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: router_contract.clone(),
+        msg: to_binary(&router_querier::QueryMsg::Config {})?,
+    }))
+}
+
+/// uusd one aUST redeems for
+pub fn query_aust_exchange_rate<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+    block_height: Option<u64>,
+) -> StdResult<Decimal256> {
     Ok(deps
         .querier
-        .query::<StateResponse>(&QueryRequest::Wasm(WasmQuery::Smart {
-            contract_addr: basset_hub_contract.clone(),
-            msg: to_binary(&hub_querier::QueryMsg::State {})?,
+        .query::<moneymarket::market::EpochStateResponse>(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: deps.api.human_address(&config.anchor_market_contract)?,
+            msg: to_binary(&moneymarket::market::QueryMsg::EpochState { block_height })?,
         }))?
         .exchange_rate)
 }
 
+/// Borrow rate per block the interest model charges for the market state
+pub fn query_borrow_rate<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    interest_model: &HumanAddr,
+    market_balance: Uint256,
+    total_liabilities: Decimal256,
+    total_reserves: Decimal256,
+) -> StdResult<Decimal256> {
+    Ok(deps
+        .querier
+        .query::<moneymarket::interest_model::BorrowRateResponse>(&QueryRequest::Wasm(
+            WasmQuery::Smart {
+                contract_addr: interest_model.clone(),
+                msg: to_binary(&moneymarket::interest_model::QueryMsg::BorrowRate {
+                    market_balance,
+                    total_liabilities,
+                    total_reserves,
+                })?,
+            },
+        ))?
+        .rate)
+}
+
+/// uusd per ANC from the pool of the ANC/UST pair
+pub fn query_anc_price<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    config: &Config,
+) -> StdResult<Decimal256> {
+    let pool = query_pool(deps, &deps.api.human_address(&config.terraswap_anc_ust)?)?;
+    let (stable, anc) = if pool.assets[0].is_native_token() {
+        (pool.assets[0].amount, pool.assets[1].amount)
+    } else {
+        (pool.assets[1].amount, pool.assets[0].amount)
+    };
+    if anc.is_zero() {
+        return Err(StdError::generic_err("ANC/UST pool is empty"));
+    }
+    Ok(Decimal256::from_ratio(
+        Uint256::from(stable).0,
+        Uint256::from(anc).0,
+    ))
+}
+
+/// Deposit asset returned for selling `amount` of bAsset on the exit pair
+pub fn simulate_basset_sale<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    exit_pair: &HumanAddr,
+    basset_token: &HumanAddr,
+    amount: Uint128,
+) -> StdResult<Uint128> {
+    Ok(terraswap::querier::simulate(
+        deps,
+        exit_pair,
+        &terraswap::asset::Asset {
+            amount,
+            info: terraswap::asset::AssetInfo::Token {
+                contract_addr: basset_token.clone(),
+            },
+        },
+    )?
+    .return_amount)
+}
+
+/// Return of `amount` of `offer_asset` offered to the pair and the price it
+/// trades at, offer per ask before commission as the pair checks `max_spread`
+pub fn query_swap_price<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    pair: &HumanAddr,
+    offer_asset: &terraswap::asset::AssetInfo,
+    amount: Uint128,
+) -> StdResult<(Uint128, Decimal)> {
+    let simulation = terraswap::querier::simulate(
+        deps,
+        pair,
+        &terraswap::asset::Asset {
+            amount,
+            info: offer_asset.clone(),
+        },
+    )?;
+    let ask_amount = simulation.return_amount + simulation.commission_amount;
+    if ask_amount.is_zero() {
+        return Err(StdError::generic_err(format!(
+            "Pair {} returns nothing for {} {}",
+            pair, amount, offer_asset
+        )));
+    }
+    Ok((
+        simulation.return_amount,
+        Decimal::from_ratio(amount, ask_amount),
+    ))
+}
+
+/// Return of the operations for `amount` offered to the router
+pub fn simulate_swap_operations<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    router_contract: &HumanAddr,
+    amount: Uint128,
+    operations: &[SwapOperation],
+) -> StdResult<Uint128> {
+    Ok(deps
+        .querier
+        .query::<SimulateSwapOperationsResponse>(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: router_contract.clone(),
+            msg: to_binary(&router_querier::QueryMsg::SimulateSwapOperations {
+                offer_amount: amount,
+                operations: operations.to_vec(),
+            })?,
+        }))?
+        .amount)
+}
+
+pub fn query_whitelisted_validators<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    basset_hub_contract: &HumanAddr,
+) -> StdResult<Vec<HumanAddr>> {
+    Ok(hub_querier::query_whitelisted_validators(deps, basset_hub_contract)?.validators)
+}
+
 pub fn query_bonded_asset<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     basset_collateral_contract: &HumanAddr,
@@ -80,33 +418,42 @@ pub fn query_loan_amount<S: Storage, A: Api, Q: Querier>(
 
 pub fn query_collateral<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
-    config: &Config,
+    custody_contract: &HumanAddr,
     borrower: &HumanAddr,
 ) -> StdResult<moneymarket::custody::BorrowerResponse> {
-    Ok(deps
-        .querier
-        .query::<moneymarket::custody::BorrowerResponse>(&QueryRequest::Wasm(
-            WasmQuery::Smart {
-                contract_addr: deps.api.human_address(&config.basset_token_contract)?,
-                msg: to_binary(&moneymarket::custody::QueryMsg::Borrower {
-                    address: borrower.clone(),
-                })?,
-            },
-        ))?)
+    deps.querier
+        .query::<moneymarket::custody::BorrowerResponse>(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: custody_contract.clone(),
+            msg: to_binary(&moneymarket::custody::QueryMsg::Borrower {
+                address: borrower.clone(),
+            })?,
+        }))
 }
 
-pub fn bond_luna(preferred_validator: &HumanAddr) -> StdResult<Binary> {
+pub fn bond_luna(validator: &HumanAddr) -> StdResult<Binary> {
     to_binary(&hub_querier::HandleMsg::Bond {
-        validator: preferred_validator.clone(),
+        validator: validator.clone(),
+    })
+}
+
+pub fn convert_to_basset(
+    converter_contract: &HumanAddr,
+    amount: Uint128,
+    msg: &Binary,
+) -> StdResult<Binary> {
+    to_binary(&Cw20HandleMsg::Send {
+        contract: converter_contract.clone(),
+        amount,
+        msg: Some(msg.clone()),
     })
 }
 
 pub fn deposit_basset_collateral(
-    basset_token_contract: &HumanAddr,
+    custody_contract: &HumanAddr,
     amount: Uint128,
 ) -> StdResult<Binary> {
     to_binary(&Cw20HandleMsg::Send {
-        contract: basset_token_contract.clone(),
+        contract: custody_contract.clone(),
         amount,
         msg: Some(to_binary(
             &moneymarket::custody::Cw20HookMsg::DepositCollateral {},
@@ -123,6 +470,66 @@ pub fn overseer_lock_collateral(
     })
 }
 
+pub fn overseer_unlock_collateral(
+    basset_collateral_contract: &HumanAddr,
+    amount: Uint256,
+) -> StdResult<Binary> {
+    to_binary(&moneymarket::overseer::HandleMsg::UnlockCollateral {
+        collaterals: vec![(basset_collateral_contract.clone(), amount)],
+    })
+}
+
+pub fn withdraw_basset_collateral(amount: Uint256) -> StdResult<Binary> {
+    to_binary(&moneymarket::custody::HandleMsg::WithdrawCollateral {
+        amount: Some(amount),
+    })
+}
+
+pub fn unbond_basset(hub_contract: &HumanAddr, amount: Uint128) -> StdResult<Binary> {
+    to_binary(&Cw20HandleMsg::Send {
+        contract: hub_contract.clone(),
+        amount,
+        msg: Some(to_binary(&hub_querier::Cw20HookMsg::Unbond {})?),
+    })
+}
+
+pub fn check_slashing() -> StdResult<Binary> {
+    to_binary(&hub_querier::HandleMsg::CheckSlashing {})
+}
+
+pub fn withdraw_unbonded() -> StdResult<Binary> {
+    to_binary(&hub_querier::HandleMsg::WithdrawUnbonded {})
+}
+
+pub fn transfer_basset(recipient: &HumanAddr, amount: Uint128) -> StdResult<Binary> {
+    to_binary(&Cw20HandleMsg::Transfer {
+        recipient: recipient.clone(),
+        amount,
+    })
+}
+
+pub fn claim_anc_rewards() -> StdResult<Binary> {
+    to_binary(&moneymarket::market::HandleMsg::ClaimRewards { to: None })
+}
+
+pub fn anchor_repay() -> StdResult<Binary> {
+    to_binary(&moneymarket::market::HandleMsg::RepayStable {})
+}
+
+pub fn anchor_deposit_stable() -> StdResult<Binary> {
+    to_binary(&moneymarket::market::HandleMsg::DepositStable {})
+}
+
+pub fn redeem_aust(anchor_market_contract: &HumanAddr, amount: Uint128) -> StdResult<Binary> {
+    to_binary(&Cw20HandleMsg::Send {
+        contract: anchor_market_contract.clone(),
+        amount,
+        msg: Some(to_binary(
+            &moneymarket::market::Cw20HookMsg::RedeemStable {},
+        )?),
+    })
+}
+
 pub fn anchor_borrow(borrow_amount: Uint256) -> StdResult<Binary> {
     to_binary(&moneymarket::market::HandleMsg::BorrowStable {
         borrow_amount,
@@ -130,16 +537,72 @@ pub fn anchor_borrow(borrow_amount: Uint256) -> StdResult<Binary> {
     })
 }
 
-pub fn swap_to_collateral(amount: Uint128) -> StdResult<Binary> {
+pub fn swap_native(
+    denom: &str,
+    amount: Uint128,
+    belief_price: Decimal,
+    max_spread: Decimal,
+    to: Option<HumanAddr>,
+) -> StdResult<Binary> {
     to_binary(&terraswap::pair::HandleMsg::Swap {
         offer_asset: terraswap::asset::Asset {
             amount,
             info: terraswap::asset::AssetInfo::NativeToken {
-                denom: crate::contract::TERRASWAP_PAIR.to_string(),
+                denom: denom.to_string(),
             },
         },
-        belief_price: None,
-        max_spread: None,
-        to: None,
+        belief_price: Some(belief_price),
+        max_spread: Some(max_spread),
+        to,
+    })
+}
+
+pub fn sell_token(
+    pair: &HumanAddr,
+    amount: Uint128,
+    belief_price: Decimal,
+    max_spread: Decimal,
+    to: Option<HumanAddr>,
+) -> StdResult<Binary> {
+    to_binary(&Cw20HandleMsg::Send {
+        contract: pair.clone(),
+        amount,
+        msg: Some(to_binary(&terraswap::pair::Cw20HookMsg::Swap {
+            belief_price: Some(belief_price),
+            max_spread: Some(max_spread),
+            to,
+        })?),
+    })
+}
+
+pub fn execute_swap_operations(
+    operations: Vec<SwapOperation>,
+    minimum_receive: Uint128,
+    to: Option<HumanAddr>,
+) -> StdResult<Binary> {
+    to_binary(&router_querier::HandleMsg::ExecuteSwapOperations {
+        operations,
+        minimum_receive: Some(minimum_receive),
+        to,
+    })
+}
+
+pub fn sell_token_on_router(
+    router_contract: &HumanAddr,
+    amount: Uint128,
+    operations: Vec<SwapOperation>,
+    minimum_receive: Uint128,
+    to: Option<HumanAddr>,
+) -> StdResult<Binary> {
+    to_binary(&Cw20HandleMsg::Send {
+        contract: router_contract.clone(),
+        amount,
+        msg: Some(to_binary(
+            &router_querier::Cw20HookMsg::ExecuteSwapOperations {
+                operations,
+                minimum_receive: Some(minimum_receive),
+                to,
+            },
+        )?),
     })
 }
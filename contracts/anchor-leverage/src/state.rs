@@ -1,32 +1,296 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{CanonicalAddr, HumanAddr, StdResult, Storage};
-use cosmwasm_storage::{ReadonlySingleton, Singleton};
+use cosmwasm_bignumber::{Decimal256, Uint256};
+use cosmwasm_std::{
+    Binary, CanonicalAddr, HumanAddr, Order, ReadonlyStorage, StdError, StdResult, Storage,
+};
+use cosmwasm_storage::{Bucket, ReadonlyBucket, ReadonlySingleton, Singleton};
+use router_querier::SwapOperation;
+use terraswap::asset::{AssetInfo, AssetInfoRaw};
 
 pub static CONFIG_KEY: &[u8] = b"config";
+pub static STATE_KEY: &[u8] = b"state";
+pub static PREFIX_COLLATERAL: &[u8] = b"collateral";
+pub static PREFIX_COLLATERAL_STATE: &[u8] = b"collateral_state";
+pub static PREFIX_VALIDATORS: &[u8] = b"validators";
+pub static PREFIX_POSITION: &[u8] = b"position";
+pub static PREFIX_RISK_INDEX: &[u8] = b"risk_index";
+pub static PREFIX_REALIZED_PNL: &[u8] = b"realized_pnl";
+pub static PREFIX_LIQUIDATION: &[u8] = b"liquidation";
+pub static PREFIX_SLASHING: &[u8] = b"slashing";
+pub static PREFIX_UNBOND_REQUESTS: &[u8] = b"unbond_requests";
+pub static PREFIX_HOOKS: &[u8] = b"hooks";
+pub static PREFIX_DEPOSIT_CALLBACK: &[u8] = b"deposit_callback";
+pub static PREFIX_OPERATORS: &[u8] = b"operators";
+
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
 
 /// Config struct
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
-    pub basset_hub_contract: CanonicalAddr,
-    pub basset_token_contract: CanonicalAddr,
-    pub basset_collateral_contract: CanonicalAddr,
+    pub owner: CanonicalAddr,
+    /// This contract's own address, the borrower at Anchor
+    pub contract_addr: CanonicalAddr,
     pub anchor_overseer_contract: CanonicalAddr,
     pub anchor_market_contract: CanonicalAddr,
-    pub terraswap_luna_ust: CanonicalAddr,
-    pub preferred_validator: HumanAddr,
+    pub anchor_oracle_contract: CanonicalAddr,
+    pub anc_token: CanonicalAddr,
+    pub terraswap_anc_ust: CanonicalAddr,
+    /// Only address that may change the fee settings, the owner cannot
+    pub fee_admin: CanonicalAddr,
+    pub fee_collector: CanonicalAddr,
+    /// Share of harvested ANC rewards kept as fee
+    pub performance_fee: Decimal256,
+    /// Share of collateral kept as fee when a position exits
+    pub management_fee: Decimal256,
+    /// Caps and minimum of deposits, valued in uusd at the oracle price
+    pub max_total_deposit: Uint256,
+    pub max_user_deposit: Uint256,
+    pub min_deposit: Uint256,
+    /// Share a swap may return below its simulation, uusd counted after tax
+    pub max_slippage: Decimal256,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ConfigMsg {
-    pub basset_hub_contract: HumanAddr, //terra1fflas6wv4snv8lsda9knvq2w0cyt493r8puh2e
-    pub basset_token_contract: HumanAddr, //terra1ltnkx0mv7lf2rca9f8w740ashu93ujughy4s7p
-    pub basset_collateral_contract: HumanAddr, //terra1u0t35drzyy0mujj8rkdyzhe264uls4ug3wdp3x
     pub anchor_overseer_contract: HumanAddr, //terra1qljxd0y3j3gk97025qvl3lgq8ygup4gsksvaxv
-    pub anchor_market_contract: HumanAddr, //terra15dwd5mj8v59wpj0wvt233mf5efdff808c5tkal
-    pub terraswap_luna_ust: HumanAddr, //terra156v8s539wtz0sjpn8y8a8lfg8fhmwa7fy22aff
-    pub preferred_validator: HumanAddr, //terravaloper1krj7amhhagjnyg2tkkuh6l0550y733jnjnnlzy
+    pub anchor_market_contract: HumanAddr,   //terra15dwd5mj8v59wpj0wvt233mf5efdff808c5tkal
+    pub anchor_oracle_contract: HumanAddr,   //terra1p4gg3p2ue6qy2qfuxtrmgv2ec3f4jmgqtazum8
+    pub anc_token: HumanAddr,
+    pub terraswap_anc_ust: HumanAddr,
+    pub fee_collector: HumanAddr,
+    pub performance_fee: Decimal256,
+    pub management_fee: Decimal256,
+    pub max_total_deposit: Uint256,
+    pub max_user_deposit: Uint256,
+    pub min_deposit: Uint256,
+    pub max_slippage: Decimal256,
+    pub collaterals: Vec<CollateralMsg>,
+}
+
+/// How a deposit asset is turned into the bAsset Anchor accepts as collateral
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MintRoute {
+    /// Bond a native deposit at a bAsset hub, e.g. uluna for bLuna
+    Hub { hub_contract: CanonicalAddr },
+    /// Send a cw20 deposit to a converter with `msg` as hook, e.g. wrapped ETH for bETH
+    Converter {
+        contract_addr: CanonicalAddr,
+        msg: Binary,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MintRouteMsg {
+    Hub {
+        hub_contract: HumanAddr,    //terra1fflas6wv4snv8lsda9knvq2w0cyt493r8puh2e
+        validators: Vec<Validator>, //terravaloper1krj7amhhagjnyg2tkkuh6l0550y733jnjnnlzy
+    },
+    Converter {
+        contract_addr: HumanAddr,
+        msg: Binary,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapRoute {
+    /// Swap on the pair of the deposit asset and uusd
+    Pair { pair_contract: CanonicalAddr },
+    /// Swap through the Terraswap router along the path that simulates the best
+    /// return, paths lead from uusd to the deposit asset and are reversed to sell
+    Router {
+        router_contract: CanonicalAddr,
+        paths: Vec<Vec<SwapOperation>>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapRouteMsg {
+    Pair {
+        pair_contract: HumanAddr, //terra156v8s539wtz0sjpn8y8a8lfg8fhmwa7fy22aff
+    },
+    Router {
+        router_contract: HumanAddr,
+        paths: Vec<Vec<SwapOperation>>,
+    },
+}
+
+/// Registered collateral the leverage loop can run on
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Collateral {
+    /// Asset depositors pay in and are paid out in
+    pub deposit_asset: AssetInfoRaw,
+    pub mint_route: MintRoute,
+    /// bAsset cw20 token locked at Anchor
+    pub token: CanonicalAddr,
+    pub custody_contract: CanonicalAddr,
+    /// Where the deposit asset is traded against uusd
+    pub swap_route: SwapRoute,
+    /// Pair trading the bAsset against the deposit asset
+    pub exit_pair: CanonicalAddr,
+    /// Yearly staking yield of the bAsset, only used to estimate the APY
+    pub staking_apr: Decimal256,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CollateralMsg {
+    /// Registry key used by deposits and queries, e.g. "bluna"
+    pub name: String,
+    pub deposit_asset: AssetInfo,
+    pub mint_route: MintRouteMsg,
+    pub token: HumanAddr, //terra1u0t35drzyy0mujj8rkdyzhe264uls4ug3wdp3x
+    pub custody_contract: HumanAddr, //terra1ltnkx0mv7lf2rca9f8w740ashu93ujughy4s7p
+    pub swap_route: SwapRouteMsg,
+    pub exit_pair: HumanAddr,
+    pub staking_apr: Decimal256,
+}
+
+/// Validator the contract bonds to, with its relative share of every bond
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Validator {
+    pub address: HumanAddr,
+    pub weight: u64,
+}
+
+/// Pooled loan at Anchor, split between depositors of all collaterals by shares
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct State {
+    pub total_debt_shares: Uint256,
+    /// aUST held on behalf of all positions
+    pub total_aust: Uint256,
+    /// uusd cost basis of all open positions
+    pub total_deposit: Uint256,
+    /// Number of liquidations recorded so far
+    pub liquidations: u64,
+    /// Number of slashings recorded so far
+    pub slashings: u64,
+    /// Number of liquidations the hooks have been notified of
+    pub notified_liquidations: u64,
+    /// Collaterals of the liquidations being notified, kept until every
+    /// owner with hooks has been paged through
+    pub notifying: Vec<String>,
+    /// Last owner with hooks notified of `notifying`
+    pub notified_hooks_owner: Option<CanonicalAddr>,
+    /// Set while a deposit or withdraw loop runs, its steps are the only
+    /// messages the contract takes until the last one clears it
+    pub loop_in_progress: bool,
+    /// Stable fees held by the contract until claimed
+    pub fee_stable: Uint256,
+    /// Times the loan was found repaid with debt shares outstanding, which
+    /// voids every debt share minted before
+    pub debt_wipes: u64,
+}
+
+/// Pooled bAsset of one collateral, split between its depositors by shares
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct CollateralState {
+    /// bAsset locked as collateral on behalf of all depositors
+    pub total_collateral: Uint256,
+    pub total_collateral_shares: Uint256,
+    /// bAsset fees held by the contract until claimed
+    pub fee_basset: Uint256,
+    /// Deposit asset withdrawn from the hub but not yet claimed by its owners
+    pub unbonded: Uint256,
+    /// Hub exchange rate seen at the last deposit, zero before the first one
+    pub exchange_rate: Decimal256,
+    /// Times custody was found empty with collateral shares outstanding, which
+    /// voids every collateral share minted before
+    pub wipes: u64,
+}
+
+/// Depositor's claim on the pooled collateral and loan
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct Position {
+    pub collateral_shares: Uint256,
+    pub debt_shares: Uint256,
+    /// Share of every loan parked in Anchor Earn instead of levered
+    pub earn_share: Decimal256,
+    /// aUST minted for the parked loans
+    pub aust: Uint256,
+    /// Deposit asset paid into the position
+    pub cost_basis: Uint256,
+    /// Deposits valued at the oracle price when they were made
+    pub cost_basis_uusd: Uint256,
+    /// Hub exchange rate the collateral was minted at, averaged over the
+    /// deposits, zero before the first one
+    pub exchange_rate: Decimal256,
+    /// Collateral wipes the collateral shares were minted after
+    pub collateral_wipes: u64,
+    /// Debt wipes the debt shares were minted after
+    pub debt_wipes: u64,
+}
+
+/// Cost basis and proceeds of an owner's exited positions
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct RealizedPnl {
+    pub cost: Uint256,
+    pub cost_uusd: Uint256,
+    pub proceeds: Uint256,
+    pub proceeds_uusd: Uint256,
+}
+
+/// Callback of a deposit whose loop is running, with what the loop did so far
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DepositCallback {
+    pub contract: CanonicalAddr,
+    pub msg: Binary,
+    pub bonded: Uint256,
+    pub borrowed: Uint256,
+}
+
+/// What an operator may do with an owner's positions, the proceeds of
+/// whatever it does are always paid to the owner
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OperatorScope {
+    /// SetEarnShare
+    Rebalance,
+    /// Withdraw and ClaimUnbonded
+    Deleverage,
+    /// AddHook and RemoveHook
+    SetTriggers,
+}
+
+/// Scopes an owner granted an operator, until block time `expires` if set
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Operator {
+    pub scopes: Vec<OperatorScope>,
+    pub expires: Option<u64>,
+}
+
+/// bAsset an owner unbonded at the hub in one of its batches
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UnbondRequest {
+    pub batch_id: u64,
+    pub amount: Uint256,
+}
+
+/// Part of the pooled collateral Anchor seized, the loss is shared by all
+/// collateral shares and the repaid loan by all debt shares
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Liquidation {
+    pub collateral: String,
+    pub block_height: u64,
+    /// bAsset custody no longer holds
+    pub seized: Uint256,
+    /// Pooled loan left after the liquidation
+    pub loan: Uint256,
+}
+
+/// Drop of a hub exchange rate between two deposits, the bAsset of every
+/// depositor of the collateral is worth that much less of the deposit asset
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Slashing {
+    pub collateral: String,
+    pub block_height: u64,
+    pub previous_rate: Decimal256,
+    pub exchange_rate: Decimal256,
 }
 
 /// Get config
@@ -38,3 +302,460 @@ pub fn get_config<S: Storage>(storage: &S) -> StdResult<Config> {
 pub fn set_config<S: Storage>(storage: &mut S, config: &Config) -> StdResult<()> {
     Singleton::new(storage, CONFIG_KEY).save(config)
 }
+
+/// Get state
+pub fn get_state<S: Storage>(storage: &S) -> StdResult<State> {
+    ReadonlySingleton::new(storage, STATE_KEY).load()
+}
+
+/// Set state
+pub fn set_state<S: Storage>(storage: &mut S, state: &State) -> StdResult<()> {
+    Singleton::new(storage, STATE_KEY).save(state)
+}
+
+/// Get registered collateral
+pub fn get_collateral<S: ReadonlyStorage>(storage: &S, name: &str) -> StdResult<Collateral> {
+    ReadonlyBucket::new(PREFIX_COLLATERAL, storage)
+        .may_load(name.as_bytes())?
+        .ok_or_else(|| StdError::generic_err(format!("Collateral '{}' is not registered", name)))
+}
+
+/// Get registered collateral, None if there is no such collateral
+pub fn may_get_collateral<S: ReadonlyStorage>(
+    storage: &S,
+    name: &str,
+) -> StdResult<Option<Collateral>> {
+    ReadonlyBucket::new(PREFIX_COLLATERAL, storage).may_load(name.as_bytes())
+}
+
+/// Set registered collateral
+pub fn set_collateral<S: Storage>(
+    storage: &mut S,
+    name: &str,
+    collateral: &Collateral,
+) -> StdResult<()> {
+    Bucket::new(PREFIX_COLLATERAL, storage).save(name.as_bytes(), collateral)
+}
+
+/// Get all registered collaterals ordered by name
+pub fn get_collaterals<S: ReadonlyStorage>(storage: &S) -> StdResult<Vec<(String, Collateral)>> {
+    ReadonlyBucket::new(PREFIX_COLLATERAL, storage)
+        .range(None, None, Order::Ascending)
+        .map(|item| {
+            let (key, collateral) = item?;
+            Ok((
+                String::from_utf8(key).map_err(StdError::invalid_utf8)?,
+                collateral,
+            ))
+        })
+        .collect()
+}
+
+/// Get pooled state of a collateral
+pub fn get_collateral_state<S: ReadonlyStorage>(
+    storage: &S,
+    name: &str,
+) -> StdResult<CollateralState> {
+    Ok(ReadonlyBucket::new(PREFIX_COLLATERAL_STATE, storage)
+        .may_load(name.as_bytes())?
+        .unwrap_or_default())
+}
+
+/// Set pooled state of a collateral
+pub fn set_collateral_state<S: Storage>(
+    storage: &mut S,
+    name: &str,
+    collateral_state: &CollateralState,
+) -> StdResult<()> {
+    Bucket::new(PREFIX_COLLATERAL_STATE, storage).save(name.as_bytes(), collateral_state)
+}
+
+/// Get weighted validator set of a hub collateral
+pub fn get_validators<S: ReadonlyStorage>(storage: &S, name: &str) -> StdResult<Vec<Validator>> {
+    Ok(ReadonlyBucket::new(PREFIX_VALIDATORS, storage)
+        .may_load(name.as_bytes())?
+        .unwrap_or_default())
+}
+
+/// Set weighted validator set of a hub collateral
+pub fn set_validators<S: Storage>(
+    storage: &mut S,
+    name: &str,
+    validators: &[Validator],
+) -> StdResult<()> {
+    Bucket::new(PREFIX_VALIDATORS, storage).save(name.as_bytes(), &validators.to_vec())
+}
+
+/// Get position of an owner in a collateral, empty if there is none
+pub fn get_position<S: ReadonlyStorage>(
+    storage: &S,
+    collateral: &str,
+    owner: &CanonicalAddr,
+) -> StdResult<Position> {
+    let position = ReadonlyBucket::multilevel(&[PREFIX_POSITION, collateral.as_bytes()], storage)
+        .may_load(owner.as_slice())?
+        .unwrap_or_default();
+    settle_wipes(storage, collateral, position)
+}
+
+/// Zeroes the shares of a position minted before their pool was last wiped
+fn settle_wipes<S: ReadonlyStorage>(
+    storage: &S,
+    collateral: &str,
+    mut position: Position,
+) -> StdResult<Position> {
+    let collateral_wipes = get_collateral_state(storage, collateral)?.wipes;
+    if position.collateral_wipes != collateral_wipes {
+        position.collateral_shares = Uint256::zero();
+        position.collateral_wipes = collateral_wipes;
+    }
+    let debt_wipes = ReadonlySingleton::<S, State>::new(storage, STATE_KEY)
+        .may_load()?
+        .unwrap_or_default()
+        .debt_wipes;
+    if position.debt_wipes != debt_wipes {
+        position.debt_shares = Uint256::zero();
+        position.debt_wipes = debt_wipes;
+    }
+    Ok(position)
+}
+
+/// Set position of an owner in a collateral and move it in the risk index
+pub fn set_position<S: Storage>(
+    storage: &mut S,
+    collateral: &str,
+    owner: &CanonicalAddr,
+    position: &Position,
+) -> StdResult<()> {
+    remove_position(storage, collateral, owner)?;
+    Bucket::multilevel(&[PREFIX_RISK_INDEX, collateral.as_bytes()], storage)
+        .save(&risk_key(owner, position), owner)?;
+    Bucket::multilevel(&[PREFIX_POSITION, collateral.as_bytes()], storage)
+        .save(owner.as_slice(), position)
+}
+
+/// Remove position of an owner in a collateral along with its risk index entry
+pub fn remove_position<S: Storage>(
+    storage: &mut S,
+    collateral: &str,
+    owner: &CanonicalAddr,
+) -> StdResult<()> {
+    let mut positions =
+        Bucket::<S, Position>::multilevel(&[PREFIX_POSITION, collateral.as_bytes()], storage);
+    if let Some(position) = positions.may_load(owner.as_slice())? {
+        positions.remove(owner.as_slice());
+        Bucket::<S, CanonicalAddr>::multilevel(
+            &[PREFIX_RISK_INDEX, collateral.as_bytes()],
+            storage,
+        )
+        .remove(&risk_key(owner, &position));
+    }
+    Ok(())
+}
+
+/// Risk index key of a position, its debt to collateral share ratio and the owner.
+/// Debt shares are pooled and the price is the same for all positions in a
+/// collateral, so the ratio orders them by LTV without either
+fn risk_key(owner: &CanonicalAddr, position: &Position) -> Vec<u8> {
+    let mut key = vec![0xffu8; 32];
+    if !position.collateral_shares.is_zero() {
+        Decimal256::from_ratio(position.debt_shares.0, position.collateral_shares.0)
+            .0
+            .to_big_endian(&mut key);
+    }
+    key.extend_from_slice(owner.as_slice());
+    key
+}
+
+/// Get positions in a collateral ordered by owner
+pub fn read_positions<S: ReadonlyStorage>(
+    storage: &S,
+    collateral: &str,
+    start_after: Option<&CanonicalAddr>,
+    limit: Option<u32>,
+) -> StdResult<Vec<(CanonicalAddr, Position)>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|owner| {
+        let mut start = owner.as_slice().to_vec();
+        start.push(0);
+        start
+    });
+
+    ReadonlyBucket::multilevel(&[PREFIX_POSITION, collateral.as_bytes()], storage)
+        .range(start.as_deref(), None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (owner, position) = item?;
+            Ok((
+                CanonicalAddr::from(owner),
+                settle_wipes(storage, collateral, position)?,
+            ))
+        })
+        .collect()
+}
+
+/// Get positions in a collateral, the highest LTV first
+pub fn read_positions_by_risk<S: ReadonlyStorage>(
+    storage: &S,
+    collateral: &str,
+    start_after: Option<&CanonicalAddr>,
+    limit: Option<u32>,
+) -> StdResult<Vec<(CanonicalAddr, Position)>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let end = match start_after {
+        Some(owner) => {
+            let position = ReadonlyBucket::<S, Position>::multilevel(
+                &[PREFIX_POSITION, collateral.as_bytes()],
+                storage,
+            )
+            .may_load(owner.as_slice())?
+            .ok_or_else(|| StdError::generic_err("start_after has no position"))?;
+            Some(risk_key(owner, &position))
+        }
+        None => None,
+    };
+
+    ReadonlyBucket::<S, CanonicalAddr>::multilevel(
+        &[PREFIX_RISK_INDEX, collateral.as_bytes()],
+        storage,
+    )
+    .range(None, end.as_deref(), Order::Descending)
+    .take(limit)
+    .map(|item| {
+        let (_, owner) = item?;
+        let position = get_position(storage, collateral, &owner)?;
+        Ok((owner, position))
+    })
+    .collect()
+}
+
+/// Get realized pnl of an owner in a collateral, empty if nothing was exited yet
+pub fn get_realized_pnl<S: ReadonlyStorage>(
+    storage: &S,
+    collateral: &str,
+    owner: &CanonicalAddr,
+) -> StdResult<RealizedPnl> {
+    Ok(
+        ReadonlyBucket::multilevel(&[PREFIX_REALIZED_PNL, collateral.as_bytes()], storage)
+            .may_load(owner.as_slice())?
+            .unwrap_or_default(),
+    )
+}
+
+/// Set realized pnl of an owner in a collateral
+pub fn set_realized_pnl<S: Storage>(
+    storage: &mut S,
+    collateral: &str,
+    owner: &CanonicalAddr,
+    realized_pnl: &RealizedPnl,
+) -> StdResult<()> {
+    Bucket::multilevel(&[PREFIX_REALIZED_PNL, collateral.as_bytes()], storage)
+        .save(owner.as_slice(), realized_pnl)
+}
+
+/// Get the unbond requests of an owner in a collateral not claimed yet
+pub fn get_unbond_requests<S: ReadonlyStorage>(
+    storage: &S,
+    collateral: &str,
+    owner: &CanonicalAddr,
+) -> StdResult<Vec<UnbondRequest>> {
+    Ok(
+        ReadonlyBucket::multilevel(&[PREFIX_UNBOND_REQUESTS, collateral.as_bytes()], storage)
+            .may_load(owner.as_slice())?
+            .unwrap_or_default(),
+    )
+}
+
+/// Set the unbond requests of an owner in a collateral
+pub fn set_unbond_requests<S: Storage>(
+    storage: &mut S,
+    collateral: &str,
+    owner: &CanonicalAddr,
+    requests: &[UnbondRequest],
+) -> StdResult<()> {
+    Bucket::multilevel(&[PREFIX_UNBOND_REQUESTS, collateral.as_bytes()], storage)
+        .save(owner.as_slice(), &requests.to_vec())
+}
+
+/// Get the callback of an owner's running deposit in a collateral, if it has one
+pub fn may_get_deposit_callback<S: ReadonlyStorage>(
+    storage: &S,
+    collateral: &str,
+    owner: &CanonicalAddr,
+) -> StdResult<Option<DepositCallback>> {
+    ReadonlyBucket::multilevel(&[PREFIX_DEPOSIT_CALLBACK, collateral.as_bytes()], storage)
+        .may_load(owner.as_slice())
+}
+
+/// Set the callback of an owner's running deposit in a collateral
+pub fn set_deposit_callback<S: Storage>(
+    storage: &mut S,
+    collateral: &str,
+    owner: &CanonicalAddr,
+    callback: &DepositCallback,
+) -> StdResult<()> {
+    Bucket::multilevel(&[PREFIX_DEPOSIT_CALLBACK, collateral.as_bytes()], storage)
+        .save(owner.as_slice(), callback)
+}
+
+/// Remove the callback of an owner's deposit once it was sent
+pub fn remove_deposit_callback<S: Storage>(
+    storage: &mut S,
+    collateral: &str,
+    owner: &CanonicalAddr,
+) {
+    Bucket::<S, DepositCallback>::multilevel(
+        &[PREFIX_DEPOSIT_CALLBACK, collateral.as_bytes()],
+        storage,
+    )
+    .remove(owner.as_slice())
+}
+
+/// Get what an owner granted an operator, if anything
+pub fn may_get_operator<S: ReadonlyStorage>(
+    storage: &S,
+    owner: &CanonicalAddr,
+    operator: &CanonicalAddr,
+) -> StdResult<Option<Operator>> {
+    ReadonlyBucket::multilevel(&[PREFIX_OPERATORS, owner.as_slice()], storage)
+        .may_load(operator.as_slice())
+}
+
+/// Set what an owner grants an operator
+pub fn set_operator<S: Storage>(
+    storage: &mut S,
+    owner: &CanonicalAddr,
+    operator: &CanonicalAddr,
+    grant: &Operator,
+) -> StdResult<()> {
+    Bucket::multilevel(&[PREFIX_OPERATORS, owner.as_slice()], storage)
+        .save(operator.as_slice(), grant)
+}
+
+/// Revoke an operator of an owner
+pub fn remove_operator<S: Storage>(
+    storage: &mut S,
+    owner: &CanonicalAddr,
+    operator: &CanonicalAddr,
+) {
+    Bucket::<S, Operator>::multilevel(&[PREFIX_OPERATORS, owner.as_slice()], storage)
+        .remove(operator.as_slice())
+}
+
+/// Get every operator of an owner
+pub fn get_operators<S: ReadonlyStorage>(
+    storage: &S,
+    owner: &CanonicalAddr,
+) -> StdResult<Vec<(CanonicalAddr, Operator)>> {
+    ReadonlyBucket::multilevel(&[PREFIX_OPERATORS, owner.as_slice()], storage)
+        .range(None, None, Order::Ascending)
+        .map(|item| {
+            let (operator, grant) = item?;
+            Ok((CanonicalAddr::from(operator), grant))
+        })
+        .collect()
+}
+
+/// Get the hook contracts of an owner
+pub fn get_hooks<S: ReadonlyStorage>(
+    storage: &S,
+    owner: &CanonicalAddr,
+) -> StdResult<Vec<CanonicalAddr>> {
+    Ok(ReadonlyBucket::new(PREFIX_HOOKS, storage)
+        .may_load(owner.as_slice())?
+        .unwrap_or_default())
+}
+
+/// Set the hook contracts of an owner, an empty list removes the entry
+pub fn set_hooks<S: Storage>(
+    storage: &mut S,
+    owner: &CanonicalAddr,
+    hooks: &[CanonicalAddr],
+) -> StdResult<()> {
+    let mut bucket = Bucket::new(PREFIX_HOOKS, storage);
+    if hooks.is_empty() {
+        bucket.remove(owner.as_slice());
+        Ok(())
+    } else {
+        bucket.save(owner.as_slice(), &hooks.to_vec())
+    }
+}
+
+/// Read owners with hooks along with them, ordered by owner
+pub fn read_hooks<S: ReadonlyStorage>(
+    storage: &S,
+    start_after: Option<&CanonicalAddr>,
+    limit: Option<u32>,
+) -> StdResult<Vec<(CanonicalAddr, Vec<CanonicalAddr>)>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|owner| {
+        let mut start = owner.as_slice().to_vec();
+        start.push(0);
+        start
+    });
+
+    ReadonlyBucket::new(PREFIX_HOOKS, storage)
+        .range(start.as_deref(), None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (owner, hooks) = item?;
+            Ok((CanonicalAddr::from(owner), hooks))
+        })
+        .collect()
+}
+
+/// Store a liquidation under the next id
+pub fn push_liquidation<S: Storage>(
+    storage: &mut S,
+    id: u64,
+    liquidation: &Liquidation,
+) -> StdResult<()> {
+    Bucket::new(PREFIX_LIQUIDATION, storage).save(&id.to_be_bytes(), liquidation)
+}
+
+/// Get liquidations ordered by id
+pub fn read_liquidations<S: ReadonlyStorage>(
+    storage: &S,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<(u64, Liquidation)>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| (id + 1).to_be_bytes().to_vec());
+
+    ReadonlyBucket::new(PREFIX_LIQUIDATION, storage)
+        .range(start.as_deref(), None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (k, v) = item?;
+            let mut id = [0u8; 8];
+            id.copy_from_slice(&k);
+            Ok((u64::from_be_bytes(id), v))
+        })
+        .collect()
+}
+
+/// Store a slashing under the next id
+pub fn push_slashing<S: Storage>(storage: &mut S, id: u64, slashing: &Slashing) -> StdResult<()> {
+    Bucket::new(PREFIX_SLASHING, storage).save(&id.to_be_bytes(), slashing)
+}
+
+/// Get slashings ordered by id
+pub fn read_slashings<S: ReadonlyStorage>(
+    storage: &S,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<Vec<(u64, Slashing)>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| (id + 1).to_be_bytes().to_vec());
+
+    ReadonlyBucket::new(PREFIX_SLASHING, storage)
+        .range(start.as_deref(), None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (k, v) = item?;
+            let mut id = [0u8; 8];
+            id.copy_from_slice(&k);
+            Ok((u64::from_be_bytes(id), v))
+        })
+        .collect()
+}
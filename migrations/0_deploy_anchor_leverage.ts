@@ -60,13 +60,39 @@ async function deploy() {
     +codeId,
     {
       config: {
-        basset_hub_contract: "terra1fflas6wv4snv8lsda9knvq2w0cyt493r8puh2e",
-        basset_token_contract: "terra1ltnkx0mv7lf2rca9f8w740ashu93ujughy4s7p",
-        basset_collateral_contract: "terra1u0t35drzyy0mujj8rkdyzhe264uls4ug3wdp3x",
         anchor_overseer_contract: "terra1qljxd0y3j3gk97025qvl3lgq8ygup4gsksvaxv",
         anchor_market_contract: "terra15dwd5mj8v59wpj0wvt233mf5efdff808c5tkal",
-        terraswap_luna_ust: "terra156v8s539wtz0sjpn8y8a8lfg8fhmwa7fy22aff",
-        preferred_validator: "terravaloper1krj7amhhagjnyg2tkkuh6l0550y733jnjnnlzy",
+        anchor_oracle_contract: "terra1p4gg3p2ue6qy2qfuxtrmgv2ec3f4jmgqtazum8",
+        anc_token: process.env.ANC_TOKEN,
+        terraswap_anc_ust: process.env.TERRASWAP_ANC_UST,
+        fee_collector: test1.key.accAddress,
+        performance_fee: "0.1",
+        management_fee: "0.005",
+        max_total_deposit: "10000000000000",
+        max_user_deposit: "100000000000",
+        min_deposit: "100000000",
+        max_slippage: "0.01",
+        collaterals: [
+          {
+            name: "bluna",
+            deposit_asset: { native_token: { denom: "uluna" } },
+            mint_route: {
+              hub: {
+                hub_contract: "terra1fflas6wv4snv8lsda9knvq2w0cyt493r8puh2e",
+                validators: [
+                  { address: "terravaloper1krj7amhhagjnyg2tkkuh6l0550y733jnjnnlzy", weight: 1 },
+                ],
+              }
+            },
+            token: "terra1u0t35drzyy0mujj8rkdyzhe264uls4ug3wdp3x",
+            custody_contract: "terra1ltnkx0mv7lf2rca9f8w740ashu93ujughy4s7p",
+            swap_route: {
+              pair: { pair_contract: "terra156v8s539wtz0sjpn8y8a8lfg8fhmwa7fy22aff" }
+            },
+            exit_pair: process.env.TERRASWAP_BLUNA_LUNA,
+            staking_apr: "0.07",
+          },
+        ],
       }
     },
     new Coins({}),